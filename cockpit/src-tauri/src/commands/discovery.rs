@@ -1,15 +1,21 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use log::{error, info};
 use tauri::{AppHandle, State};
 use tauri_specta::Event;
 use telemetry::ConnectionStatus;
 use tokio::sync::Mutex;
 
+use crate::commands::joystick::{spawn_joystick_service, stop_joystick_service};
+use crate::commands::link::{open_link, stop_link_service};
 use crate::discovery::DiscoveryService;
-use crate::types::{CarUpdatedEvent, DiscoveryError, F1Car};
+use crate::joystick::JOYSTICK_WS_PORT;
+use crate::link::{self, HandshakeError};
+use crate::pairing::PairingStore;
+use crate::types::{CarUpdatedEvent, CarsMap, DiscoveryError, F1Car};
 
 #[tauri::command]
 #[specta::specta]
@@ -77,38 +83,89 @@ pub async fn get_car_by_id(
 #[specta::specta]
 pub async fn connect_to_car(
     car_id: String,
+    pin: Option<String>,
     handle: AppHandle,
     discovery_service: State<'_, Arc<Mutex<DiscoveryService>>>,
 ) -> Result<(), DiscoveryError> {
-    let service = discovery_service.lock().await;
-    if let Some(mut car) = service.get_car_by_id(&car_id) {
-        // set status to Connecting and emit update
-        car.connection_status = ConnectionStatus::Connecting;
-        CarUpdatedEvent { car: car.clone() }
-            .emit(&handle)
-            .context("Failed to emit car-updated event")?;
+    let (mut car, cars_map) = {
+        let service = discovery_service.lock().await;
+        match service.get_car_by_id(&car_id) {
+            Some(car) => (car, service.get_cars()),
+            None => return Err(DiscoveryError::car_not_found(&car_id)),
+        }
+    };
 
-        let cars_map = service.get_cars();
-        let handle_clone = handle.clone();
-        tokio::spawn(async move {
-            // simulate network connect attempt
-            tokio::time::sleep(Duration::from_millis(500)).await;
-            // update cached car
-            {
-                let mut guard = match cars_map.lock() {
-                    Ok(g) => g,
-                    Err(_) => return,
-                };
-                if let Some(c) = guard.get_mut(&car_id) {
-                    c.connection_status = ConnectionStatus::Connected;
-                    c.last_seen = Some(SystemTime::now());
-                    let _ = CarUpdatedEvent { car: c.clone() }.emit(&handle_clone);
-                }
-            }
-        });
+    // set status to Connecting and emit update
+    car.connection_status = ConnectionStatus::Connecting;
+    update_cached_car(&cars_map, &car);
+    CarUpdatedEvent { car: car.clone() }
+        .emit(&handle)
+        .context("Failed to emit car-updated event")?;
+
+    let failure = match handshake_and_start(&handle, &car, pin.as_deref()).await {
+        Ok(()) => {
+            info!(
+                "Connected to car #{} at {}:{}",
+                car.number, car.ip, car.port
+            );
+            car.connection_status = ConnectionStatus::Connected;
+            car.last_seen = Some(SystemTime::now());
+            None
+        }
+        Err(e) => {
+            error!("Failed to connect to car {car_id}: {e}");
+            let _ = stop_link_service().await;
+            car.connection_status = ConnectionStatus::Failed(e.to_string());
+            Some(match e {
+                HandshakeError::PairingRequired => DiscoveryError::pairing_required(),
+                HandshakeError::PairingFailed(reason) => DiscoveryError::pairing_failed(&reason),
+                HandshakeError::Link(e) => DiscoveryError::connection_failed(&e.to_string()),
+            })
+        }
+    };
+
+    update_cached_car(&cars_map, &car);
+    CarUpdatedEvent { car: car.clone() }
+        .emit(&handle)
+        .context("Failed to emit car-updated event")?;
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
+}
 
-    Ok(())
+async fn handshake_and_start(
+    handle: &AppHandle,
+    car: &F1Car,
+    pin: Option<&str>,
+) -> Result<(), HandshakeError> {
+    let car_addr: SocketAddr = format!("{}:{}", car.ip, car.port)
+        .parse()
+        .map_err(|e| anyhow!("Invalid car address {}:{}: {e}", car.ip, car.port))?;
+
+    let mut store = PairingStore::load(handle)?;
+    let (socket, mut replies) = open_link(handle.clone(), car_addr)
+        .await
+        .map_err(|e| anyhow!(e))?;
+    let config = link::handshake(&socket, car_addr, &mut replies, &mut store, &car.id, pin).await?;
+    info!(
+        "Handshake with {car_addr} complete: #{} {} ({})",
+        config.number, config.driver_name, config.team_name
+    );
+
+    spawn_joystick_service(JOYSTICK_WS_PORT, car_addr)
+        .await
+        .map_err(|e| anyhow!(e).into())
+}
+
+fn update_cached_car(cars_map: &CarsMap, car: &F1Car) {
+    if let Ok(mut guard) = cars_map.lock() {
+        if let Some(c) = guard.get_mut(&car.id) {
+            c.connection_status = car.connection_status.clone();
+            c.last_seen = car.last_seen;
+        }
+    }
 }
 
 #[tauri::command]
@@ -118,6 +175,10 @@ pub async fn disconnect_car(
     handle: AppHandle,
     discovery_service: State<'_, Arc<Mutex<DiscoveryService>>>,
 ) -> Result<(), DiscoveryError> {
+    // joystick first so its final idle packet still goes out on the link socket
+    let _ = stop_joystick_service().await;
+    let _ = stop_link_service().await;
+
     let service = discovery_service.lock().await;
     if let Some(mut car) = service.get_car_by_id(&car_id) {
         car.connection_status = ConnectionStatus::Disconnected;
@@ -1,9 +1,11 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
+use tokio::net::UdpSocket;
 use tokio::sync::watch;
 
-use crate::commands::{JoystickControl, JOYSTICK_TASK};
+use crate::commands::{link::link_socket, JoystickControl, JOYSTICK_TASK};
 use crate::joystick;
 
 #[tauri::command]
@@ -13,10 +15,27 @@ pub async fn start_joystick_service(ws_port: u16, pi_addr: String) -> Result<(),
         .parse()
         .map_err(|e| format!("Invalid PI address: {}", e))?;
 
+    spawn_joystick_service(ws_port, parsed).await
+}
+
+pub(crate) async fn spawn_joystick_service(
+    ws_port: u16,
+    pi_addr: SocketAddr,
+) -> Result<(), String> {
+    let udp = match link_socket(pi_addr).await {
+        Some(socket) => socket,
+        None => {
+            let socket = UdpSocket::bind("0.0.0.0:0")
+                .await
+                .map_err(|e| format!("Failed to bind UDP socket: {}", e))?;
+            Arc::new(socket)
+        }
+    };
+
     let (shutdown_tx, shutdown_rx) = watch::channel::<bool>(false);
 
     let handle = tokio::spawn(async move {
-        joystick::start_joystick_service(ws_port, parsed, shutdown_rx).await;
+        joystick::start_joystick_service(ws_port, pi_addr, udp, shutdown_rx).await;
     });
 
     let ctrl = JoystickControl {
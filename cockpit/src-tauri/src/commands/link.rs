@@ -0,0 +1,138 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tauri::AppHandle;
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, watch};
+
+use crate::commands::{LinkControl, LINK_TASK, LINK_THRESHOLDS};
+use crate::link::{self, ClientMessage, ServerMessage};
+use crate::link_monitor::LinkThresholds;
+
+#[tauri::command]
+#[specta::specta]
+pub async fn start_link_service(handle: AppHandle, car_addr: String) -> Result<(), String> {
+    let parsed: SocketAddr = car_addr
+        .parse()
+        .map_err(|e| format!("Invalid car address: {}", e))?;
+
+    open_link(handle, parsed).await.map(|_| ())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn stop_link_service() -> Result<(), String> {
+    if let Some(mutex) = LINK_TASK.get() {
+        let mut guard = mutex.lock().await;
+        if let Some(ctrl) = guard.take() {
+            // hand the car back so the next driver does not wait out the lock timeout
+            if let Err(e) =
+                link::send_message(&ctrl.socket, ctrl.car_addr, &ClientMessage::Release).await
+            {
+                log::debug!("Failed to release control: {}", e);
+            }
+            let _ = ctrl.shutdown.send(true);
+            if tokio::time::timeout(Duration::from_secs(2), ctrl.handle)
+                .await
+                .is_err()
+            {
+                log::warn!("car link task did not stop within 2s");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Asks the radio for control of the car; the answer arrives as a `ControlOwnershipEvent`.
+#[tauri::command]
+#[specta::specta]
+pub async fn request_takeover() -> Result<(), String> {
+    send_to_car(ClientMessage::Takeover).await
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn release_control() -> Result<(), String> {
+    send_to_car(ClientMessage::Release).await
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn get_link_thresholds() -> Result<LinkThresholds, String> {
+    let thresholds = LINK_THRESHOLDS.get_or_init(Default::default).lock().await;
+    Ok(thresholds.clone())
+}
+
+#[tauri::command]
+#[specta::specta]
+pub async fn set_link_thresholds(thresholds: LinkThresholds) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&thresholds.max_loss) {
+        return Err(format!(
+            "max_loss must be within 0..=1, got {}",
+            thresholds.max_loss
+        ));
+    }
+
+    *LINK_THRESHOLDS.get_or_init(Default::default).lock().await = thresholds;
+    Ok(())
+}
+
+/// Starts the car link for `car_addr`, or returns the running one if it already points there,
+/// along with a subscription to its control-plane replies.
+pub(crate) async fn open_link(
+    handle: AppHandle,
+    car_addr: SocketAddr,
+) -> Result<(Arc<UdpSocket>, broadcast::Receiver<ServerMessage>), String> {
+    let mutex = LINK_TASK.get_or_init(|| tokio::sync::Mutex::new(None));
+    let mut guard = mutex.lock().await;
+    if let Some(ctrl) = guard.as_ref() {
+        if ctrl.car_addr == car_addr {
+            return Ok((ctrl.socket.clone(), ctrl.replies.subscribe()));
+        }
+        return Err(format!("Already linked to {}", ctrl.car_addr));
+    }
+
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|e| format!("Failed to bind UDP socket: {}", e))?;
+    let socket = Arc::new(socket);
+
+    let (shutdown_tx, shutdown_rx) = watch::channel::<bool>(false);
+    let (replies_tx, replies_rx) = broadcast::channel::<ServerMessage>(16);
+    let task_socket = socket.clone();
+    let task_replies = replies_tx.clone();
+    let task = tokio::spawn(async move {
+        link::start_link_service(handle, task_socket, car_addr, task_replies, shutdown_rx).await;
+    });
+
+    *guard = Some(LinkControl {
+        handle: task,
+        shutdown: shutdown_tx,
+        socket: socket.clone(),
+        car_addr,
+        replies: replies_tx,
+    });
+
+    Ok((socket, replies_rx))
+}
+
+async fn send_to_car(message: ClientMessage) -> Result<(), String> {
+    let guard = LINK_TASK.get().ok_or("Car link not running")?.lock().await;
+    let ctrl = guard.as_ref().ok_or("Car link not running")?;
+
+    link::send_message(&ctrl.socket, ctrl.car_addr, &message)
+        .await
+        .map_err(|e| format!("Failed to send to {}: {}", ctrl.car_addr, e))
+}
+
+/// Socket of the running car link if it points at `car_addr`, so joystick packets
+/// come from the address the radio sends telemetry back to.
+pub(crate) async fn link_socket(car_addr: SocketAddr) -> Option<Arc<UdpSocket>> {
+    let guard = LINK_TASK.get()?.lock().await;
+    guard
+        .as_ref()
+        .filter(|ctrl| ctrl.car_addr == car_addr)
+        .map(|ctrl| ctrl.socket.clone())
+}
@@ -1,5 +1,10 @@
-use std::sync::OnceLock;
-use tokio::sync::{watch, Mutex};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+
+use crate::link::ServerMessage;
+use crate::link_monitor::LinkThresholds;
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, watch, Mutex};
 use tokio::task::JoinHandle;
 
 pub struct JoystickControl {
@@ -7,7 +12,17 @@ pub struct JoystickControl {
     pub shutdown: watch::Sender<bool>,
 }
 
+pub struct LinkControl {
+    pub handle: JoinHandle<()>,
+    pub shutdown: watch::Sender<bool>,
+    pub socket: Arc<UdpSocket>,
+    pub car_addr: SocketAddr,
+    pub replies: broadcast::Sender<ServerMessage>,
+}
+
 pub(crate) static JOYSTICK_TASK: OnceLock<Mutex<Option<JoystickControl>>> = OnceLock::new();
+pub(crate) static LINK_TASK: OnceLock<Mutex<Option<LinkControl>>> = OnceLock::new();
+pub(crate) static LINK_THRESHOLDS: OnceLock<Mutex<LinkThresholds>> = OnceLock::new();
 
 #[macro_export]
 macro_rules! collect_commands {
@@ -24,10 +39,17 @@ macro_rules! collect_commands {
             $crate::commands::discovery::is_discovery_running,
             $crate::commands::joystick::start_joystick_service,
             $crate::commands::joystick::stop_joystick_service,
+            $crate::commands::link::start_link_service,
+            $crate::commands::link::stop_link_service,
+            $crate::commands::link::request_takeover,
+            $crate::commands::link::release_control,
+            $crate::commands::link::get_link_thresholds,
+            $crate::commands::link::set_link_thresholds,
         ]
     };
 }
 
 pub mod discovery;
 pub mod joystick;
+pub mod link;
 pub mod ui;
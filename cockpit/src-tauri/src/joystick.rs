@@ -1,4 +1,6 @@
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use log::{debug, error, info};
@@ -10,6 +12,9 @@ use tokio_stream::StreamExt;
 use tokio_tungstenite::accept_async;
 use tokio_tungstenite::tungstenite::Message;
 
+/// Local websocket port the control page streams joystick samples to
+pub const JOYSTICK_WS_PORT: u16 = 9001;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Sample {
     pub ts: Instant,
@@ -21,11 +26,12 @@ pub struct Sample {
 pub async fn start_joystick_service(
     ws_port: u16,
     pi_addr: SocketAddr,
+    udp: Arc<UdpSocket>,
     mut shutdown_rx: watch::Receiver<bool>,
 ) {
     let (tx, rx) = mpsc::channel::<Sample>(512);
 
-    tokio::spawn(processor_task(rx, pi_addr));
+    tokio::spawn(processor_task(rx, udp, pi_addr));
 
     let bind_addr = format!("127.0.0.1:{}", ws_port);
     let listener = match TcpListener::bind(&bind_addr).await {
@@ -40,6 +46,8 @@ pub async fn start_joystick_service(
 
     // keep track of spawned client tasks so we can abort them on shutdown
     let mut client_handles: Vec<JoinHandle<()>> = Vec::new();
+    // input order across all websocket clients, stamped as samples arrive
+    let next_seq = Arc::new(AtomicU32::new(0));
 
     loop {
         tokio::select! {
@@ -47,6 +55,7 @@ pub async fn start_joystick_service(
                 match accept {
                     Ok((stream, addr)) => {
                         let tx = tx.clone();
+                        let next_seq = next_seq.clone();
                         let handle = tokio::spawn(async move {
                             if let Ok(ws_stream) = accept_async(stream).await {
                                 let mut ws = ws_stream;
@@ -67,7 +76,7 @@ pub async fn start_joystick_service(
                                                 ts: Instant::now(),
                                                 x: xf,
                                                 y: yf,
-                                                seq: 0,
+                                                seq: next_seq.fetch_add(1, Ordering::Relaxed),
                                             };
 
                                             // try to enqueue, drop on full
@@ -108,16 +117,10 @@ pub async fn start_joystick_service(
     }
 }
 
-async fn processor_task(mut rx: Receiver<Sample>, pi_addr: SocketAddr) {
-    let udp = match UdpSocket::bind("0.0.0.0:0").await {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Failed to bind UDP socket: {}", e);
-            return;
-        }
-    };
-
+async fn processor_task(mut rx: Receiver<Sample>, udp: Arc<UdpSocket>, pi_addr: SocketAddr) {
+    // wire seq also covers watchdog packets, so it is counted separately from sample seq
     let mut seq: u32 = 0;
+    let mut last_sample_seq: Option<u32> = None;
     let mut last_sample_time = Instant::now();
 
     let mut last_x = 0.0f32;
@@ -130,6 +133,11 @@ async fn processor_task(mut rx: Receiver<Sample>, pi_addr: SocketAddr) {
             maybe = rx.recv() => {
                 match maybe {
                     Some(s) => {
+                        if last_sample_seq.is_some_and(|last| s.seq.wrapping_sub(last) > u32::MAX / 2) {
+                            debug!("Dropping out-of-order sample seq={} (last {:?})", s.seq, last_sample_seq);
+                            continue;
+                        }
+                        last_sample_seq = Some(s.seq);
                         last_sample_time = Instant::now();
                         let mut x = s.x;
                         let mut y = s.y;
@@ -165,8 +173,9 @@ async fn processor_task(mut rx: Receiver<Sample>, pi_addr: SocketAddr) {
                     }
                     None => {
                         // channel closed; send safe/idle packet then exit
-                        // send a final idle packet
-                        let idle = [0u8;8];
+                        // it still needs a fresh seq or the radio drops it as stale
+                        let mut idle = [0u8;8];
+                        idle[0..4].copy_from_slice(&seq.to_le_bytes());
                         debug!("Sending final idle packet bytes={:02x?}", &idle);
                         let _ = udp.send_to(&idle, &pi_addr).await;
                         break;
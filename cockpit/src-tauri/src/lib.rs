@@ -13,6 +13,9 @@ use crate::discovery::DiscoveryService;
 pub mod commands;
 pub mod discovery;
 pub mod joystick;
+pub mod link;
+pub mod link_monitor;
+pub mod pairing;
 pub mod types;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
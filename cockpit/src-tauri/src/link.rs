@@ -0,0 +1,454 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+use tauri_specta::Event;
+use telemetry::{CameraSettings, CarConfiguration, CarTelemetry};
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+use crate::commands::LINK_THRESHOLDS;
+use crate::link_monitor::{LinkMonitor, PING_RATE_HZ, REPORT_INTERVAL};
+use crate::pairing::{self, PairingStore};
+use crate::types::{ConfigEvent, ControlOwnershipEvent, PongEvent, TelemetryEvent};
+
+const HANDSHAKE_ATTEMPTS: u32 = 3;
+const HANDSHAKE_ATTEMPT_TIMEOUT: Duration = Duration::from_millis(700);
+
+/// Messages understood by the radio server (`radio/src/server.rs`).
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum ClientMessage {
+    #[serde(rename = "config_request")]
+    ConfigRequest,
+    #[serde(rename = "ping")]
+    Ping { timestamp: u64 },
+    #[serde(rename = "pair_request")]
+    PairRequest { client_id: String },
+    #[serde(rename = "pair_response")]
+    PairResponse { hmac: String },
+    #[serde(rename = "resume")]
+    Resume { token: String },
+    #[serde(rename = "takeover")]
+    Takeover,
+    #[serde(rename = "release")]
+    Release,
+}
+
+/// Messages pushed back by the radio server.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum ServerMessage {
+    #[serde(rename = "config")]
+    Config { config: CarConfiguration },
+    #[serde(rename = "config_updated")]
+    ConfigUpdated { success: bool, message: String },
+    #[serde(rename = "camera_settings_updated")]
+    CameraSettingsUpdated {
+        success: bool,
+        message: String,
+        settings: CameraSettings,
+    },
+    #[serde(rename = "recording")]
+    Recording {
+        success: bool,
+        message: String,
+        recording: Option<String>,
+    },
+    #[serde(rename = "pong")]
+    Pong {
+        timestamp: u64,
+        #[serde(default)]
+        last_seq: Option<u32>,
+        #[serde(default)]
+        received: u32,
+    },
+    #[serde(rename = "telemetry")]
+    Telemetry(CarTelemetry),
+    #[serde(rename = "pair_challenge")]
+    PairChallenge { nonce: String },
+    #[serde(rename = "paired")]
+    Paired { token: String },
+    #[serde(rename = "pair_failed")]
+    PairFailed { reason: String },
+    #[serde(rename = "unauthorized")]
+    Unauthorized { reason: String },
+    #[serde(rename = "control_granted")]
+    ControlGranted,
+    #[serde(rename = "control_denied")]
+    ControlDenied { reason: String },
+    #[serde(rename = "control_lost")]
+    ControlLost { reason: String },
+    #[serde(rename = "joined")]
+    Joined { role: String },
+    /// Only sent to pit engineers, the cockpit never asks for it
+    #[serde(rename = "clients")]
+    Clients { clients: Vec<serde_json::Value> },
+    /// Anything a newer radio sends that this cockpit doesn't know yet
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// The car only talks to paired cockpits and no PIN was given
+    PairingRequired,
+    /// The car rejected the PIN or session token
+    PairingFailed(String),
+    Link(anyhow::Error),
+}
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PairingRequired => f.write_str("Car requires pairing, enter its PIN"),
+            Self::PairingFailed(reason) => write!(f, "Pairing failed: {}", reason),
+            Self::Link(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for HandshakeError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Link(err)
+    }
+}
+
+/// Microseconds on a monotonic clock local to this process, used as the ping timestamp.
+pub fn link_clock_us() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+pub async fn send_message(
+    udp: &UdpSocket,
+    car_addr: SocketAddr,
+    message: &ClientMessage,
+) -> Result<()> {
+    let json = serde_json::to_vec(message)?;
+    udp.send_to(&json, car_addr).await?;
+    Ok(())
+}
+
+/// Authenticates with the car and fetches its config. A stored session token is tried
+/// first; if the car still refuses, `pin` is used to pair and the new token is stored. The
+/// link service must already be running to receive the replies.
+pub async fn handshake(
+    udp: &UdpSocket,
+    car_addr: SocketAddr,
+    replies: &mut broadcast::Receiver<ServerMessage>,
+    store: &mut PairingStore,
+    car_id: &str,
+    pin: Option<&str>,
+) -> Result<CarConfiguration, HandshakeError> {
+    if let Some(token) = store.token(car_id).map(str::to_string) {
+        let resumed = request(
+            udp,
+            car_addr,
+            replies,
+            &ClientMessage::Resume { token },
+            |msg| match msg {
+                ServerMessage::Paired { .. } => Some(None),
+                ServerMessage::PairFailed { reason } => Some(Some(reason)),
+                _ => None,
+            },
+        )
+        .await?;
+
+        if let Some(reason) = resumed {
+            warn!("Session for car {} rejected: {}", car_id, reason);
+            if let Err(e) = store.forget(car_id) {
+                warn!("Failed to drop stale session: {:#}", e);
+            }
+        }
+    }
+
+    if let Some(config) = request_config(udp, car_addr, replies).await? {
+        return Ok(config);
+    }
+
+    let pin = pin.ok_or(HandshakeError::PairingRequired)?;
+    let client_id = store.client_id().to_string();
+
+    let nonce = request(
+        udp,
+        car_addr,
+        replies,
+        &ClientMessage::PairRequest {
+            client_id: client_id.clone(),
+        },
+        |msg| match msg {
+            ServerMessage::PairChallenge { nonce } => Some(Ok(nonce)),
+            ServerMessage::PairFailed { reason } => Some(Err(reason)),
+            _ => None,
+        },
+    )
+    .await?
+    .map_err(HandshakeError::PairingFailed)?;
+
+    let hmac = pairing::sign(pin, &nonce, &client_id);
+    let token = request(
+        udp,
+        car_addr,
+        replies,
+        &ClientMessage::PairResponse { hmac },
+        |msg| match msg {
+            ServerMessage::Paired { token } => Some(Ok(token)),
+            ServerMessage::PairFailed { reason } => Some(Err(reason)),
+            _ => None,
+        },
+    )
+    .await?
+    .map_err(HandshakeError::PairingFailed)?;
+
+    info!("Paired with car {} as {}", car_id, client_id);
+    if let Err(e) = store.set_token(car_id, token) {
+        warn!("Failed to store session for car {}: {:#}", car_id, e);
+    }
+
+    request_config(udp, car_addr, replies)
+        .await?
+        .ok_or_else(|| HandshakeError::PairingFailed("Car refused the new session".to_string()))
+}
+
+/// `None` when the car wants this client to pair first.
+async fn request_config(
+    udp: &UdpSocket,
+    car_addr: SocketAddr,
+    replies: &mut broadcast::Receiver<ServerMessage>,
+) -> Result<Option<CarConfiguration>> {
+    request(
+        udp,
+        car_addr,
+        replies,
+        &ClientMessage::ConfigRequest,
+        |msg| match msg {
+            ServerMessage::Config { config } => Some(Some(config)),
+            ServerMessage::Unauthorized { .. } => Some(None),
+            _ => None,
+        },
+    )
+    .await
+}
+
+/// Sends `message` until `reply` picks an answer out of the incoming server messages.
+async fn request<T>(
+    udp: &UdpSocket,
+    car_addr: SocketAddr,
+    replies: &mut broadcast::Receiver<ServerMessage>,
+    message: &ClientMessage,
+    mut reply: impl FnMut(ServerMessage) -> Option<T>,
+) -> Result<T> {
+    for attempt in 1..=HANDSHAKE_ATTEMPTS {
+        send_message(udp, car_addr, message)
+            .await
+            .map_err(|e| anyhow!("Failed to reach {}: {}", car_addr, e))?;
+
+        let deadline = tokio::time::Instant::now() + HANDSHAKE_ATTEMPT_TIMEOUT;
+        loop {
+            match tokio::time::timeout_at(deadline, replies.recv()).await {
+                Ok(Ok(msg)) => {
+                    if let Some(answer) = reply(msg) {
+                        return Ok(answer);
+                    }
+                }
+                Ok(Err(RecvError::Lagged(_))) => {}
+                Ok(Err(RecvError::Closed)) => {
+                    return Err(anyhow!("Car link closed during handshake"))
+                }
+                Err(_) => {
+                    debug!("handshake attempt {} to {} timed out", attempt, car_addr);
+                    break;
+                }
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "No response from {} after {} attempts",
+        car_addr,
+        HANDSHAKE_ATTEMPTS
+    ))
+}
+
+/// Receives server messages on the socket shared with the joystick sender and forwards them
+/// to the UI. The radio replies to whichever address its control packets come from, so both
+/// directions must use the same socket.
+pub async fn start_link_service(
+    app: AppHandle,
+    udp: Arc<UdpSocket>,
+    car_addr: SocketAddr,
+    replies: broadcast::Sender<ServerMessage>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    let mut buf = vec![0u8; 65507];
+    let mut monitor = LinkMonitor::default();
+    let mut ping_interval = tokio::time::interval(Duration::from_secs(1) / PING_RATE_HZ);
+    let mut report_interval = tokio::time::interval(REPORT_INTERVAL);
+    let mut degraded = false;
+
+    info!("car link to {} open", car_addr);
+
+    loop {
+        tokio::select! {
+            _ = ping_interval.tick() => {
+                let timestamp = link_clock_us();
+                monitor.on_ping_sent(timestamp, Instant::now());
+                if let Err(e) = send_message(&udp, car_addr, &ClientMessage::Ping { timestamp }).await {
+                    debug!("ping send error: {}", e);
+                }
+            }
+            _ = report_interval.tick() => {
+                let thresholds = LINK_THRESHOLDS.get_or_init(Default::default).lock().await.clone();
+                let report = monitor.report(&thresholds, Instant::now());
+
+                if report.warnings.is_empty() {
+                    if degraded {
+                        info!("link to {} recovered", car_addr);
+                    }
+                } else if !degraded {
+                    warn!("link to {} degraded: {}", car_addr, report.warnings.join(", "));
+                }
+                degraded = !report.warnings.is_empty();
+
+                if let Err(e) = report.emit(&app) {
+                    error!("Failed to emit link quality event: {}", e);
+                }
+            }
+            recv = udp.recv_from(&mut buf) => {
+                match recv {
+                    Ok((len, from)) if from == car_addr => {
+                        match serde_json::from_slice::<ServerMessage>(&buf[..len]) {
+                            Ok(ServerMessage::Pong { timestamp, last_seq, received }) => {
+                                monitor.on_pong(timestamp, last_seq, received, Instant::now());
+                                emit_pong(&app, timestamp);
+                            }
+                            Ok(msg) => handle_server_message(&app, &replies, msg),
+                            Err(e) => warn!("Unparseable message from {}: {}", from, e),
+                        }
+                    }
+                    Ok((_, from)) => debug!("Ignoring datagram from {}", from),
+                    Err(e) => {
+                        // ICMP port unreachable surfaces here while the radio restarts
+                        debug!("car link recv error: {}", e);
+                    }
+                }
+            }
+            _ = shutdown_rx.changed() => {
+                if *shutdown_rx.borrow() {
+                    info!("car link shutdown requested");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn handle_server_message(
+    app: &AppHandle,
+    replies: &broadcast::Sender<ServerMessage>,
+    msg: ServerMessage,
+) {
+    // control-plane replies feed any handshake in progress; nobody listening is fine
+    if !matches!(
+        msg,
+        ServerMessage::Telemetry(_) | ServerMessage::Pong { .. }
+    ) {
+        let _ = replies.send(msg.clone());
+    }
+
+    let emitted = match msg {
+        ServerMessage::Telemetry(telemetry) => TelemetryEvent { telemetry }.emit(app),
+        ServerMessage::Config { config } => {
+            info!(
+                "Car config: #{} {} ({})",
+                config.number, config.driver_name, config.team_name
+            );
+            ConfigEvent { config }.emit(app)
+        }
+        ServerMessage::ConfigUpdated { success, message } => {
+            if success {
+                info!("Car config updated: {}", message);
+            } else {
+                warn!("Car config update failed: {}", message);
+            }
+            Ok(())
+        }
+        ServerMessage::CameraSettingsUpdated {
+            success, message, ..
+        } => {
+            if success {
+                info!("Camera settings updated: {}", message);
+            } else {
+                warn!("Camera settings update failed: {}", message);
+            }
+            Ok(())
+        }
+        ServerMessage::Recording {
+            success, message, ..
+        } => {
+            if success {
+                info!("Car recording: {}", message);
+            } else {
+                warn!("Car recording request failed: {}", message);
+            }
+            Ok(())
+        }
+        ServerMessage::Joined { role } => {
+            info!("Joined the car as {}", role);
+            Ok(())
+        }
+        ServerMessage::Pong { timestamp, .. } => {
+            emit_pong(app, timestamp);
+            Ok(())
+        }
+        ServerMessage::Unauthorized { reason } => {
+            warn!("Car refused request: {}", reason);
+            Ok(())
+        }
+        ServerMessage::ControlGranted => {
+            info!("Driving the car");
+            ControlOwnershipEvent {
+                driving: true,
+                reason: None,
+            }
+            .emit(app)
+        }
+        ServerMessage::ControlDenied { reason } | ServerMessage::ControlLost { reason } => {
+            warn!("Not driving the car: {}", reason);
+            ControlOwnershipEvent {
+                driving: false,
+                reason: Some(reason),
+            }
+            .emit(app)
+        }
+        ServerMessage::PairChallenge { .. }
+        | ServerMessage::Paired { .. }
+        | ServerMessage::PairFailed { .. }
+        | ServerMessage::Clients { .. } => Ok(()),
+        ServerMessage::Unknown => {
+            debug!("Ignoring message type this cockpit doesn't know");
+            Ok(())
+        }
+    };
+
+    if let Err(e) = emitted {
+        error!("Failed to emit link event: {}", e);
+    }
+}
+
+fn emit_pong(app: &AppHandle, timestamp: u64) {
+    let rtt_us = link_clock_us().saturating_sub(timestamp);
+    let pong = PongEvent {
+        timestamp,
+        rtt_ms: rtt_us as f64 / 1000.0,
+    };
+    if let Err(e) = pong.emit(app) {
+        error!("Failed to emit link event: {}", e);
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use telemetry::SeqSample;
+
+use crate::types::LinkQualityEvent;
+
+/// Pings sent per second
+pub const PING_RATE_HZ: u32 = 5;
+/// How often a `LinkQualityEvent` is published
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+const RTT_WINDOW: usize = 50;
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Limits past which the link counts as degraded and a warning is raised.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkThresholds {
+    pub max_rtt_p95_ms: f64,
+    pub max_jitter_ms: f64,
+    /// Fraction of pings or control packets lost, 0.0..=1.0
+    pub max_loss: f64,
+}
+
+impl Default for LinkThresholds {
+    fn default() -> Self {
+        Self {
+            max_rtt_p95_ms: 100.0,
+            max_jitter_ms: 30.0,
+            max_loss: 0.05,
+        }
+    }
+}
+
+/// Ping/pong bookkeeping for one car link.
+///
+/// Jitter is the RFC 3550 interarrival estimate over consecutive RTTs. Control loss compares
+/// how far the radio's highest joystick seq advanced against how many packets it counted.
+#[derive(Debug, Default)]
+pub struct LinkMonitor {
+    pending: HashMap<u64, Instant>,
+    rtts_ms: VecDeque<f64>,
+    jitter_ms: f64,
+    pings_sent: u32,
+    pings_lost: u32,
+    seq_base: Option<SeqSample>,
+    seq_latest: Option<SeqSample>,
+}
+
+impl LinkMonitor {
+    pub fn on_ping_sent(&mut self, timestamp: u64, now: Instant) {
+        self.pending.insert(timestamp, now);
+        self.pings_sent += 1;
+    }
+
+    pub fn on_pong(&mut self, timestamp: u64, last_seq: Option<u32>, received: u32, now: Instant) {
+        let Some(sent_at) = self.pending.remove(&timestamp) else {
+            // answered after it was already counted as lost
+            return;
+        };
+        let rtt_ms = now.duration_since(sent_at).as_secs_f64() * 1000.0;
+
+        if let Some(&prev) = self.rtts_ms.back() {
+            self.jitter_ms += ((rtt_ms - prev).abs() - self.jitter_ms) / 16.0;
+        }
+        if self.rtts_ms.len() == RTT_WINDOW {
+            self.rtts_ms.pop_front();
+        }
+        self.rtts_ms.push_back(rtt_ms);
+
+        if let Some(last_seq) = last_seq {
+            let sample = SeqSample { last_seq, received };
+            self.seq_base.get_or_insert(sample);
+            self.seq_latest = Some(sample);
+        }
+    }
+
+    /// Expires unanswered pings and summarises the period since the last report.
+    pub fn report(&mut self, thresholds: &LinkThresholds, now: Instant) -> LinkQualityEvent {
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, sent_at| now.duration_since(*sent_at) < PING_TIMEOUT);
+        self.pings_lost += (before - self.pending.len()) as u32;
+
+        let mut sorted: Vec<f64> = self.rtts_ms.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+
+        let ping_loss = ratio(self.pings_lost, self.pings_sent);
+        let control_loss = match (self.seq_base, self.seq_latest) {
+            (Some(base), Some(latest)) => f64::from(latest.loss_since(&base)),
+            _ => 0.0,
+        };
+
+        let mut event = LinkQualityEvent {
+            rtt_p50_ms: percentile(&sorted, 0.50),
+            rtt_p95_ms: percentile(&sorted, 0.95),
+            rtt_p99_ms: percentile(&sorted, 0.99),
+            jitter_ms: self.jitter_ms,
+            ping_loss,
+            control_loss,
+            warnings: Vec::new(),
+        };
+
+        if event
+            .rtt_p95_ms
+            .is_some_and(|p95| p95 > thresholds.max_rtt_p95_ms)
+        {
+            event.warnings.push(format!(
+                "RTT p95 {:.0} ms above {:.0} ms",
+                event.rtt_p95_ms.unwrap_or_default(),
+                thresholds.max_rtt_p95_ms
+            ));
+        }
+        if event.jitter_ms > thresholds.max_jitter_ms {
+            event.warnings.push(format!(
+                "Jitter {:.0} ms above {:.0} ms",
+                event.jitter_ms, thresholds.max_jitter_ms
+            ));
+        }
+        if ping_loss > thresholds.max_loss {
+            event
+                .warnings
+                .push(format!("Ping loss {:.0}%", ping_loss * 100.0));
+        }
+        if control_loss > thresholds.max_loss {
+            event
+                .warnings
+                .push(format!("Control packet loss {:.0}%", control_loss * 100.0));
+        }
+
+        // loss is reported per period, the RTT window keeps rolling
+        self.pings_sent = self.pending.len() as u32;
+        self.pings_lost = 0;
+        self.seq_base = self.seq_latest;
+
+        event
+    }
+}
+
+fn ratio(part: u32, whole: u32) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p * (sorted.len() - 1) as f64).round() as usize;
+    Some(sorted[rank])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pings at `sent` and answers after `rtt_ms`, reporting the joystick seqs.
+    fn ping(
+        monitor: &mut LinkMonitor,
+        timestamp: u64,
+        sent: Instant,
+        rtt_ms: u64,
+        seq: Option<SeqSample>,
+    ) {
+        monitor.on_ping_sent(timestamp, sent);
+        monitor.on_pong(
+            timestamp,
+            seq.map(|s| s.last_seq),
+            seq.map_or(0, |s| s.received),
+            sent + Duration::from_millis(rtt_ms),
+        );
+    }
+
+    #[test]
+    fn percentiles_pick_the_nearest_rank() {
+        let odd = [10.0, 20.0, 30.0, 40.0, 50.0];
+        assert_eq!(percentile(&odd, 0.50), Some(30.0));
+        assert_eq!(percentile(&odd, 0.95), Some(50.0));
+        assert_eq!(percentile(&odd, 0.0), Some(10.0));
+
+        // rank 1.5 rounds up
+        let even = [10.0, 20.0, 30.0, 40.0];
+        assert_eq!(percentile(&even, 0.50), Some(30.0));
+        assert_eq!(percentile(&even, 0.95), Some(40.0));
+        assert_eq!(percentile(&even, 0.25), Some(20.0));
+
+        assert_eq!(percentile(&[], 0.50), None);
+    }
+
+    #[test]
+    fn one_rtt_has_no_jitter() {
+        let mut monitor = LinkMonitor::default();
+        let t0 = Instant::now();
+        ping(&mut monitor, 1, t0, 40, None);
+
+        let event = monitor.report(&LinkThresholds::default(), t0 + REPORT_INTERVAL);
+        assert_eq!(event.jitter_ms, 0.0);
+        assert_eq!(event.rtt_p50_ms, Some(40.0));
+        assert_eq!(event.rtt_p99_ms, Some(40.0));
+        assert_eq!((event.ping_loss, event.control_loss), (0.0, 0.0));
+        assert!(event.warnings.is_empty(), "{:?}", event.warnings);
+
+        // the second RTT moves it a sixteenth of the way to the difference
+        ping(&mut monitor, 2, t0 + REPORT_INTERVAL, 56, None);
+        let event = monitor.report(&LinkThresholds::default(), t0 + REPORT_INTERVAL * 2);
+        assert_eq!(event.jitter_ms, 1.0);
+    }
+
+    #[test]
+    fn control_loss_counts_across_seq_wrap() {
+        let mut monitor = LinkMonitor::default();
+        let t0 = Instant::now();
+        let before = SeqSample {
+            last_seq: u32::MAX - 9,
+            received: 100,
+        };
+        ping(&mut monitor, 1, t0, 10, Some(before));
+        // 20 seqs later, past the wrap, with 10 of them received
+        let after = SeqSample {
+            last_seq: 10,
+            received: 110,
+        };
+        ping(
+            &mut monitor,
+            2,
+            t0 + Duration::from_millis(200),
+            10,
+            Some(after),
+        );
+
+        let event = monitor.report(&LinkThresholds::default(), t0 + REPORT_INTERVAL);
+        assert_eq!(event.control_loss, 0.5);
+        assert_eq!(event.warnings, ["Control packet loss 50%"]);
+
+        // the next period starts from where this one ended
+        let later = SeqSample {
+            last_seq: 30,
+            received: 130,
+        };
+        ping(&mut monitor, 3, t0 + REPORT_INTERVAL, 10, Some(later));
+        let event = monitor.report(&LinkThresholds::default(), t0 + REPORT_INTERVAL * 2);
+        assert_eq!(event.control_loss, 0.0);
+    }
+}
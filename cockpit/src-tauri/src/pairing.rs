@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use log::warn;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tauri::{AppHandle, Manager};
+
+const STORE_FILE: &str = "pairings.json";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The identity this cockpit pairs under and the session tokens cars have issued to it,
+/// so the PIN only has to be entered once per car.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PairingStore {
+    client_id: String,
+    /// Session token keyed by car id
+    tokens: HashMap<String, String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl PairingStore {
+    pub fn load(app: &AppHandle) -> Result<Self> {
+        let path = app
+            .path()
+            .app_data_dir()
+            .context("No app data directory")?
+            .join(STORE_FILE);
+
+        let mut store = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str::<Self>(&json).unwrap_or_else(|e| {
+                warn!("Discarding unreadable {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        store.path = path;
+
+        if store.client_id.is_empty() {
+            store.client_id = format!("cockpit-{}", random_hex(8));
+            store.save()?;
+        }
+
+        Ok(store)
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn token(&self, car_id: &str) -> Option<&str> {
+        self.tokens.get(car_id).map(String::as_str)
+    }
+
+    pub fn set_token(&mut self, car_id: &str, token: String) -> Result<()> {
+        self.tokens.insert(car_id.to_string(), token);
+        self.save()
+    }
+
+    pub fn forget(&mut self, car_id: &str) -> Result<()> {
+        if self.tokens.remove(car_id).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&self.path, json)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// Answer to a pairing challenge: `hex(HMAC-SHA256(pin, nonce || client_id))`, matching
+/// `radio/src/pairing.rs`.
+pub fn sign(pin: &str, nonce: &str, client_id: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(pin.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(nonce.as_bytes());
+    mac.update(client_id.as_bytes());
+    to_hex(&mac.finalize().into_bytes())
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::rng().fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+            let _ = write!(s, "{:02x}", b);
+            s
+        })
+}
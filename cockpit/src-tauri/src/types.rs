@@ -6,7 +6,7 @@ use std::{
 use serde::{Deserialize, Serialize};
 use specta::Type;
 use tauri_specta::Event;
-pub use telemetry::{CarStatus, ConnectionStatus, F1Car};
+pub use telemetry::{CarConfiguration, CarStatus, CarTelemetry, ConnectionStatus, F1Car};
 
 #[macro_export]
 macro_rules! collect_events {
@@ -17,6 +17,11 @@ macro_rules! collect_events {
             $crate::types::CarOfflineEvent,
             $crate::types::CarRemovedEvent,
             $crate::types::DiscoveryStatusEvent,
+            $crate::types::TelemetryEvent,
+            $crate::types::ConfigEvent,
+            $crate::types::PongEvent,
+            $crate::types::LinkQualityEvent,
+            $crate::types::ControlOwnershipEvent,
         ]
     };
 }
@@ -49,6 +54,47 @@ pub struct DiscoveryStatusEvent {
     pub message: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+pub struct TelemetryEvent {
+    pub telemetry: CarTelemetry,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+pub struct ConfigEvent {
+    pub config: CarConfiguration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct PongEvent {
+    pub timestamp: u64,
+    pub rtt_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkQualityEvent {
+    pub rtt_p50_ms: Option<f64>,
+    pub rtt_p95_ms: Option<f64>,
+    pub rtt_p99_ms: Option<f64>,
+    pub jitter_ms: f64,
+    /// Fraction of pings unanswered during the last report period
+    pub ping_loss: f64,
+    /// Fraction of joystick packets the radio never saw during the last report period
+    pub control_loss: f64,
+    /// Thresholds currently exceeded, empty while the link is healthy
+    pub warnings: Vec<String>,
+}
+
+/// Whether this cockpit is the one driving the car, sent whenever the radio says so.
+#[derive(Debug, Clone, Serialize, Deserialize, Type, Event)]
+#[serde(rename_all = "camelCase")]
+pub struct ControlOwnershipEvent {
+    pub driving: bool,
+    /// Why control was refused or lost
+    pub reason: Option<String>,
+}
+
 pub type CarsMap = Arc<Mutex<HashMap<String, F1Car>>>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Type)]
@@ -79,6 +125,27 @@ impl DiscoveryError {
         }
     }
 
+    pub fn connection_failed(msg: &str) -> Self {
+        Self {
+            code: "CONNECTION_FAILED".to_string(),
+            message: format!("Connection to car failed: {msg}"),
+        }
+    }
+
+    pub fn pairing_required() -> Self {
+        Self {
+            code: "PAIRING_REQUIRED".to_string(),
+            message: "Car requires pairing, enter the PIN shown on the car".to_string(),
+        }
+    }
+
+    pub fn pairing_failed(msg: &str) -> Self {
+        Self {
+            code: "PAIRING_FAILED".to_string(),
+            message: format!("Pairing failed: {msg}"),
+        }
+    }
+
     pub fn car_not_found(car_id: &str) -> Self {
         Self {
             code: "CAR_NOT_FOUND".to_string(),
@@ -45,9 +45,9 @@ async getDiscoveredCars() : Promise<Result<F1Car[], DiscoveryError>> {
     else return { status: "error", error: e  as any };
 }
 },
-async connectToCar(carId: string) : Promise<Result<null, DiscoveryError>> {
+async connectToCar(carId: string, pin: string | null) : Promise<Result<null, DiscoveryError>> {
     try {
-    return { status: "ok", data: await TAURI_INVOKE("connect_to_car", { carId }) };
+    return { status: "ok", data: await TAURI_INVOKE("connect_to_car", { carId, pin }) };
 } catch (e) {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
@@ -92,6 +92,54 @@ async stopJoystickService() : Promise<Result<null, string>> {
     if(e instanceof Error) throw e;
     else return { status: "error", error: e  as any };
 }
+},
+async startLinkService(carAddr: string) : Promise<Result<null, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("start_link_service", { carAddr }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async stopLinkService() : Promise<Result<null, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("stop_link_service") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async requestTakeover() : Promise<Result<null, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("request_takeover") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async releaseControl() : Promise<Result<null, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("release_control") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async getLinkThresholds() : Promise<Result<LinkThresholds, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("get_link_thresholds") };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
+},
+async setLinkThresholds(thresholds: LinkThresholds) : Promise<Result<null, string>> {
+    try {
+    return { status: "ok", data: await TAURI_INVOKE("set_link_thresholds", { thresholds }) };
+} catch (e) {
+    if(e instanceof Error) throw e;
+    else return { status: "error", error: e  as any };
+}
 }
 }
 
@@ -103,13 +151,23 @@ carDiscoveredEvent: CarDiscoveredEvent,
 carOfflineEvent: CarOfflineEvent,
 carRemovedEvent: CarRemovedEvent,
 carUpdatedEvent: CarUpdatedEvent,
-discoveryStatusEvent: DiscoveryStatusEvent
+configEvent: ConfigEvent,
+controlOwnershipEvent: ControlOwnershipEvent,
+discoveryStatusEvent: DiscoveryStatusEvent,
+linkQualityEvent: LinkQualityEvent,
+pongEvent: PongEvent,
+telemetryEvent: TelemetryEvent
 }>({
 carDiscoveredEvent: "car-discovered-event",
 carOfflineEvent: "car-offline-event",
 carRemovedEvent: "car-removed-event",
 carUpdatedEvent: "car-updated-event",
-discoveryStatusEvent: "discovery-status-event"
+configEvent: "config-event",
+controlOwnershipEvent: "control-ownership-event",
+discoveryStatusEvent: "discovery-status-event",
+linkQualityEvent: "link-quality-event",
+pongEvent: "pong-event",
+telemetryEvent: "telemetry-event"
 })
 
 /** user-defined constants **/
@@ -118,16 +176,147 @@ discoveryStatusEvent: "discovery-status-event"
 
 /** user-defined types **/
 
+/**
+ * Onboard camera capture settings. The radio clamps them to what the camera supports and
+ * reports back the settings it actually runs with.
+ */
+export type CameraSettings = { width: number; height: number; fps: number; 
+/**
+ * JPEG quality, 1-100
+ */
+quality: number; 
+/**
+ * Degrees, 0 or 180
+ */
+rotation: number; hflip: boolean; vflip: boolean; exposure: ExposureMode; codec: VideoCodec }
+export type CarConfiguration = { number: number; driver_name: string; team_name: string; camera?: CameraSettings }
 export type CarDiscoveredEvent = { car: F1Car }
 export type CarOfflineEvent = { car: F1Car }
 export type CarRemovedEvent = { carId: string }
+/**
+ * Periodic car state pushed from the radio to the controlling client.
+ */
+export type CarTelemetry = { 
+/**
+ * Unix time on the car when the snapshot was taken
+ */
+timestampMs: number; 
+/**
+ * m/s^2 in the car frame, x pointing forward
+ */
+acceleration: Vector3; 
+/**
+ * m/s, integrated from forward acceleration so it drifts back to zero at rest
+ */
+speedEstimate: number; link: LinkQuality; 
+/**
+ * `None` until the powertrain has reported in, or if it can't sense the battery
+ */
+batteryMv: number | null; failsafe: FailsafeState; 
+/**
+ * `None` while the camera is off or adaptive quality is disabled
+ */
+video: VideoQuality | null }
 export type CarUpdatedEvent = { car: F1Car }
+export type ConfigEvent = { config: CarConfiguration }
 export type ConnectionStatus = "Disconnected" | "Connecting" | "Connected" | { Failed: string }
+/**
+ * Whether this cockpit is the one driving the car, sent whenever the radio says so.
+ */
+export type ControlOwnershipEvent = { driving: boolean; 
+/**
+ * Why control was refused or lost
+ */
+reason: string | null }
 export type DiscoveryError = { code: string; message: string }
 export type DiscoveryStatusEvent = { isRunning: boolean; message: string }
+export type ExposureMode = "normal" | 
+/**
+ * Shorter exposures to cut motion blur at speed
+ */
+"sport" | "short" | "long"
 export type F1Car = { id: string; number: number; driver: string; team: string; ip: string; port: number; version: string; connectionStatus: ConnectionStatus; lastSeen: SystemTime | null }
+/**
+ * Car-side failsafe, escalated by the radio as control packets go missing.
+ * 
+ * Variants are ordered by severity. `Disarmed` is the power-on state and is
+ * only left once the driver sends a neutral throttle.
+ */
+export type FailsafeState = "Armed" | "Coast" | "Brake" | "Disarmed"
+export type LinkQuality = { 
+/**
+ * Time since the driver's last accepted control packet
+ */
+controlAgeMs: number | null; 
+/**
+ * Control packets accepted per second over the last telemetry period
+ */
+controlRateHz: number; 
+/**
+ * Stale or duplicate joystick packets discarded since the driver connected
+ */
+controlDropped: number; 
+/**
+ * Fraction of the driver's joystick packets that never arrived over the last telemetry
+ * period, from how far the sequence advanced against how many packets came in
+ */
+controlLoss: number; 
+/**
+ * Whether status frames are arriving from the powertrain
+ */
+uartConnected: boolean }
+export type LinkQualityEvent = { rttP50Ms: number | null; rttP95Ms: number | null; rttP99Ms: number | null; jitterMs: number; 
+/**
+ * Fraction of pings unanswered during the last report period
+ */
+pingLoss: number; 
+/**
+ * Fraction of joystick packets the radio never saw during the last report period
+ */
+controlLoss: number; 
+/**
+ * Thresholds currently exceeded, empty while the link is healthy
+ */
+warnings: string[] }
+/**
+ * Limits past which the link counts as degraded and a warning is raised.
+ */
+export type LinkThresholds = { maxRttP95Ms: number; maxJitterMs: number; 
+/**
+ * Fraction of pings or control packets lost, 0.0..=1.0
+ */
+maxLoss: number }
 export type Orientation = "Portrait" | "Landscape"
+export type PongEvent = { timestamp: number; rttMs: number }
 export type SystemTime = { duration_since_epoch: number; duration_since_unix_epoch: number }
+export type TelemetryEvent = { telemetry: CarTelemetry }
+export type Vector3 = { x: number; y: number; z: number }
+export type VideoCodec = 
+/**
+ * JPEG frames, for the MJPEG stream, snapshots and recordings
+ */
+"mjpeg" | 
+/**
+ * Hardware H.264 at a fraction of the bandwidth, over the WebSocket video only
+ */
+"h264"
+export type VideoProfile = { width: number; height: number; fps: number; 
+/**
+ * JPEG quality, 1-100
+ */
+quality: number }
+/**
+ * The video quality the radio settled on for the current link.
+ */
+export type VideoQuality = { 
+/**
+ * Step of the ladder, 0 being the configured camera settings
+ */
+level: number; 
+/**
+ * What the camera runs at
+ */
+profile: VideoProfile }
 
 /** tauri-specta globals **/
 
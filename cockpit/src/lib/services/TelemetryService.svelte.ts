@@ -0,0 +1,88 @@
+import type { UnlistenFn } from "@tauri-apps/api/event";
+import { warn } from "@tauri-apps/plugin-log";
+import {
+    events,
+    type CarConfiguration,
+    type CarTelemetry,
+    type LinkQualityEvent
+} from "../bindings";
+
+const STANDARD_GRAVITY = 9.80665;
+
+export class CarTelemetryService {
+    telemetry = $state<CarTelemetry | undefined>(undefined);
+    config = $state<CarConfiguration | undefined>(undefined);
+    rttMs = $state<number | undefined>(undefined);
+    linkQuality = $state<LinkQualityEvent | undefined>(undefined);
+    // undefined until the radio has said who is driving
+    driving = $state<boolean | undefined>(undefined);
+    controlReason = $state<string | null>(null);
+    private unlistenFns: UnlistenFn[] = [];
+
+    speedKmh = $derived((this.telemetry?.speedEstimate ?? 0) * 3.6);
+    gForce = $derived.by(() => {
+        const a = this.telemetry?.acceleration;
+        if (!a) return 0;
+        // lateral and longitudinal only, gravity sits on z
+        return Math.hypot(a.x, a.y) / STANDARD_GRAVITY;
+    });
+
+    // the car link itself is opened by `connectToCar`, this only subscribes to its events
+    async listen(): Promise<void> {
+        await this.setupEventListeners();
+    }
+
+    async unlisten(): Promise<void> {
+        for (const unlisten of this.unlistenFns) {
+            unlisten();
+        }
+        this.unlistenFns = [];
+        this.telemetry = undefined;
+        this.rttMs = undefined;
+        this.linkQuality = undefined;
+        this.driving = undefined;
+        this.controlReason = null;
+    }
+
+    private async setupEventListeners(): Promise<void> {
+        if (this.unlistenFns.length > 0) return;
+
+        const unlistenTelemetry = await events.telemetryEvent.listen((event) => {
+            this.telemetry = event.payload.telemetry;
+        });
+
+        const unlistenConfig = await events.configEvent.listen((event) => {
+            this.config = event.payload.config;
+        });
+
+        const unlistenPong = await events.pongEvent.listen((event) => {
+            this.rttMs = event.payload.rttMs;
+        });
+
+        const unlistenLinkQuality = await events.linkQualityEvent.listen((event) => {
+            const degraded = event.payload.warnings.length > 0;
+            if (degraded && !this.linkQuality?.warnings.length) {
+                warn(`Link degraded: ${event.payload.warnings.join(", ")}`);
+            }
+            this.linkQuality = event.payload;
+        });
+
+        const unlistenOwnership = await events.controlOwnershipEvent.listen((event) => {
+            if (!event.payload.driving && this.driving !== false) {
+                warn(`Not driving: ${event.payload.reason}`);
+            }
+            this.driving = event.payload.driving;
+            this.controlReason = event.payload.reason;
+        });
+
+        this.unlistenFns = [
+            unlistenTelemetry,
+            unlistenConfig,
+            unlistenPong,
+            unlistenLinkQuality,
+            unlistenOwnership
+        ];
+    }
+}
+
+export const carTelemetryService = new CarTelemetryService();
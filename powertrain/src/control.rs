@@ -0,0 +1,372 @@
+//! Hardware-independent mapping from `ControlMessage` to motor and servo outputs.
+//!
+//! Outputs are tracked in per-mille (-1000..=1000) so ramping stays smooth at
+//! the 100 Hz control rate even though commands arrive as -100..=100 percent.
+
+use telemetry::{ControlMessage, FailsafeState};
+
+pub const OUTPUT_MAX: i16 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drive {
+    /// PWM duty in per-mille with IN1 high / IN2 low
+    Forward(u16),
+    /// PWM duty in per-mille with IN1 low / IN2 high
+    Reverse(u16),
+    /// IN1 = IN2 with the enable pin high, shorts the motor terminals
+    Brake,
+    /// Enable pin low, the motor spins freely
+    Coast,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServoCalibration {
+    pub min_us: u16,
+    pub center_us: u16,
+    pub max_us: u16,
+}
+
+impl ServoCalibration {
+    pub fn pulse_us(&self, steering: i16) -> u16 {
+        let steering = steering.clamp(-OUTPUT_MAX, OUTPUT_MAX) as i32;
+        let center = self.center_us as i32;
+
+        let span = if steering < 0 {
+            center - self.min_us as i32
+        } else {
+            self.max_us as i32 - center
+        };
+
+        (center + span * steering / OUTPUT_MAX as i32) as u16
+    }
+}
+
+impl Default for ServoCalibration {
+    fn default() -> Self {
+        Self {
+            min_us: 1000,
+            center_us: 1500,
+            max_us: 2000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlConfig {
+    /// Throttle magnitude (per-mille) below which the motor is braked instead of driven
+    pub throttle_deadband: i16,
+    /// Per-tick change allowed while speeding up
+    pub throttle_accel_step: i16,
+    /// Per-tick change allowed while slowing down or reversing
+    pub throttle_decel_step: i16,
+    pub steering_step: i16,
+    /// Targets fall back to neutral when no control frame arrives for this long
+    pub link_timeout_ms: u64,
+    pub servo: ServoCalibration,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            throttle_deadband: 30,
+            throttle_accel_step: 20, // 0 -> full in 0.5 s at 100 Hz
+            throttle_decel_step: 50, // full -> 0 in 0.2 s
+            steering_step: 100,      // lock to lock in 0.2 s
+            link_timeout_ms: 250,
+            servo: ServoCalibration::default(),
+        }
+    }
+}
+
+/// Slew-rate limiter that moves towards a target by at most one step per update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ramp {
+    value: i16,
+}
+
+impl Ramp {
+    pub const fn new() -> Self {
+        Self { value: 0 }
+    }
+
+    pub fn value(&self) -> i16 {
+        self.value
+    }
+
+    pub fn update(&mut self, target: i16, up_step: i16, down_step: i16) -> i16 {
+        let target = target.clamp(-OUTPUT_MAX, OUTPUT_MAX);
+        let speeding_up = self.value == 0
+            || (target.signum() == self.value.signum() && target.abs() > self.value.abs());
+        let step = if speeding_up { up_step } else { down_step };
+
+        let delta = (target - self.value).clamp(-step, step);
+        self.value += delta;
+        self.value
+    }
+}
+
+impl Default for Ramp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outputs {
+    pub throttle: i16,
+    pub steering: i16,
+    pub drive: Drive,
+    pub servo_us: u16,
+}
+
+impl Outputs {
+    /// Outputs scaled back to the -100..=100 range used on the wire.
+    pub fn as_percent(&self) -> (i8, i8) {
+        ((self.throttle / 10) as i8, (self.steering / 10) as i8)
+    }
+}
+
+pub struct Controller {
+    config: ControlConfig,
+    target: ControlMessage,
+    last_control_ms: Option<u64>,
+    failsafe: FailsafeState,
+    throttle: Ramp,
+    steering: Ramp,
+}
+
+impl Controller {
+    pub fn new(config: ControlConfig) -> Self {
+        Self {
+            config,
+            target: ControlMessage {
+                steering: 0,
+                throttle: 0,
+            },
+            last_control_ms: None,
+            failsafe: FailsafeState::Disarmed,
+            throttle: Ramp::new(),
+            steering: Ramp::new(),
+        }
+    }
+
+    pub fn on_control(&mut self, ctrl: ControlMessage, now_ms: u64) {
+        // the radio already gates this, but never take a command while disarmed
+        if self.failsafe == FailsafeState::Disarmed {
+            return;
+        }
+        self.target = ctrl;
+        self.last_control_ms = Some(now_ms);
+    }
+
+    pub fn on_failsafe(&mut self, state: FailsafeState) {
+        self.failsafe = state;
+        if state != FailsafeState::Armed {
+            self.target = ControlMessage {
+                steering: 0,
+                throttle: 0,
+            };
+        }
+    }
+
+    pub fn failsafe(&self) -> FailsafeState {
+        self.failsafe
+    }
+
+    pub fn link_alive(&self, now_ms: u64) -> bool {
+        self.last_control_ms
+            .is_some_and(|t| now_ms.saturating_sub(t) <= self.config.link_timeout_ms)
+    }
+
+    /// Advances the ramps by one control period and returns what to drive.
+    pub fn tick(&mut self, now_ms: u64) -> Outputs {
+        let (throttle_target, steering_target) = if self.link_alive(now_ms) {
+            (
+                percent_to_output(self.target.throttle),
+                percent_to_output(self.target.steering),
+            )
+        } else {
+            (0, 0)
+        };
+
+        let cfg = &self.config;
+        if matches!(
+            self.failsafe,
+            FailsafeState::Brake | FailsafeState::Disarmed
+        ) {
+            // braking is immediate, only steering is eased back to centre
+            self.throttle = Ramp::new();
+        }
+        let throttle = self.throttle.update(
+            throttle_target,
+            cfg.throttle_accel_step,
+            cfg.throttle_decel_step,
+        );
+        let steering = self
+            .steering
+            .update(steering_target, cfg.steering_step, cfg.steering_step);
+
+        let drive = match self.failsafe {
+            FailsafeState::Armed => drive_for(throttle, cfg.throttle_deadband),
+            FailsafeState::Coast => Drive::Coast,
+            FailsafeState::Brake | FailsafeState::Disarmed => Drive::Brake,
+        };
+
+        Outputs {
+            throttle,
+            steering,
+            drive,
+            servo_us: cfg.servo.pulse_us(steering),
+        }
+    }
+}
+
+pub fn percent_to_output(percent: i8) -> i16 {
+    (percent as i16).clamp(-100, 100) * 10
+}
+
+pub fn drive_for(throttle: i16, deadband: i16) -> Drive {
+    let throttle = throttle.clamp(-OUTPUT_MAX, OUTPUT_MAX);
+    if throttle.abs() <= deadband {
+        Drive::Brake
+    } else if throttle > 0 {
+        Drive::Forward(throttle as u16)
+    } else {
+        Drive::Reverse(throttle.unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctrl(steering: i8, throttle: i8) -> ControlMessage {
+        ControlMessage { steering, throttle }
+    }
+
+    #[test]
+    fn throttle_maps_to_direction_and_duty() {
+        assert_eq!(drive_for(0, 30), Drive::Brake);
+        assert_eq!(drive_for(-30, 30), Drive::Brake);
+        assert_eq!(drive_for(500, 30), Drive::Forward(500));
+        assert_eq!(drive_for(-1000, 30), Drive::Reverse(1000));
+        assert_eq!(drive_for(i16::MIN, 30), Drive::Reverse(1000));
+        assert_eq!(percent_to_output(i8::MIN), -1000);
+    }
+
+    #[test]
+    fn servo_pulse_follows_calibration() {
+        let servo = ServoCalibration {
+            min_us: 1100,
+            center_us: 1450,
+            max_us: 1900,
+        };
+
+        assert_eq!(servo.pulse_us(0), 1450);
+        assert_eq!(servo.pulse_us(-OUTPUT_MAX), 1100);
+        assert_eq!(servo.pulse_us(OUTPUT_MAX), 1900);
+        assert_eq!(servo.pulse_us(500), 1675);
+    }
+
+    #[test]
+    fn ramp_accelerates_slowly_and_brakes_fast() {
+        let mut ramp = Ramp::new();
+
+        assert_eq!(ramp.update(1000, 20, 50), 20);
+        for _ in 0..100 {
+            ramp.update(1000, 20, 50);
+        }
+        assert_eq!(ramp.value(), 1000);
+
+        assert_eq!(ramp.update(0, 20, 50), 950);
+        // reversing decelerates through zero before accelerating the other way
+        let mut ticks = 1;
+        while ramp.value() > 0 {
+            ramp.update(-1000, 20, 50);
+            ticks += 1;
+        }
+        assert_eq!(ticks, 20);
+        assert_eq!(ramp.update(-1000, 20, 50), -20);
+    }
+
+    fn armed_controller(config: ControlConfig) -> Controller {
+        let mut controller = Controller::new(config);
+        controller.on_failsafe(FailsafeState::Armed);
+        controller
+    }
+
+    #[test]
+    fn controller_ramps_towards_command() {
+        let mut controller = armed_controller(ControlConfig::default());
+        controller.on_control(ctrl(100, 100), 0);
+
+        let first = controller.tick(10);
+        assert_eq!(first.throttle, 20);
+        assert_eq!(first.steering, 100);
+        assert_eq!(first.drive, Drive::Brake);
+
+        let mut out = first;
+        for t in 2..=50 {
+            controller.on_control(ctrl(100, 100), t * 10);
+            out = controller.tick(t * 10);
+        }
+        assert_eq!(out.drive, Drive::Forward(1000));
+        assert_eq!(out.servo_us, 2000);
+        assert_eq!(out.as_percent(), (100, 100));
+    }
+
+    #[test]
+    fn controller_returns_to_neutral_when_link_drops() {
+        let config = ControlConfig::default();
+        let mut controller = armed_controller(config);
+
+        assert!(!controller.link_alive(0));
+        assert_eq!(controller.tick(0).drive, Drive::Brake);
+
+        for t in 0..60 {
+            controller.on_control(ctrl(-100, 100), t * 10);
+            controller.tick(t * 10);
+        }
+        assert!(controller.tick(600).throttle > 0);
+
+        let lost_at = 590 + config.link_timeout_ms + 10;
+        let mut out = controller.tick(lost_at);
+        assert!(!controller.link_alive(lost_at));
+        for t in 1..=20 {
+            out = controller.tick(lost_at + t * 10);
+        }
+        assert_eq!(out.drive, Drive::Brake);
+        assert_eq!(out.steering, 0);
+        assert_eq!(out.servo_us, config.servo.center_us);
+    }
+
+    #[test]
+    fn failsafe_overrides_commands() {
+        let mut controller = Controller::new(ControlConfig::default());
+
+        controller.on_control(ctrl(0, 100), 0);
+        let out = controller.tick(10);
+        assert_eq!(out.drive, Drive::Brake);
+        assert_eq!(out.throttle, 0);
+
+        controller.on_failsafe(FailsafeState::Armed);
+        for t in 1..=20 {
+            controller.on_control(ctrl(50, 100), t * 10);
+            controller.tick(t * 10);
+        }
+
+        controller.on_failsafe(FailsafeState::Coast);
+        let out = controller.tick(210);
+        assert_eq!(out.drive, Drive::Coast);
+        assert!(out.throttle > 0, "coasting lets the ramp wind down");
+
+        controller.on_failsafe(FailsafeState::Brake);
+        let out = controller.tick(220);
+        assert_eq!(out.drive, Drive::Brake);
+        assert_eq!(out.throttle, 0);
+
+        controller.on_failsafe(FailsafeState::Disarmed);
+        controller.on_control(ctrl(0, 100), 230);
+        assert_eq!(controller.tick(230).drive, Drive::Brake);
+    }
+}
@@ -0,0 +1,238 @@
+//! Embassy firmware for the STM32F411 powertrain board.
+//!
+//! | Function         | Pin  | Peripheral |
+//! |------------------|------|------------|
+//! | UART TX to Pi    | PA9  | USART1     |
+//! | UART RX from Pi  | PA10 | USART1     |
+//! | L298N ENA (PWM)  | PA6  | TIM3 CH1   |
+//! | L298N IN1        | PB0  | GPIO       |
+//! | L298N IN2        | PB1  | GPIO       |
+//! | Steering servo   | PA5  | TIM2 CH1   |
+
+use defmt::{info, warn};
+use embassy_executor::Spawner;
+use embassy_stm32::{
+    bind_interrupts,
+    gpio::{Level, Output, OutputType, Speed},
+    peripherals::{self, TIM2, TIM3},
+    time::{hz, khz},
+    timer::{
+        low_level::CountingMode,
+        simple_pwm::{PwmPin, SimplePwm, SimplePwmChannel},
+    },
+    usart::{self, BufferedUart, BufferedUartRx},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_io_async::{Read, Write};
+use powertrain::control::{ControlConfig, Controller, Drive, OUTPUT_MAX};
+use static_cell::StaticCell;
+use telemetry::{
+    ControlMessage, FailsafeState,
+    frame::{self, Frame, FrameDecoder, MAX_FRAME_LEN, Packet, PowertrainStatus},
+};
+use {defmt_rtt as _, panic_probe as _};
+
+const UART_BAUD: u32 = 115_200;
+const CONTROL_PERIOD: Duration = Duration::from_millis(10);
+const STATUS_EVERY_TICKS: u32 = 10;
+const SERVO_PERIOD_US: u32 = 20_000;
+
+bind_interrupts!(struct Irqs {
+    USART1 => usart::BufferedInterruptHandler<peripherals::USART1>;
+});
+
+static CONTROL: Signal<CriticalSectionRawMutex, ControlMessage> = Signal::new();
+static FAILSAFE: Signal<CriticalSectionRawMutex, FailsafeState> = Signal::new();
+
+struct L298n<'d> {
+    enable: SimplePwmChannel<'d, TIM3>,
+    in1: Output<'d>,
+    in2: Output<'d>,
+}
+
+impl L298n<'_> {
+    fn apply(&mut self, drive: Drive) {
+        match drive {
+            Drive::Forward(duty) => {
+                self.in1.set_high();
+                self.in2.set_low();
+                self.set_duty(duty);
+            }
+            Drive::Reverse(duty) => {
+                self.in1.set_low();
+                self.in2.set_high();
+                self.set_duty(duty);
+            }
+            Drive::Brake => {
+                self.in1.set_low();
+                self.in2.set_low();
+                self.set_duty(OUTPUT_MAX as u16);
+            }
+            Drive::Coast => {
+                self.in1.set_low();
+                self.in2.set_low();
+                self.set_duty(0);
+            }
+        }
+    }
+
+    fn set_duty(&mut self, per_mille: u16) {
+        self.enable
+            .set_duty_cycle_fraction(per_mille.min(OUTPUT_MAX as u16), OUTPUT_MAX as u16);
+    }
+}
+
+struct Servo<'d> {
+    pwm: SimplePwmChannel<'d, TIM2>,
+}
+
+impl Servo<'_> {
+    fn set_pulse_us(&mut self, pulse_us: u16) {
+        let max = self.pwm.max_duty_cycle() as u32;
+        let duty = max * pulse_us as u32 / SERVO_PERIOD_US;
+        self.pwm.set_duty_cycle(duty as u16);
+    }
+}
+
+#[embassy_executor::task]
+async fn uart_rx_task(mut rx: BufferedUartRx<'static>) {
+    let mut decoder = FrameDecoder::new();
+    let mut buf = [0u8; 32];
+
+    loop {
+        let n = match rx.read(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("uart rx error: {}", e);
+                continue;
+            }
+        };
+
+        for &byte in &buf[..n] {
+            match decoder.push(byte) {
+                Some(Ok(Frame {
+                    packet: Packet::Control(ctrl),
+                    ..
+                })) => CONTROL.signal(ctrl),
+                Some(Ok(Frame {
+                    packet: Packet::Failsafe(state),
+                    ..
+                })) => FAILSAFE.signal(state),
+                Some(Ok(_)) => warn!("unexpected frame type from radio"),
+                Some(Err(_)) => warn!("dropped corrupt frame"),
+                None => {}
+            }
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_stm32::init(Default::default());
+    info!("powertrain starting");
+
+    static TX_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+    static RX_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+
+    let mut uart_config = usart::Config::default();
+    uart_config.baudrate = UART_BAUD;
+    let uart = BufferedUart::new(
+        p.USART1,
+        Irqs,
+        p.PA10,
+        p.PA9,
+        TX_BUF.init([0; 64]),
+        RX_BUF.init([0; 64]),
+        uart_config,
+    )
+    .expect("invalid USART1 config");
+    let (mut tx, rx) = uart.split();
+    spawner.must_spawn(uart_rx_task(rx));
+
+    let motor_pwm = SimplePwm::new(
+        p.TIM3,
+        Some(PwmPin::new_ch1(p.PA6, OutputType::PushPull)),
+        None,
+        None,
+        None,
+        khz(20),
+        CountingMode::EdgeAlignedUp,
+    );
+    let mut motor = L298n {
+        enable: motor_pwm.split().ch1,
+        in1: Output::new(p.PB0, Level::Low, Speed::Low),
+        in2: Output::new(p.PB1, Level::Low, Speed::Low),
+    };
+    motor.enable.enable();
+
+    let servo_pwm = SimplePwm::new(
+        p.TIM2,
+        Some(PwmPin::new_ch1(p.PA5, OutputType::PushPull)),
+        None,
+        None,
+        None,
+        hz(50),
+        CountingMode::EdgeAlignedUp,
+    );
+    let mut servo = Servo {
+        pwm: servo_pwm.split().ch1,
+    };
+    servo.pwm.enable();
+
+    let mut controller = Controller::new(ControlConfig::default());
+    let mut ticker = Ticker::every(CONTROL_PERIOD);
+    let mut link_alive = false;
+    let mut tick: u32 = 0;
+    let mut status_seq: u8 = 0;
+
+    loop {
+        ticker.next().await;
+        let now_ms = Instant::now().as_millis();
+
+        if let Some(state) = FAILSAFE.try_take()
+            && state != controller.failsafe()
+        {
+            info!("failsafe: {}", defmt::Debug2Format(&state));
+            controller.on_failsafe(state);
+        }
+        if let Some(ctrl) = CONTROL.try_take() {
+            controller.on_control(ctrl, now_ms);
+        }
+
+        if controller.link_alive(now_ms) != link_alive {
+            link_alive = !link_alive;
+            if link_alive {
+                info!("control link up");
+            } else {
+                warn!("control link lost, ramping to neutral");
+            }
+        }
+
+        let outputs = controller.tick(now_ms);
+        motor.apply(outputs.drive);
+        servo.set_pulse_us(outputs.servo_us);
+
+        tick = tick.wrapping_add(1);
+        if tick.is_multiple_of(STATUS_EVERY_TICKS) {
+            let (motor_output, steering_output) = outputs.as_percent();
+            let status = Frame {
+                seq: status_seq,
+                packet: Packet::Status(PowertrainStatus {
+                    battery_mv: 0, // no battery sense divider on this board revision yet
+                    motor_output,
+                    steering_output,
+                    faults: 0,
+                }),
+            };
+            status_seq = status_seq.wrapping_add(1);
+
+            let mut buf = [0u8; MAX_FRAME_LEN];
+            if let Ok(len) = frame::encode(&status, &mut buf)
+                && tx.write_all(&buf[..len]).await.is_err()
+            {
+                warn!("uart tx error");
+            }
+        }
+    }
+}
@@ -1,3 +1,10 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+#[cfg(target_os = "none")]
+mod firmware;
+
+#[cfg(not(target_os = "none"))]
 fn main() {
-    println!("Hello, world!");
+    eprintln!("powertrain is STM32 firmware, build it with `cargo build-powertrain`");
 }
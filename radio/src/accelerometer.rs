@@ -1,14 +1,66 @@
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use adxl345_driver::i2c::Device as AdxlDevice;
 use adxl345_driver::{Adxl345Reader, Adxl345Writer};
 use anyhow::{Context, Result};
-use log::{error, trace};
+use log::{error, info, trace};
+use telemetry::Vector3;
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 
+use crate::hal::ImuSource;
+
 const SCALE_MULTIPLIER: f64 = 0.004; // 4 mg per LSB -> g
 const EARTH_GRAVITY_MS2: f64 = 9.80665;
+const CALIBRATION_SAMPLES: u32 = 20;
+const NOISE_FLOOR_MS2: f64 = 0.15;
+const SPEED_DECAY_SECS: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ImuReading {
+    pub acceleration: Vector3,
+    pub speed_estimate: f32,
+}
+
+/// Speed from integrating forward (x) acceleration.
+///
+/// The first samples are averaged into a bias, so the car must be at rest at power-on.
+/// There is no absolute speed reference on board, so the estimate leaks back towards zero
+/// to keep integration drift bounded.
+#[derive(Debug, Default)]
+pub struct SpeedEstimator {
+    bias_sum: f64,
+    bias_samples: u32,
+    speed: f64,
+}
+
+impl SpeedEstimator {
+    pub fn update(&mut self, forward_ms2: f64, dt: Duration) -> f64 {
+        if self.bias_samples < CALIBRATION_SAMPLES {
+            self.bias_sum += forward_ms2;
+            self.bias_samples += 1;
+            if self.bias_samples == CALIBRATION_SAMPLES {
+                info!(
+                    "Accelerometer forward bias calibrated: {:.3} m/s^2",
+                    self.bias_sum / CALIBRATION_SAMPLES as f64
+                );
+            }
+            return 0.0;
+        }
+
+        let accel = forward_ms2 - self.bias_sum / CALIBRATION_SAMPLES as f64;
+        let accel = if accel.abs() < NOISE_FLOOR_MS2 {
+            0.0
+        } else {
+            accel
+        };
+        let dt = dt.as_secs_f64();
+
+        self.speed = self.speed * (-dt / SPEED_DECAY_SECS).exp() + accel * dt;
+        self.speed
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct RawSample {
@@ -51,33 +103,63 @@ impl Accelerometer {
 
         Ok((to_ms2(r.x), to_ms2(r.y), to_ms2(r.z)))
     }
+}
+
+impl ImuSource for Accelerometer {
+    fn read(&mut self) -> Result<Vector3> {
+        let (x, y, z) = self.read_ms2()?;
+        Ok(Vector3 {
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+        })
+    }
 
-    pub fn shutdown(&mut self) -> Result<()> {
+    fn shutdown(&mut self) -> Result<()> {
         self.inner
             .set_power_control(0)
             .context("Failed to turn off measurement mode")?;
 
         Ok(())
     }
+}
 
-    pub fn start_poller(self, cancel_token: CancellationToken, interval_ms: u64) -> JoinHandle<()> {
-        thread::spawn(move || {
-            let mut dev = self;
-            while !cancel_token.is_cancelled() {
-                match dev.read_ms2() {
-                    Ok((x, y, z)) => {
-                        trace!("accel m/s^2: x={:.3} y={:.3} z={:.3}", x, y, z);
-                        // maybe in the future we can calculate the velocity
-                    }
-                    Err(e) => {
-                        error!("accelerometer read error: {e}");
-                    }
+/// Polls `source` every `interval_ms` on its own thread and publishes readings with the
+/// integrated speed estimate.
+pub fn start_poller(
+    mut source: Box<dyn ImuSource>,
+    cancel_token: CancellationToken,
+    interval_ms: u64,
+    reading_tx: watch::Sender<Option<ImuReading>>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut speed = SpeedEstimator::default();
+        let mut last_sample = Instant::now();
+
+        while !cancel_token.is_cancelled() {
+            match source.read() {
+                Ok(acceleration) => {
+                    let now = Instant::now();
+                    let speed_estimate = speed.update(acceleration.x as f64, now - last_sample);
+                    last_sample = now;
+
+                    trace!(
+                        "accel m/s^2: x={:.3} y={:.3} z={:.3}, speed {:.2} m/s",
+                        acceleration.x, acceleration.y, acceleration.z, speed_estimate
+                    );
+                    reading_tx.send_replace(Some(ImuReading {
+                        acceleration,
+                        speed_estimate: speed_estimate as f32,
+                    }));
+                }
+                Err(e) => {
+                    error!("accelerometer read error: {e}");
                 }
-
-                thread::sleep(Duration::from_millis(interval_ms));
             }
 
-            let _ = dev.shutdown();
-        })
-    }
+            thread::sleep(Duration::from_millis(interval_ms));
+        }
+
+        let _ = source.shutdown();
+    })
 }
@@ -0,0 +1,624 @@
+//! Adaptive video quality. Once a second the link is checked for signs of video crowding out
+//! control: stream clients skipping frames because they can't take them fast enough, and the
+//! driver's control packets going missing or, while the driver is driving, arriving late.
+//! Sustained congestion steps the camera down the quality ladder and a link that has stayed
+//! healthy for longer steps it back up, with a dead band between the two thresholds so the
+//! quality doesn't flap.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use log::{info, trace};
+use telemetry::{CameraSettings, ControlMessage, LinkQuality, VideoProfile, VideoQuality};
+use tokio::sync::{
+    broadcast::{self, error::TryRecvError},
+    watch::{self, error::RecvError},
+};
+
+use crate::{
+    camera::{CameraCapture, settings},
+    config::AdaptiveVideoSettings,
+};
+
+const CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// What stream clients were sent, shared by all of them.
+#[derive(Clone, Default)]
+pub struct StreamStats {
+    counters: Arc<Mutex<StreamCounters>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct StreamCounters {
+    clients: usize,
+    frames: u64,
+    bytes: u64,
+}
+
+/// A connected stream client, counted in `StreamStats` until this is dropped.
+pub struct ClientStats {
+    stats: StreamStats,
+}
+
+impl StreamStats {
+    pub fn client(&self) -> ClientStats {
+        self.counters.lock().unwrap().clients += 1;
+        ClientStats {
+            stats: self.clone(),
+        }
+    }
+
+    /// The counters since the last call, and the clients connected now.
+    fn take(&self) -> StreamCounters {
+        let mut counters = self.counters.lock().unwrap();
+        let taken = *counters;
+        counters.frames = 0;
+        counters.bytes = 0;
+        taken
+    }
+}
+
+impl ClientStats {
+    pub fn sent(&self, bytes: usize) {
+        let mut counters = self.stats.counters.lock().unwrap();
+        counters.frames += 1;
+        counters.bytes += bytes as u64;
+    }
+}
+
+impl Drop for ClientStats {
+    fn drop(&mut self) {
+        self.stats.counters.lock().unwrap().clients -= 1;
+    }
+}
+
+/// What the driver's link looks like from the radio.
+pub struct DriverLink {
+    link_rx: watch::Receiver<LinkQuality>,
+    control_rx: broadcast::Receiver<ControlMessage>,
+    /// The latest control packet
+    control: ControlMessage,
+}
+
+impl DriverLink {
+    /// Follows the link quality in `link_rx` and the driver's input in `control_rx`.
+    pub fn new(
+        link_rx: watch::Receiver<LinkQuality>,
+        control_rx: broadcast::Receiver<ControlMessage>,
+    ) -> Self {
+        Self {
+            link_rx,
+            control_rx,
+            control: ControlMessage {
+                steering: 0,
+                throttle: 0,
+            },
+        }
+    }
+
+    /// The link quality from telemetry since the last call, of which there is none without
+    /// clients.
+    fn latest(&mut self) -> Option<LinkQuality> {
+        self.link_rx
+            .has_changed()
+            .unwrap_or(false)
+            .then(|| *self.link_rx.borrow_and_update())
+    }
+
+    /// Whether control packets should be flowing: the throttle is open or the driver has
+    /// touched the stick since the last call. An idle cockpit only sends the odd keepalive, so
+    /// the age of its last packet says nothing about the link.
+    fn expecting_control(&mut self) -> bool {
+        let mut input = false;
+        loop {
+            match self.control_rx.try_recv() {
+                Ok(ctrl) => {
+                    input |= ctrl != self.control || ctrl.steering != 0;
+                    self.control = ctrl;
+                }
+                // more than the queue holds is plenty of input
+                Err(TryRecvError::Lagged(_)) => input = true,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+        input || self.control.throttle != 0
+    }
+}
+
+/// The camera settings asked for: the configured ones, with the JPEG quality capped by the
+/// lowest of the caps set by WebSocket viewers. A cap holds only while its viewer is connected
+/// and is never saved.
+#[derive(Clone)]
+pub struct RequestedCamera {
+    configured_rx: watch::Receiver<CameraSettings>,
+    caps: Arc<watch::Sender<BTreeMap<u64, u8>>>,
+    caps_rx: watch::Receiver<BTreeMap<u64, u8>>,
+    next_cap: Arc<AtomicU64>,
+}
+
+/// A viewer's quality cap, lifted when this is dropped.
+pub struct QualityCap {
+    caps: Arc<watch::Sender<BTreeMap<u64, u8>>>,
+    id: u64,
+}
+
+impl RequestedCamera {
+    pub fn new(configured_rx: watch::Receiver<CameraSettings>) -> Self {
+        let (caps, caps_rx) = watch::channel(BTreeMap::new());
+        Self {
+            configured_rx,
+            caps: Arc::new(caps),
+            caps_rx,
+            next_cap: Arc::default(),
+        }
+    }
+
+    /// A cap for a new viewer, which limits nothing until it is set.
+    pub fn cap(&self) -> QualityCap {
+        QualityCap {
+            caps: self.caps.clone(),
+            id: self.next_cap.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// The effective settings asked for, marking them seen.
+    pub fn settings(&mut self) -> CameraSettings {
+        let mut settings = self.configured_rx.borrow_and_update().clone();
+        if let Some(&cap) = self.caps_rx.borrow_and_update().values().min() {
+            settings.quality = settings.quality.min(cap);
+        }
+        settings::effective(&settings)
+    }
+
+    /// Waits for the configured settings or the caps to change.
+    pub async fn changed(&mut self) -> Result<(), RecvError> {
+        tokio::select! {
+            changed = self.configured_rx.changed() => changed,
+            changed = self.caps_rx.changed() => changed,
+        }
+    }
+}
+
+impl QualityCap {
+    pub fn set(&self, quality: u8) {
+        self.caps.send_modify(|caps| {
+            caps.insert(self.id, quality);
+        });
+    }
+}
+
+impl Drop for QualityCap {
+    fn drop(&mut self) {
+        self.caps
+            .send_if_modified(|caps| caps.remove(&self.id).is_some());
+    }
+}
+
+/// Sits between the requested camera settings and the capture, capping them to the current
+/// step of the ladder.
+pub struct AdaptiveQuality {
+    settings: AdaptiveVideoSettings,
+    stream_stats: StreamStats,
+    camera_capture: CameraCapture,
+    driver: DriverLink,
+    requested: RequestedCamera,
+    camera_tx: watch::Sender<CameraSettings>,
+    quality_tx: watch::Sender<Option<VideoQuality>>,
+    /// Frames the camera had captured at the last check
+    captured: u64,
+    /// 0 for the configured settings, `n` for `ladder[n - 1]`
+    level: usize,
+    congested_checks: u32,
+    healthy_checks: u32,
+}
+
+impl AdaptiveQuality {
+    /// Adapts `requested` to the link, sending the result to `camera_tx`.
+    pub fn new(
+        settings: AdaptiveVideoSettings,
+        stream_stats: StreamStats,
+        camera_capture: CameraCapture,
+        driver: DriverLink,
+        requested: RequestedCamera,
+        camera_tx: watch::Sender<CameraSettings>,
+        quality_tx: watch::Sender<Option<VideoQuality>>,
+    ) -> Self {
+        Self {
+            settings,
+            stream_stats,
+            camera_capture,
+            driver,
+            requested,
+            camera_tx,
+            quality_tx,
+            captured: 0,
+            level: 0,
+            congested_checks: 0,
+            healthy_checks: 0,
+        }
+    }
+
+    pub async fn run(mut self) {
+        if self.settings.enabled {
+            info!(
+                "Adaptive video quality over {} profiles",
+                self.settings.ladder.len()
+            );
+        }
+
+        let mut checks = tokio::time::interval(CHECK_PERIOD);
+        loop {
+            tokio::select! {
+                changed = self.requested.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                _ = checks.tick(), if self.settings.enabled => self.check(),
+            }
+            self.apply();
+        }
+    }
+
+    fn check(&mut self) {
+        // every client should have had every frame, a stalled one included
+        let stream = self.stream_stats.take();
+        let captured = self.camera_capture.frames_captured();
+        let offered = captured.saturating_sub(self.captured) * stream.clients as u64;
+        self.captured = captured;
+        let skipped = if offered > 0 {
+            1.0 - stream.frames.min(offered) as f32 / offered as f32
+        } else {
+            0.0
+        };
+
+        // only judge control by telemetry from this period
+        let link = self.driver.latest();
+        let control_loss = link.map_or(0.0, |link| link.control_loss);
+        let control_late = self.driver.expecting_control()
+            && link
+                .and_then(|link| link.control_age_ms)
+                .is_some_and(|age| age > self.settings.max_control_age_ms);
+
+        let s = &self.settings;
+        let congested =
+            skipped > s.max_skipped || control_loss > s.max_control_loss || control_late;
+        let healthy = skipped <= s.max_skipped / 2.0 && control_loss <= s.max_control_loss / 2.0;
+
+        if congested {
+            self.healthy_checks = 0;
+            self.congested_checks += 1;
+        } else if healthy {
+            self.congested_checks = 0;
+            self.healthy_checks += 1;
+        } else {
+            self.congested_checks = 0;
+            self.healthy_checks = 0;
+        }
+
+        let summary = format!(
+            "{:.0}% of frames skipped, {} kB/s sent, {:.0}% control loss",
+            skipped * 100.0,
+            stream.bytes * 1000 / CHECK_PERIOD.as_millis() as u64 / 1024,
+            control_loss * 100.0
+        );
+        trace!("Video link: {summary}");
+
+        if self.congested_checks >= s.step_down_after_secs && self.level < s.ladder.len() {
+            self.level += 1;
+            self.congested_checks = 0;
+            info!(
+                "Video congested ({summary}), stepping down to level {}",
+                self.level
+            );
+        } else if self.healthy_checks >= s.step_up_after_secs && self.level > 0 {
+            self.level -= 1;
+            self.healthy_checks = 0;
+            info!("Video link healthy, stepping up to level {}", self.level);
+        }
+    }
+
+    /// Sends the requested settings capped to the current level.
+    fn apply(&mut self) {
+        let requested = self.requested.settings();
+        let camera = match self.level.checked_sub(1) {
+            Some(step) if self.settings.enabled => cap(&requested, &self.settings.ladder[step]),
+            _ => requested,
+        };
+
+        let quality = (self.settings.enabled && self.camera_capture.is_running().unwrap_or(false))
+            .then_some(VideoQuality {
+                level: self.level as u8,
+                profile: VideoProfile {
+                    width: camera.width,
+                    height: camera.height,
+                    fps: camera.fps,
+                    quality: camera.quality,
+                },
+            });
+        self.quality_tx.send_if_modified(|current| {
+            let changed = *current != quality;
+            *current = quality;
+            changed
+        });
+
+        self.camera_tx.send_if_modified(|current| {
+            let changed = *current != camera;
+            *current = camera;
+            changed
+        });
+    }
+}
+
+/// `requested` scaled down to fit `profile`, keeping its aspect ratio.
+fn cap(requested: &CameraSettings, profile: &VideoProfile) -> CameraSettings {
+    let scale = (f32::from(profile.width) / f32::from(requested.width))
+        .min(f32::from(profile.height) / f32::from(requested.height))
+        .min(1.0);
+
+    settings::effective(&CameraSettings {
+        width: (f32::from(requested.width) * scale) as u16,
+        height: (f32::from(requested.height) * scale) as u16,
+        fps: requested.fps.min(profile.fps),
+        quality: requested.quality.min(profile.quality),
+        ..requested.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CameraBackend;
+
+    fn ctrl(steering: i8, throttle: i8) -> ControlMessage {
+        ControlMessage { steering, throttle }
+    }
+
+    /// A driver whose last control packet is always `age_ms` old.
+    fn late_link(age_ms: u32) -> LinkQuality {
+        LinkQuality {
+            control_age_ms: Some(age_ms),
+            ..LinkQuality::default()
+        }
+    }
+
+    fn configured(quality: u8) -> CameraSettings {
+        CameraSettings {
+            quality,
+            ..CameraSettings::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn lowest_viewer_cap_holds_while_connected() {
+        let (configured_tx, configured_rx) = watch::channel(configured(80));
+        let mut requested = RequestedCamera::new(configured_rx);
+        let mut adaptive_side = requested.clone();
+        assert_eq!(adaptive_side.settings().quality, 80);
+
+        let first = requested.cap();
+        let second = requested.cap();
+        first.set(60);
+        second.set(40);
+        adaptive_side.changed().await.unwrap();
+        assert_eq!(adaptive_side.settings().quality, 40);
+
+        // a cap above the configured quality doesn't raise it
+        second.set(95);
+        assert_eq!(requested.settings().quality, 60);
+        drop(first);
+        assert_eq!(requested.settings().quality, 80);
+
+        // the cap applies over whatever is configured later on
+        second.set(50);
+        configured_tx.send_replace(configured(70));
+        assert_eq!(requested.settings().quality, 50);
+        drop(second);
+        adaptive_side.changed().await.unwrap();
+        assert_eq!(adaptive_side.settings(), configured(70));
+    }
+
+    #[test]
+    fn caps_are_clamped_like_requested_settings() {
+        let (_configured_tx, configured_rx) = watch::channel(configured(80));
+        let mut requested = RequestedCamera::new(configured_rx);
+        let cap = requested.cap();
+        cap.set(0);
+        assert_eq!(requested.settings().quality, 1);
+    }
+
+    #[test]
+    fn control_is_expected_while_the_driver_drives() {
+        let (_link_tx, link_rx) = watch::channel(LinkQuality::default());
+        let (control_tx, control_rx) = broadcast::channel(4);
+        let mut driver = DriverLink::new(link_rx, control_rx);
+        assert!(!driver.expecting_control());
+
+        // keepalives from an idle cockpit
+        control_tx.send(ctrl(0, 0)).unwrap();
+        assert!(!driver.expecting_control());
+
+        // steering alone counts until the next check
+        control_tx.send(ctrl(-40, 0)).unwrap();
+        control_tx.send(ctrl(0, 0)).unwrap();
+        assert!(driver.expecting_control());
+        assert!(!driver.expecting_control());
+
+        // an open throttle keeps control expected with nothing new coming in, and letting go
+        // of it is input too
+        control_tx.send(ctrl(0, 30)).unwrap();
+        assert!(driver.expecting_control());
+        assert!(driver.expecting_control());
+        control_tx.send(ctrl(0, 0)).unwrap();
+        assert!(driver.expecting_control());
+        assert!(!driver.expecting_control());
+
+        // more than the queue holds
+        for _ in 0..10 {
+            control_tx.send(ctrl(0, 0)).unwrap();
+        }
+        assert!(driver.expecting_control());
+    }
+
+    /// Adaptive quality with the default ladder over a camera that never runs, and the
+    /// senders for the driver's link and control.
+    fn adaptive() -> (
+        AdaptiveQuality,
+        watch::Sender<LinkQuality>,
+        broadcast::Sender<ControlMessage>,
+    ) {
+        let (frame_buffer, _) = watch::channel(None);
+        let (h264_buffer, _) = broadcast::channel(1);
+        let (_, configured_rx) = watch::channel(CameraSettings::default());
+        let requested = RequestedCamera::new(configured_rx);
+        let (camera_tx, camera_rx) = watch::channel(CameraSettings::default());
+        let camera_capture = CameraCapture::new(
+            frame_buffer,
+            h264_buffer,
+            CameraBackend::TestPattern,
+            camera_rx,
+        );
+        let (link_tx, link_rx) = watch::channel(LinkQuality::default());
+        let (control_tx, control_rx) = broadcast::channel(16);
+        let (quality_tx, _) = watch::channel(None);
+        let adaptive = AdaptiveQuality::new(
+            AdaptiveVideoSettings::default(),
+            StreamStats::default(),
+            camera_capture,
+            DriverLink::new(link_rx, control_rx),
+            requested,
+            camera_tx,
+            quality_tx,
+        );
+        (adaptive, link_tx, control_tx)
+    }
+
+    #[test]
+    fn quality_cap_limits_nothing_until_set() {
+        let (_configured_tx, configured_rx) = watch::channel(configured(80));
+        let mut requested = RequestedCamera::new(configured_rx);
+        let cap = requested.cap();
+        assert_eq!(requested.settings().quality, 80);
+
+        cap.set(30);
+        assert_eq!(requested.settings().quality, 30);
+        // a viewer can raise its own cap again
+        cap.set(70);
+        assert_eq!(requested.settings().quality, 70);
+
+        // each viewer gets its own cap, and the lowest wins
+        let other = requested.cap();
+        other.set(50);
+        assert_eq!(requested.settings().quality, 50);
+        drop(other);
+        assert_eq!(requested.settings().quality, 70);
+
+        drop(cap);
+        assert_eq!(requested.settings().quality, 80);
+        assert!(requested.caps_rx.borrow().is_empty());
+    }
+
+    #[test]
+    fn healthy_link_steps_back_up_after_the_hold() {
+        let (mut adaptive, link_tx, _control_tx) = adaptive();
+        let step_up_after = adaptive.settings.step_up_after_secs;
+        adaptive.level = 2;
+
+        for _ in 0..step_up_after - 1 {
+            adaptive.check();
+        }
+        assert_eq!(adaptive.level, 2);
+        adaptive.check();
+        assert_eq!(adaptive.level, 1);
+        adaptive.apply();
+        let ladder = &adaptive.settings.ladder;
+        assert_eq!(
+            *adaptive.camera_tx.borrow(),
+            cap(&CameraSettings::default(), &ladder[0])
+        );
+
+        // loss in the dead band between healthy and congested restarts the hold
+        for _ in 0..step_up_after - 1 {
+            adaptive.check();
+        }
+        link_tx.send_replace(LinkQuality {
+            control_loss: adaptive.settings.max_control_loss * 0.75,
+            ..LinkQuality::default()
+        });
+        adaptive.check();
+        for _ in 0..step_up_after - 1 {
+            adaptive.check();
+        }
+        assert_eq!(adaptive.level, 1);
+        adaptive.check();
+        assert_eq!(adaptive.level, 0);
+        adaptive.apply();
+        assert_eq!(*adaptive.camera_tx.borrow(), CameraSettings::default());
+
+        // nothing above the configured settings
+        for _ in 0..step_up_after {
+            adaptive.check();
+        }
+        assert_eq!(adaptive.level, 0);
+    }
+
+    #[test]
+    fn frames_skipped_by_any_client_count() {
+        let (mut adaptive, _link_tx, _control_tx) = adaptive();
+        let step_down_after = adaptive.settings.step_down_after_secs;
+        let keeping_up = adaptive.stream_stats.client();
+        let falling_behind = adaptive.stream_stats.client();
+
+        // 20 frames offered to the two clients, and 10 sent
+        for _ in 0..step_down_after {
+            adaptive.camera_capture.count_captured(10);
+            for _ in 0..10 {
+                keeping_up.sent(1_000);
+            }
+            adaptive.check();
+        }
+        assert_eq!(adaptive.level, 1);
+
+        // a client that disconnected isn't owed frames, and the counts start over each check
+        drop(falling_behind);
+        adaptive.camera_capture.count_captured(10);
+        for _ in 0..10 {
+            keeping_up.sent(1_000);
+        }
+        adaptive.check();
+        assert_eq!((adaptive.congested_checks, adaptive.healthy_checks), (0, 1));
+
+        // nor are clients while the camera captures nothing
+        let _connecting = adaptive.stream_stats.client();
+        adaptive.check();
+        assert_eq!((adaptive.congested_checks, adaptive.healthy_checks), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn late_control_only_steps_down_while_driving() {
+        let (mut adaptive, link_tx, control_tx) = adaptive();
+        let step_down_after = adaptive.settings.step_down_after_secs;
+
+        // an idle driver's keepalives are far apart, which isn't congestion
+        for _ in 0..step_down_after * 2 {
+            control_tx.send(ctrl(0, 0)).unwrap();
+            link_tx.send_replace(late_link(2_000));
+            adaptive.check();
+        }
+        assert_eq!(adaptive.level, 0);
+
+        // the same gaps with the throttle open are
+        control_tx.send(ctrl(0, 50)).unwrap();
+        for _ in 0..step_down_after {
+            link_tx.send_replace(late_link(2_000));
+            adaptive.check();
+        }
+        assert_eq!(adaptive.level, 1);
+    }
+}
@@ -0,0 +1,282 @@
+//! Minimal MJPEG AVI writer. Frames go into a single `movi` list and the `idx1` index written
+//! by `finish` makes the file seekable in ordinary players.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+/// Assumed until `finish` knows the real rate
+const NOMINAL_FPS: f64 = 30.0;
+/// Where the header fields patched by `finish` live
+const RIFF_SIZE: u64 = 4;
+const AVIH_MICROS_PER_FRAME: u64 = 32;
+const AVIH_MAX_BYTES_PER_SEC: u64 = 36;
+const AVIH_TOTAL_FRAMES: u64 = 48;
+const AVIH_SUGGESTED_BUFFER: u64 = 60;
+const STRH_RATE: u64 = 132;
+const STRH_LENGTH: u64 = 140;
+const STRH_SUGGESTED_BUFFER: u64 = 144;
+const MOVI_SIZE: u64 = 216;
+/// The `movi` fourcc, which index offsets count from
+const MOVI_START: u64 = 220;
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+/// `dwRate` is in frames per this many seconds
+const RATE_SCALE: u32 = 1000;
+
+pub struct AviWriter {
+    file: BufWriter<File>,
+    /// Offset from `MOVI_START` and length of each frame chunk
+    index: Vec<(u32, u32)>,
+    len: u64,
+    largest_frame: u32,
+}
+
+impl AviWriter {
+    pub fn create(path: &Path, width: u16, height: u16) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            index: Vec::new(),
+            len: 0,
+            largest_frame: 0,
+        };
+        writer.write_header(u32::from(width), u32::from(height))?;
+        Ok(writer)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.len
+    }
+
+    pub fn write_frame(&mut self, jpeg: &[u8]) -> Result<()> {
+        let size = u32::try_from(jpeg.len()).context("Frame too large for AVI")?;
+        let offset = u32::try_from(self.len - MOVI_START).context("AVI segment too large")?;
+
+        self.put(b"00dc")?;
+        self.put_u32(size)?;
+        self.put(jpeg)?;
+        if size % 2 == 1 {
+            // chunks are word aligned
+            self.put(&[0])?;
+        }
+
+        self.index.push((offset, size));
+        self.largest_frame = self.largest_frame.max(size);
+        Ok(())
+    }
+
+    /// Writes the index and fills in the header. `duration` is the time from the first frame
+    /// to the last, from which the frame rate is worked out.
+    pub fn finish(mut self, duration: Duration) -> Result<()> {
+        let frames = self.index.len() as u32;
+        let fps = if frames > 1 && !duration.is_zero() {
+            f64::from(frames - 1) / duration.as_secs_f64()
+        } else {
+            NOMINAL_FPS
+        };
+        let movi_size = (self.len - MOVI_START) as u32;
+
+        self.put(b"idx1")?;
+        self.put_u32(frames * 16)?;
+        for (offset, size) in std::mem::take(&mut self.index) {
+            self.put(b"00dc")?;
+            self.put_u32(AVIIF_KEYFRAME)?;
+            self.put_u32(offset)?;
+            self.put_u32(size)?;
+        }
+
+        let riff_size = (self.len - 8) as u32;
+        let bytes_per_sec = (f64::from(self.largest_frame) * fps) as u32;
+        self.patch(RIFF_SIZE, riff_size)?;
+        self.patch(AVIH_MICROS_PER_FRAME, (1_000_000.0 / fps) as u32)?;
+        self.patch(AVIH_MAX_BYTES_PER_SEC, bytes_per_sec)?;
+        self.patch(AVIH_TOTAL_FRAMES, frames)?;
+        self.patch(AVIH_SUGGESTED_BUFFER, self.largest_frame)?;
+        self.patch(STRH_RATE, (fps * f64::from(RATE_SCALE)).round() as u32)?;
+        self.patch(STRH_LENGTH, frames)?;
+        self.patch(STRH_SUGGESTED_BUFFER, self.largest_frame)?;
+        self.patch(MOVI_SIZE, movi_size)?;
+
+        self.file.flush().context("Failed to write recording")?;
+        Ok(())
+    }
+
+    fn write_header(&mut self, width: u32, height: u32) -> Result<()> {
+        self.put(b"RIFF")?;
+        self.put_u32(0)?;
+        self.put(b"AVI ")?;
+
+        self.put(b"LIST")?;
+        self.put_u32(192)?;
+        self.put(b"hdrl")?;
+
+        self.put(b"avih")?;
+        self.put_u32(56)?;
+        for value in [
+            0,
+            0,
+            0,
+            AVIF_HASINDEX,
+            0,
+            0,
+            1,
+            0,
+            width,
+            height,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            self.put_u32(value)?;
+        }
+
+        self.put(b"LIST")?;
+        self.put_u32(116)?;
+        self.put(b"strl")?;
+
+        self.put(b"strh")?;
+        self.put_u32(56)?;
+        self.put(b"vidsMJPG")?;
+        // flags, priority and language, initial frames, scale, rate, start, length,
+        // suggested buffer size, default quality, sample size
+        for value in [0, 0, 0, RATE_SCALE, 0, 0, 0, 0, u32::MAX, 0] {
+            self.put_u32(value)?;
+        }
+        // frame rectangle
+        self.put_u32(0)?;
+        self.put_u32((height << 16) | width)?;
+
+        // BITMAPINFOHEADER
+        self.put(b"strf")?;
+        self.put_u32(40)?;
+        for value in [40, width, height] {
+            self.put_u32(value)?;
+        }
+        // one plane of 24 bit colour
+        self.put_u32((24 << 16) | 1)?;
+        self.put(b"MJPG")?;
+        for value in [width * height * 3, 0, 0, 0, 0] {
+            self.put_u32(value)?;
+        }
+
+        self.put(b"LIST")?;
+        self.put_u32(0)?;
+        self.put(b"movi")?;
+        debug_assert_eq!(self.len, MOVI_START + 4);
+        Ok(())
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        self.file
+            .write_all(bytes)
+            .context("Failed to write recording")?;
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn put_u32(&mut self, value: u32) -> Result<()> {
+        self.put(&value.to_le_bytes())
+    }
+
+    fn patch(&mut self, offset: u64, value: u32) -> Result<()> {
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.write_all(&value.to_le_bytes()))
+            .context("Failed to finish recording")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(data: &[u8], offset: u64) -> u32 {
+        let offset = offset as usize;
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("radio-avi-{name}-{}.avi", std::process::id()))
+    }
+
+    #[test]
+    fn frames_are_indexed_and_header_patched() {
+        let path = temp_file("frames");
+        let frames: [&[u8]; 3] = [b"first", b"second", b"odd"];
+
+        let mut avi = AviWriter::create(&path, 640, 480).unwrap();
+        for frame in frames {
+            avi.write_frame(frame).unwrap();
+        }
+        // three frames over 100 ms, 20fps
+        avi.finish(Duration::from_millis(100)).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(&data[8..12], b"AVI ");
+        assert_eq!(u32_at(&data, RIFF_SIZE) as usize, data.len() - 8);
+        assert_eq!(u32_at(&data, AVIH_MICROS_PER_FRAME), 50_000);
+        assert_eq!(u32_at(&data, AVIH_TOTAL_FRAMES), 3);
+        assert_eq!(u32_at(&data, AVIH_SUGGESTED_BUFFER), 6);
+        assert_eq!(u32_at(&data, STRH_RATE), 20 * RATE_SCALE);
+        assert_eq!(u32_at(&data, STRH_LENGTH), 3);
+        assert_eq!((u32_at(&data, 64), u32_at(&data, 68)), (640, 480));
+        assert_eq!(&data[MOVI_START as usize..MOVI_START as usize + 4], b"movi");
+
+        // the index follows the movi list and points at each frame's chunk
+        let idx1 = (MOVI_START + u64::from(u32_at(&data, MOVI_SIZE))) as usize;
+        assert_eq!(&data[idx1..idx1 + 4], b"idx1");
+        assert_eq!(u32_at(&data, idx1 as u64 + 4), 3 * 16);
+        for (i, frame) in frames.iter().enumerate() {
+            let entry = (idx1 + 8 + i * 16) as u64;
+            assert_eq!(&data[entry as usize..entry as usize + 4], b"00dc");
+            assert_eq!(u32_at(&data, entry + 4), AVIIF_KEYFRAME);
+            let chunk = MOVI_START + u64::from(u32_at(&data, entry + 8));
+            assert_eq!(u32_at(&data, entry + 12) as usize, frame.len());
+
+            let chunk = chunk as usize;
+            assert_eq!(&data[chunk..chunk + 4], b"00dc");
+            assert_eq!(u32_at(&data, chunk as u64 + 4) as usize, frame.len());
+            assert_eq!(&data[chunk + 8..chunk + 8 + frame.len()], *frame);
+        }
+    }
+
+    #[test]
+    fn odd_frames_are_padded_to_a_word() {
+        let path = temp_file("padding");
+        let mut avi = AviWriter::create(&path, 64, 64).unwrap();
+        let start = avi.bytes_written();
+        avi.write_frame(b"odd").unwrap();
+        assert_eq!(avi.bytes_written() - start, 8 + 4);
+        avi.write_frame(b"even").unwrap();
+        assert_eq!(avi.bytes_written() - start, 2 * (8 + 4));
+        avi.finish(Duration::ZERO).unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn single_frame_assumes_the_nominal_rate() {
+        let path = temp_file("single");
+        let mut avi = AviWriter::create(&path, 64, 64).unwrap();
+        avi.write_frame(b"only").unwrap();
+        avi.finish(Duration::ZERO).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            u32_at(&data, AVIH_MICROS_PER_FRAME),
+            (1_000_000.0 / NOMINAL_FPS) as u32
+        );
+        assert_eq!(u32_at(&data, STRH_RATE), 30 * RATE_SCALE);
+        assert_eq!(u32_at(&data, AVIH_TOTAL_FRAMES), 1);
+    }
+}
@@ -1,212 +1,330 @@
 use anyhow::{Result, anyhow};
-use log::{error, info, trace};
-use std::process::Stdio;
+use log::{error, info, trace, warn};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tokio::{
-    io::{AsyncReadExt, BufReader},
-    process,
-    time::sleep,
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use telemetry::{CameraSettings, VideoCodec};
+use tokio::sync::{Mutex as AsyncMutex, watch};
+
+use crate::{
+    camera::{Frame, FrameBuffer, H264Buffer, H264Frame, settings},
+    config::CameraBackend,
+    hal::{FrameSource, H264Source, open_frame_source, open_h264_source},
 };
 
-use crate::camera::FrameBuffer;
+/// How long `stop` waits for the capture thread to close the camera, in case a source hangs
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait before reopening a camera that failed to open
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+
+/// How the capture is doing, for status reports.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureStats {
+    /// Frames per second, measured over the last second
+    pub fps: f32,
+    /// `None` while stopped
+    pub started_at: Option<Instant>,
+}
 
 #[derive(Clone)]
 pub struct CameraCapture {
     frame_buffer: FrameBuffer,
-    is_running: Arc<Mutex<bool>>,
+    h264_buffer: H264Buffer,
+    keyframe_requested: Arc<AtomicBool>,
+    seq: Arc<AtomicU64>,
+    stats: Arc<Mutex<CaptureStats>>,
+    worker: Arc<Mutex<Option<Worker>>>,
+    /// Held through `start` and `stop`, so one can't interleave with the other
+    lifecycle: Arc<AsyncMutex<()>>,
+    backend: CameraBackend,
+    settings_rx: watch::Receiver<CameraSettings>,
 }
 
 impl CameraCapture {
-    pub fn new(frame_buffer: Arc<Mutex<Option<Vec<u8>>>>) -> Self {
+    /// Captures from `backend`, restarting it whenever `settings_rx` changes. JPEG frames go to
+    /// `frame_buffer` and H.264 ones to `h264_buffer`, depending on the codec setting.
+    pub fn new(
+        frame_buffer: FrameBuffer,
+        h264_buffer: H264Buffer,
+        backend: CameraBackend,
+        settings_rx: watch::Receiver<CameraSettings>,
+    ) -> Self {
         Self {
             frame_buffer,
-            is_running: Arc::new(Mutex::new(false)),
+            h264_buffer,
+            keyframe_requested: Arc::new(AtomicBool::new(false)),
+            seq: Arc::new(AtomicU64::new(0)),
+            stats: Arc::new(Mutex::new(CaptureStats::default())),
+            worker: Arc::new(Mutex::new(None)),
+            lifecycle: Arc::new(AsyncMutex::new(())),
+            backend,
+            settings_rx,
         }
     }
 
     pub async fn start(&self) -> Result<()> {
-        // Check if already running
-        {
-            let running = self.is_running.lock().unwrap();
-            if *running {
-                info!("Camera capture is already running");
-                return Ok(());
-            }
+        let _lifecycle = self.lifecycle.lock().await;
+        if self.is_running()? {
+            info!("Camera capture is already running");
+            return Ok(());
         }
 
-        info!("Starting camera capture with rpicam-vid MJPEG...");
+        info!("Starting camera capture from {:?}...", self.backend);
 
-        // Set running state
-        {
-            let mut running = self.is_running.lock().unwrap();
-            *running = true;
-        }
+        *self.stats.lock().unwrap() = CaptureStats {
+            fps: 0.0,
+            started_at: Some(Instant::now()),
+        };
 
         // Clone what we need for the spawned task
         let frame_buffer = self.frame_buffer.clone();
-        let is_running = self.is_running.clone();
-
-        tokio::spawn(async move {
-            let mut frame_counter = 0u32;
-
-            loop {
-                // Check if we should stop
-                let should_continue = {
-                    let running = is_running.lock().unwrap();
-                    *running
+        let h264_buffer = self.h264_buffer.clone();
+        let keyframe_requested = self.keyframe_requested.clone();
+        let seq = self.seq.clone();
+        let stats = self.stats.clone();
+        let backend = self.backend.clone();
+        let mut settings_rx = self.settings_rx.clone();
+        // each thread has its own flag, so a stopped one stays stopped whatever comes after
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let handle = thread::spawn(move || {
+            let should_continue = || !stopped.load(Ordering::Relaxed);
+            let mut window = (Instant::now(), 0u32);
+
+            while should_continue() {
+                let settings = settings::effective(&settings_rx.borrow_and_update());
+                info!("Camera settings: {settings:?}");
+
+                let opened = match settings.codec {
+                    VideoCodec::Mjpeg => open_frame_source(&backend, &settings).map(Source::Jpeg),
+                    VideoCodec::H264 => open_h264_source(&backend, &settings).map(Source::H264),
                 };
-
-                if !should_continue {
-                    break;
-                }
-
-                // Start the camera process
-                let mut child = match process::Command::new("rpicam-vid")
-                    .args([
-                        "-t",
-                        "0",
-                        "--width",
-                        "1280",
-                        "--height",
-                        "720",
-                        "--framerate",
-                        "30",
-                        "--codec",
-                        "mjpeg",
-                        "--hflip",
-                        "--vflip",
-                        "--nopreview",
-                        "-o",
-                        "-",
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                {
-                    Ok(child) => child,
+                let mut source = match opened {
+                    Ok(source) => source,
                     Err(e) => {
-                        error!("Failed to start rpicam-vid: {e}");
-                        sleep(Duration::from_secs(5)).await;
+                        error!("Failed to open camera: {e:#}");
+                        let retry_at = Instant::now() + REOPEN_DELAY;
+                        while should_continue() && Instant::now() < retry_at {
+                            thread::sleep(Duration::from_millis(100));
+                        }
                         continue;
                     }
                 };
 
-                if let Some(stdout) = child.stdout.take() {
-                    let mut reader = BufReader::new(stdout);
-                    let mut buffer = Vec::new();
-                    let mut temp_buf = [0u8; 8192];
-
-                    loop {
-                        // Check if we should stop
-                        let should_continue = {
-                            let running = is_running.lock().unwrap();
-                            *running
-                        };
+                while should_continue() {
+                    if settings_rx.has_changed().unwrap_or(false) {
+                        info!("Camera settings changed, restarting capture");
+                        break;
+                    }
 
-                        if !should_continue {
-                            let _ = child.kill().await;
-                            break;
+                    let captured = match &mut source {
+                        Source::Jpeg(source) => source.next_frame().map(|jpeg| {
+                            jpeg.map(|jpeg| {
+                                let seq = seq.fetch_add(1, Ordering::Relaxed) + 1;
+                                frame_buffer.send_replace(Some(Frame {
+                                    seq,
+                                    captured_at: SystemTime::now(),
+                                    jpeg: jpeg.into(),
+                                }));
+                                seq
+                            })
+                        }),
+                        Source::H264(source) => {
+                            if keyframe_requested.swap(false, Ordering::Relaxed) {
+                                source.request_keyframe();
+                            }
+                            source.next_access_unit().map(|unit| {
+                                unit.map(|unit| {
+                                    let seq = seq.fetch_add(1, Ordering::Relaxed) + 1;
+                                    // nobody subscribed is fine
+                                    let _ = h264_buffer.send(H264Frame {
+                                        seq,
+                                        captured_at: SystemTime::now(),
+                                        keyframe: unit.keyframe,
+                                        dimensions: unit.dimensions.unwrap_or_default(),
+                                        data: unit.data.into(),
+                                    });
+                                    seq
+                                })
+                            })
                         }
+                    };
 
-                        match reader.read(&mut temp_buf).await {
-                            Ok(0) => {
-                                // EOF - process ended
-                                break;
-                            }
-                            Ok(n) => {
-                                buffer.extend_from_slice(&temp_buf[..n]);
-
-                                // Look for complete JPEG frames
-                                while let Some(start) = Self::find_jpeg_start(&buffer) {
-                                    if let Some(end) = Self::find_jpeg_end(&buffer[start..]) {
-                                        let frame_end = start + end + 2;
-                                        let frame = buffer[start..frame_end].to_vec();
-                                        buffer.drain(..frame_end);
-
-                                        // Update the frame buffer
-                                        if let Ok(mut current_frame) = frame_buffer.lock() {
-                                            *current_frame = Some(frame);
-                                            frame_counter += 1;
-
-                                            if frame_counter % 100 == 0 {
-                                                trace!("Captured {frame_counter} frames");
-                                            }
-                                        }
-                                    } else {
-                                        break;
-                                    }
-                                }
+                    match captured {
+                        Ok(Some(seq)) => {
+                            if seq.is_multiple_of(100) {
+                                trace!("Captured {seq} frames");
                             }
-                            Err(e) => {
-                                error!("Error reading from camera: {e}");
-                                break;
+
+                            window.1 += 1;
+                            let elapsed = window.0.elapsed();
+                            if elapsed >= Duration::from_secs(1) {
+                                stats.lock().unwrap().fps = window.1 as f32 / elapsed.as_secs_f32();
+                                window = (Instant::now(), 0);
                             }
                         }
+                        Ok(None) => break,
+                        Err(e) => {
+                            error!("{e:#}");
+                            break;
+                        }
                     }
                 }
 
-                let _ = child.kill().await;
-                let _ = child.wait().await;
-
-                // Check if we should restart
-                let should_restart = {
-                    let running = is_running.lock().unwrap();
-                    *running
-                };
-
-                if !should_restart {
-                    break;
-                }
+                // Dropping the source stops the camera
+                drop(source);
 
                 // Brief delay before restart
-                sleep(Duration::from_millis(100)).await;
-            }
-
-            // Clear running state
-            {
-                let mut running = is_running.lock().unwrap();
-                *running = false;
+                if should_continue() {
+                    thread::sleep(Duration::from_millis(100));
+                }
             }
 
             info!("Camera capture task ended");
         });
+        *self.worker.lock().unwrap() = Some(Worker { stop, handle });
 
         Ok(())
     }
 
+    /// Stops the capture thread and waits for it to close the camera, so a `start` straight
+    /// after opens it afresh rather than racing the old thread for it.
     pub async fn stop(&self) -> Result<()> {
+        self.stop_if(|| true).await.map(|_| ())
+    }
+
+    /// Stops the capture like `stop` if `idle` still holds once no `start` is under way,
+    /// returning whether it did. A caller that decided to stop earlier can't otherwise know
+    /// that a `start` hasn't come and gone since.
+    pub async fn stop_if(&self, idle: impl FnOnce() -> bool) -> Result<bool> {
+        let _lifecycle = self.lifecycle.lock().await;
+        if !idle() {
+            return Ok(false);
+        }
         info!("Stopping camera capture...");
 
-        // Set running to false
-        {
-            let mut running = self.is_running.lock().unwrap();
-            *running = false;
+        let worker = self.worker.lock().unwrap().take();
+        if let Some(Worker { stop, handle }) = worker {
+            stop.store(true, Ordering::Relaxed);
+            let joined = tokio::task::spawn_blocking(move || handle.join());
+            if tokio::time::timeout(STOP_TIMEOUT, joined).await.is_err() {
+                warn!("Camera capture thread is stuck, leaving it to stop on its own");
+            }
         }
 
         // Clear the frame buffer
-        {
-            let mut buffer = self.frame_buffer.lock().unwrap();
-            *buffer = None;
-        }
+        self.frame_buffer.send_replace(None);
+        *self.stats.lock().unwrap() = CaptureStats::default();
 
         info!("Camera capture stopped");
-        Ok(())
+        Ok(true)
+    }
+
+    /// Holds off `start` and `stop` until the guard is dropped, to line races up in tests.
+    #[cfg(test)]
+    pub(crate) async fn hold_lifecycle(&self) -> tokio::sync::OwnedMutexGuard<()> {
+        self.lifecycle.clone().lock_owned().await
+    }
+
+    /// The codec the capture currently runs, or starts, with.
+    pub fn codec(&self) -> VideoCodec {
+        self.settings_rx.borrow().codec
+    }
+
+    /// Counts `frames` as captured without a camera, to test what watches the frame rate.
+    #[cfg(test)]
+    pub(crate) fn count_captured(&self, frames: u64) {
+        self.seq.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    /// Asks the H.264 encoder for a keyframe, so a new viewer can start decoding sooner.
+    pub fn request_keyframe(&self) {
+        self.keyframe_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Frames captured since the radio started, in either codec.
+    pub fn frames_captured(&self) -> u64 {
+        self.seq.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> CaptureStats {
+        *self.stats.lock().unwrap()
     }
 
     pub fn is_running(&self) -> Result<bool> {
-        let running = self
-            .is_running
+        let worker = self
+            .worker
             .lock()
             .map_err(|e| anyhow!("Mutex poisoned: {}", e))?;
-        Ok(*running)
+        Ok(worker
+            .as_ref()
+            .is_some_and(|worker| !worker.handle.is_finished()))
     }
+}
 
-    fn find_jpeg_start(buffer: &[u8]) -> Option<usize> {
-        buffer.windows(2).position(|w| w == [0xFF, 0xD8])
+/// A running capture thread.
+struct Worker {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+/// The capture's source in the configured codec.
+enum Source {
+    Jpeg(Box<dyn FrameSource>),
+    H264(Box<dyn H264Source>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FPS: u8 = 20;
+
+    fn test_pattern() -> CameraCapture {
+        let (frame_buffer, _) = watch::channel(None);
+        let (h264_buffer, _) = tokio::sync::broadcast::channel(1);
+        let (_, settings_rx) = watch::channel(CameraSettings {
+            width: 64,
+            height: 64,
+            fps: FPS,
+            ..CameraSettings::default()
+        });
+        CameraCapture::new(
+            frame_buffer,
+            h264_buffer,
+            CameraBackend::TestPattern,
+            settings_rx,
+        )
     }
 
-    fn find_jpeg_end(buffer: &[u8]) -> Option<usize> {
-        buffer.windows(2).position(|w| w == [0xFF, 0xD9])
+    #[tokio::test(flavor = "multi_thread")]
+    async fn restarting_quickly_leaves_one_capture_thread() {
+        let capture = test_pattern();
+        for _ in 0..5 {
+            capture.start().await.unwrap();
+            capture.stop().await.unwrap();
+        }
+        capture.start().await.unwrap();
+        assert!(capture.is_running().unwrap());
+
+        // a second thread would double the frame rate
+        let before = capture.frames_captured();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let captured = capture.frames_captured() - before;
+        assert!(
+            captured <= FPS as u64 * 3 / 2,
+            "{captured} frames in a second at {FPS}fps"
+        );
+
+        // and once stopped nothing is left capturing
+        capture.stop().await.unwrap();
+        assert!(!capture.is_running().unwrap());
+        let stopped_at = capture.frames_captured();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(capture.frames_captured(), stopped_at);
+        assert!(capture.frame_buffer.borrow().is_none());
     }
 }
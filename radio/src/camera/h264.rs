@@ -0,0 +1,420 @@
+//! Incremental splitter for H.264 Annex-B byte streams, as `rpicam-vid --codec h264` writes
+//! them.
+//!
+//! NAL units are delimited by `00 00 01` start codes and grouped into access units, one per
+//! frame. A new access unit starts at an access unit delimiter, SEI or parameter set, or at a
+//! slice whose first macroblock is 0, once the current one holds a slice. Each unit comes out
+//! with four byte start codes, marked as a keyframe when it holds an IDR slice.
+//!
+//! An access unit is capped at `max_unit_len` bytes. An oversized one is dropped up to the start
+//! of the next, counting a resync, and so is a runaway NAL unit.
+
+use std::collections::VecDeque;
+
+use log::debug;
+
+/// Far beyond a 1080p keyframe at the encoder's highest bitrate
+pub const DEFAULT_MAX_UNIT_LEN: usize = 4 * 1024 * 1024;
+
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+const NAL_SLICE: u8 = 1;
+const NAL_IDR: u8 = 5;
+const NAL_SEI: u8 = 6;
+const NAL_SPS: u8 = 7;
+const NAL_AUD: u8 = 9;
+
+/// The NAL units of one frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessUnit {
+    /// Annex-B, each NAL unit behind a four byte start code
+    pub data: Vec<u8>,
+    /// Holds an IDR slice, so decoding can start here
+    pub keyframe: bool,
+    /// From the latest sequence parameter set, `None` before the first one
+    pub dimensions: Option<(u16, u16)>,
+}
+
+#[derive(Debug)]
+pub struct AnnexBParser {
+    /// Bytes after the last start code, or junk before the first
+    nal: Vec<u8>,
+    /// How far `nal` has been searched for the next start code
+    scanned: usize,
+    /// Whether a start code has been seen since the last resync
+    synced: bool,
+    unit: Vec<u8>,
+    has_slice: bool,
+    keyframe: bool,
+    /// Set when the current access unit outgrew the limit, dropping the rest of it
+    oversized: bool,
+    dimensions: Option<(u16, u16)>,
+    ready: VecDeque<AccessUnit>,
+    max_unit_len: usize,
+    resyncs: u64,
+}
+
+impl Default for AnnexBParser {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_UNIT_LEN)
+    }
+}
+
+impl AnnexBParser {
+    pub fn new(max_unit_len: usize) -> Self {
+        Self {
+            nal: Vec::new(),
+            scanned: 0,
+            synced: false,
+            unit: Vec::new(),
+            has_slice: false,
+            keyframe: false,
+            oversized: false,
+            dimensions: None,
+            ready: VecDeque::new(),
+            max_unit_len,
+            resyncs: 0,
+        }
+    }
+
+    /// Times the parser dropped data to find the next access unit
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    pub fn next_unit(&mut self) -> Option<AccessUnit> {
+        self.ready.pop_front()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.nal.extend_from_slice(bytes);
+
+        while let Some(at) = self.nal[self.scanned..]
+            .windows(3)
+            .position(|w| w == [0, 0, 1])
+            .map(|i| self.scanned + i)
+        {
+            if self.synced {
+                let nal = std::mem::take(&mut self.nal);
+                // the zero before a four byte start code belongs to it, not to this NAL unit
+                let end = nal[..at].iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                self.add_nal(&nal[..end]);
+                self.nal = nal;
+            }
+            self.nal.drain(..at + 3);
+            self.scanned = 0;
+            self.synced = true;
+        }
+        // a start code may be split across pushes
+        self.scanned = self.nal.len().saturating_sub(2);
+
+        if self.nal.len() > self.max_unit_len {
+            self.resync("NAL unit too large");
+        }
+    }
+
+    /// Ends the stream, passing on the last access unit.
+    pub fn finish(&mut self) {
+        if self.synced {
+            let nal = std::mem::take(&mut self.nal);
+            self.add_nal(&nal);
+        }
+        self.finish_unit();
+        self.nal.clear();
+        self.scanned = 0;
+        self.synced = false;
+    }
+
+    fn add_nal(&mut self, nal: &[u8]) {
+        let Some(&header) = nal.first() else {
+            return;
+        };
+        let nal_type = header & 0x1F;
+        let slice = matches!(nal_type, NAL_SLICE | NAL_IDR);
+        // first_mb_in_slice is Exp-Golomb, so a single 1 bit when it is 0
+        let first_slice = slice && nal.get(1).is_some_and(|b| b & 0x80 != 0);
+        let starts_unit = first_slice || matches!(nal_type, NAL_SEI..=NAL_AUD | 14..=18);
+        if self.has_slice && starts_unit {
+            self.finish_unit();
+        }
+
+        if nal_type == NAL_SPS
+            && let Some(dimensions) = sps_dimensions(nal)
+        {
+            self.dimensions = Some(dimensions);
+        }
+        self.has_slice |= slice;
+        self.keyframe |= nal_type == NAL_IDR;
+        if self.oversized {
+            return;
+        }
+        self.unit.extend_from_slice(&START_CODE);
+        self.unit.extend_from_slice(nal);
+        if self.unit.len() > self.max_unit_len {
+            debug!("Dropping H.264 access unit: too large");
+            self.resyncs += 1;
+            self.oversized = true;
+            self.unit = Vec::new();
+        }
+    }
+
+    fn finish_unit(&mut self) {
+        let data = std::mem::take(&mut self.unit);
+        // parameter sets without a picture are no use on their own
+        if self.has_slice && !self.oversized {
+            self.ready.push_back(AccessUnit {
+                data,
+                keyframe: self.keyframe,
+                dimensions: self.dimensions,
+            });
+        }
+        self.has_slice = false;
+        self.keyframe = false;
+        self.oversized = false;
+    }
+
+    fn resync(&mut self, reason: &str) {
+        debug!("Dropping H.264 access unit: {reason}");
+        self.resyncs += 1;
+        self.nal.clear();
+        self.unit.clear();
+        self.scanned = 0;
+        self.synced = false;
+        self.has_slice = false;
+        self.keyframe = false;
+        self.oversized = false;
+    }
+}
+
+/// Reads Exp-Golomb coded fields from a NAL unit's payload.
+struct BitReader {
+    rbsp: Vec<u8>,
+    bit: usize,
+}
+
+impl BitReader {
+    /// Drops the emulation prevention bytes from `payload`.
+    fn new(payload: &[u8]) -> Self {
+        let mut rbsp = Vec::with_capacity(payload.len());
+        let mut zeros = 0;
+        for &byte in payload {
+            if zeros >= 2 && byte == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            rbsp.push(byte);
+        }
+        Self { rbsp, bit: 0 }
+    }
+
+    fn u(&mut self, bits: u32) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self.rbsp.get(self.bit / 8)?;
+            value = (value << 1) | u32::from((byte >> (7 - self.bit % 8)) & 1);
+            self.bit += 1;
+        }
+        Some(value)
+    }
+
+    fn flag(&mut self) -> Option<bool> {
+        Some(self.u(1)? == 1)
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.u(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some(((1u64 << zeros) - 1 + u64::from(self.u(zeros)?)) as u32)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let code = self.ue()?;
+        let magnitude = code.div_ceil(2) as i32;
+        Some(if code % 2 == 1 { magnitude } else { -magnitude })
+    }
+}
+
+/// Picture size in pixels from a sequence parameter set NAL unit, after cropping.
+pub fn sps_dimensions(nal: &[u8]) -> Option<(u16, u16)> {
+    let mut r = BitReader::new(nal.get(1..)?);
+    let profile = r.u(8)?;
+    r.u(16)?; // constraint flags and level
+    r.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format = 1;
+    let mut separate_planes = false;
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format = r.ue()?;
+        if chroma_format == 3 {
+            separate_planes = r.flag()?;
+        }
+        r.ue()?; // luma bit depth
+        r.ue()?; // chroma bit depth
+        r.flag()?; // qpprime_y_zero_transform_bypass_flag
+        if r.flag()? {
+            let lists = if chroma_format == 3 { 12 } else { 8 };
+            for list in 0..lists {
+                if r.flag()? {
+                    skip_scaling_list(&mut r, if list < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    r.ue()?; // log2_max_frame_num_minus4
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.flag()?; // delta_pic_order_always_zero_flag
+            r.se()?; // offset_for_non_ref_pic
+            r.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.flag()?; // gaps_in_frame_num_value_allowed_flag
+
+    let width_mbs = r.ue()?.checked_add(1)?;
+    let height_map_units = r.ue()?.checked_add(1)?;
+    let frame_mbs_only = r.flag()?;
+    if !frame_mbs_only {
+        r.flag()?; // mb_adaptive_frame_field_flag
+    }
+    r.flag()?; // direct_8x8_inference_flag
+
+    let field_factor = if frame_mbs_only { 1 } else { 2 };
+    let (mut crop_x, mut crop_y) = (0, 0);
+    if r.flag()? {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        let (unit_x, unit_y) = match chroma_format {
+            _ if separate_planes => (1, 1),
+            0 | 3 => (1, 1),
+            1 => (2, 2),
+            _ => (2, 1),
+        };
+        crop_x = left.checked_add(right)?.checked_mul(unit_x)?;
+        crop_y = top
+            .checked_add(bottom)?
+            .checked_mul(unit_y * field_factor)?;
+    }
+
+    let width = width_mbs.checked_mul(16)?.checked_sub(crop_x)?;
+    let height = height_map_units
+        .checked_mul(field_factor * 16)?
+        .checked_sub(crop_y)?;
+    Some((width.try_into().ok()?, height.try_into().ok()?))
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Option<()> {
+    let (mut last, mut next) = (8i32, 8i32);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// 30 frames of 64x48 video with a keyframe every 10, each one behind its parameter sets.
+    const FIXTURE: &[u8] = include_bytes!("../../fixtures/test-64x48.h264");
+
+    fn parse_chunked(stream: &[u8], chunks: &[usize]) -> (AnnexBParser, Vec<AccessUnit>) {
+        let mut parser = AnnexBParser::default();
+        let mut units = Vec::new();
+        let mut rest = stream;
+        let mut sizes = chunks.iter().cycle();
+        while !rest.is_empty() {
+            let n = sizes
+                .next()
+                .copied()
+                .unwrap_or(rest.len())
+                .clamp(1, rest.len());
+            parser.push(&rest[..n]);
+            rest = &rest[n..];
+            units.extend(std::iter::from_fn(|| parser.next_unit()));
+        }
+        parser.finish();
+        units.extend(std::iter::from_fn(|| parser.next_unit()));
+        (parser, units)
+    }
+
+    #[test]
+    fn fixture_splits_into_frames() {
+        let (parser, units) = parse_chunked(FIXTURE, &[FIXTURE.len()]);
+        assert_eq!(units.len(), 30);
+        let keyframes: Vec<_> = (0..units.len()).filter(|&i| units[i].keyframe).collect();
+        assert_eq!(keyframes, [0, 10, 20]);
+        assert!(units.iter().all(|u| u.dimensions == Some((64, 48))));
+        assert_eq!(
+            units.iter().map(|u| u.data.len()).sum::<usize>(),
+            FIXTURE.len()
+        );
+        assert_eq!(parser.resyncs(), 0);
+    }
+
+    #[test]
+    fn dimensions_are_cropped() {
+        // 1920x1088 in macroblocks, cropped by 8 rows to 1080p as the encoder does
+        let sps = [
+            0x67, 0x42, 0xC0, 0x28, 0x95, 0xA0, 0x1E, 0x00, 0x89, 0xF9, 0x50,
+        ];
+        assert_eq!(sps_dimensions(&sps), Some((1920, 1080)));
+        assert_eq!(sps_dimensions(&sps[..6]), None);
+    }
+
+    #[test]
+    fn oversized_unit_is_dropped() {
+        let (_, units) = parse_chunked(FIXTURE, &[FIXTURE.len()]);
+        let keyframe_len = units[0].data.len();
+
+        let mut parser = AnnexBParser::new(keyframe_len - 1);
+        parser.push(FIXTURE);
+        parser.finish();
+        let kept: Vec<_> = std::iter::from_fn(|| parser.next_unit()).collect();
+        assert!(parser.resyncs() > 0);
+        assert!(kept.iter().all(|u| u.data.len() < keyframe_len));
+    }
+
+    proptest! {
+        #[test]
+        fn fixture_survives_any_chunking(
+            chunks in prop::collection::vec(1..300usize, 1..8),
+        ) {
+            let (_, expected) = parse_chunked(FIXTURE, &[FIXTURE.len()]);
+            let (parser, units) = parse_chunked(FIXTURE, &chunks);
+            prop_assert_eq!(units, expected);
+            prop_assert_eq!(parser.resyncs(), 0);
+        }
+
+        #[test]
+        fn junk_never_panics(
+            junk in prop::collection::vec(any::<u8>(), 0..2000),
+            chunks in prop::collection::vec(1..300usize, 1..8),
+        ) {
+            parse_chunked(&junk, &chunks);
+        }
+    }
+}
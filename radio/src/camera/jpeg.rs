@@ -0,0 +1,545 @@
+//! Incremental JPEG splitter for MJPEG byte streams.
+//!
+//! Rather than scanning for `FFD8`/`FFD9` pairs, the parser walks the marker structure: segment
+//! lengths are skipped, so an end-of-image inside an embedded EXIF thumbnail does not end the
+//! frame, and entropy-coded data is scanned for the next real marker, stepping over stuffed
+//! bytes and restart markers. Each byte is looked at once.
+//!
+//! A frame is capped at `max_frame_len` bytes. Oversized and malformed frames are dropped and
+//! the parser resyncs on the next start-of-image, counting each resync. A frame cut short inside
+//! an application segment cannot be told apart from one carrying a thumbnail, so the frame after
+//! it may be lost along with it.
+
+use std::collections::VecDeque;
+
+use log::debug;
+
+/// Generous for a 1080p frame at quality 100
+pub const DEFAULT_MAX_FRAME_LEN: usize = 4 * 1024 * 1024;
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DHT: u8 = 0xC4;
+const DQT: u8 = 0xDB;
+const DRI: u8 = 0xDD;
+const TEM: u8 = 0x01;
+const RST0: u8 = 0xD0;
+const RST7: u8 = 0xD7;
+
+/// Application and comment segments hold arbitrary data, embedded thumbnails included. Every
+/// other segment is a small table in which `FFD8` never occurs.
+fn opaque(code: u8) -> bool {
+    matches!(code, 0xE0..=0xEF | 0xFE)
+}
+
+/// Largest valid length of a segment, so a length read from a cut-off frame can't swallow the
+/// frames after it.
+fn max_segment_len(code: u8) -> u16 {
+    match code {
+        // four tables of 16 counts and up to 256 symbols
+        DHT => 2 + 4 * (1 + 16 + 256),
+        // four 16 bit tables
+        DQT => 2 + 4 * (1 + 128),
+        DRI => 4,
+        // up to four components
+        SOS => 2 + 1 + 4 * 2 + 3,
+        // start-of-frame with up to 255 components
+        0xC0..=0xCF => 2 + 6 + 3 * 255,
+        _ => u16::MAX,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Between frames, looking for a start-of-image. `counted` is set once the bytes being
+    /// skipped have been counted as a resync.
+    Seek { saw_ff: bool, counted: bool },
+    /// Expecting the `FF` that starts the next marker
+    Marker,
+    /// After an `FF`, expecting the marker code
+    MarkerCode,
+    /// Reading the big-endian length of segment `code`. `high` is the first byte once read.
+    Length { code: u8, high: Option<u8> },
+    /// Inside segment `code`, with this many bytes left
+    Segment {
+        code: u8,
+        remaining: usize,
+        saw_ff: bool,
+    },
+    /// Entropy-coded data after a start-of-scan, up to the next marker
+    Entropy { saw_ff: bool },
+}
+
+#[derive(Debug)]
+pub struct JpegParser {
+    state: State,
+    frame: Vec<u8>,
+    ready: VecDeque<Vec<u8>>,
+    max_frame_len: usize,
+    resyncs: u64,
+}
+
+impl Default for JpegParser {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_LEN)
+    }
+}
+
+impl JpegParser {
+    pub fn new(max_frame_len: usize) -> Self {
+        Self {
+            state: State::Seek {
+                saw_ff: false,
+                counted: false,
+            },
+            frame: Vec::new(),
+            ready: VecDeque::new(),
+            max_frame_len,
+            resyncs: 0,
+        }
+    }
+
+    /// Times the parser dropped data to find the next frame
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        self.ready.pop_front()
+    }
+
+    pub fn push(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let consumed = self.step(bytes);
+            bytes = &bytes[consumed..];
+
+            if self.frame.len() > self.max_frame_len {
+                self.resync("frame too large");
+            }
+        }
+    }
+
+    /// Consumes at least one byte from `bytes` and returns how many.
+    fn step(&mut self, bytes: &[u8]) -> usize {
+        match self.state {
+            State::Seek { saw_ff, counted } => {
+                let byte = bytes[0];
+                if saw_ff && byte == SOI {
+                    self.frame.clear();
+                    self.frame.extend_from_slice(&[0xFF, SOI]);
+                    self.state = State::Marker;
+                    return 1;
+                }
+                // anything but a start-of-image, or the FFs before one, is junk
+                let junk = byte != 0xFF;
+                if junk && !counted {
+                    self.resyncs += 1;
+                    debug!("Skipping junk between JPEG frames");
+                }
+                self.state = State::Seek {
+                    saw_ff: byte == 0xFF,
+                    counted: counted || junk,
+                };
+                1
+            }
+            State::Marker => {
+                if bytes[0] == 0xFF {
+                    self.frame.push(0xFF);
+                    self.state = State::MarkerCode;
+                } else {
+                    self.resync("expected a marker");
+                }
+                1
+            }
+            State::MarkerCode => {
+                self.marker(bytes[0]);
+                1
+            }
+            State::Length { code, high: None } => {
+                self.frame.push(bytes[0]);
+                self.state = State::Length {
+                    code,
+                    high: Some(bytes[0]),
+                };
+                1
+            }
+            State::Length {
+                code,
+                high: Some(high),
+            } => {
+                if high == 0xFF && bytes[0] == SOI {
+                    self.truncated();
+                    return 1;
+                }
+
+                self.frame.push(bytes[0]);
+                // the length counts its own two bytes
+                match u16::from_be_bytes([high, bytes[0]]) {
+                    len if len < 2 || len > max_segment_len(code) => {
+                        self.resync("bad segment length")
+                    }
+                    2 => self.end_segment(code, false),
+                    len => {
+                        self.state = State::Segment {
+                            code,
+                            remaining: len as usize - 2,
+                            saw_ff: false,
+                        }
+                    }
+                }
+                1
+            }
+            State::Segment {
+                code,
+                remaining,
+                saw_ff,
+            } => {
+                let n = if opaque(code) {
+                    remaining.min(bytes.len()).min(self.room())
+                } else if saw_ff && bytes[0] == SOI {
+                    self.truncated();
+                    return 1;
+                } else {
+                    // tables are short, go byte by byte to catch a start-of-image
+                    1
+                };
+
+                self.frame.extend_from_slice(&bytes[..n]);
+                let saw_ff = bytes[n - 1] == 0xFF;
+                if n == remaining {
+                    self.end_segment(code, saw_ff);
+                } else {
+                    self.state = State::Segment {
+                        code,
+                        remaining: remaining - n,
+                        saw_ff,
+                    };
+                }
+                n
+            }
+            State::Entropy { saw_ff: false } => {
+                // copy straight through to the next FF
+                let n = bytes
+                    .iter()
+                    .position(|&b| b == 0xFF)
+                    .map_or(bytes.len(), |i| i + 1)
+                    .min(self.room());
+                self.frame.extend_from_slice(&bytes[..n]);
+                if bytes[n - 1] == 0xFF {
+                    self.state = State::Entropy { saw_ff: true };
+                }
+                n
+            }
+            State::Entropy { saw_ff: true } => {
+                let byte = bytes[0];
+                match byte {
+                    // stuffed FF, restart marker, or fill byte: still in the scan
+                    0x00 | RST0..=RST7 => {
+                        self.frame.push(byte);
+                        self.state = State::Entropy { saw_ff: false };
+                    }
+                    0xFF => self.frame.push(byte),
+                    _ => self.marker(byte),
+                }
+                1
+            }
+        }
+    }
+
+    /// Handles a marker code. The `FF` before it is already in the frame.
+    fn marker(&mut self, code: u8) {
+        match code {
+            // fill byte before the code
+            0xFF => self.frame.push(code),
+            SOI => self.truncated(),
+            EOI if self.frame.len() >= self.max_frame_len => self.resync("frame too large"),
+            EOI => {
+                self.frame.push(code);
+                self.ready.push_back(std::mem::take(&mut self.frame));
+                self.state = State::Seek {
+                    saw_ff: false,
+                    counted: false,
+                };
+            }
+            TEM | RST0..=RST7 => {
+                self.frame.push(code);
+                self.state = State::Marker;
+            }
+            0x00 => self.resync("expected a marker code"),
+            _ => {
+                self.frame.push(code);
+                self.state = State::Length { code, high: None };
+            }
+        }
+    }
+
+    /// Bytes that can be copied in one go before the frame is over the limit
+    fn room(&self) -> usize {
+        self.max_frame_len.saturating_sub(self.frame.len()) + 1
+    }
+
+    /// `saw_ff` if the segment ended in an `FF`, which could start a marker when the segment
+    /// was cut short.
+    fn end_segment(&mut self, code: u8, saw_ff: bool) {
+        self.state = if code == SOS {
+            State::Entropy { saw_ff }
+        } else if saw_ff {
+            State::MarkerCode
+        } else {
+            State::Marker
+        };
+    }
+
+    /// A new image started before this one ended: the stream lost the rest of it.
+    fn truncated(&mut self) {
+        self.resync("frame truncated");
+        self.state = State::Seek {
+            saw_ff: true,
+            counted: true,
+        };
+        self.step(&[SOI]);
+    }
+
+    fn resync(&mut self, reason: &str) {
+        debug!("Dropping {} byte JPEG frame: {reason}", self.frame.len());
+        self.resyncs += 1;
+        self.frame.clear();
+        self.state = State::Seek {
+            saw_ff: false,
+            counted: true,
+        };
+    }
+}
+
+/// Width and height from the start-of-frame of a complete JPEG.
+pub fn dimensions(jpeg: &[u8]) -> Option<(u16, u16)> {
+    let mut pos = 2;
+    while let [0xFF, code, high, low, rest @ ..] = jpeg.get(pos..)? {
+        match *code {
+            // DHT, JPG and DAC share the start-of-frame range
+            0xC0..=0xCF if !matches!(*code, DHT | 0xC8 | 0xCC) => {
+                let [_precision, h1, h0, w1, w0, ..] = rest else {
+                    return None;
+                };
+                return Some((
+                    u16::from_be_bytes([*w1, *w0]),
+                    u16::from_be_bytes([*h1, *h0]),
+                ));
+            }
+            SOS => return None,
+            _ => pos += 2 + usize::from(u16::from_be_bytes([*high, *low])),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use jpeg_encoder::{ColorType, Encoder};
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn encode(
+        width: u16,
+        height: u16,
+        seed: u8,
+        configure: impl FnOnce(&mut Encoder<&mut Vec<u8>>),
+    ) -> Vec<u8> {
+        let pixels: Vec<u8> = (0..width as usize * height as usize * 3)
+            .map(|i| (i as u8).wrapping_mul(seed).wrapping_add(seed))
+            .collect();
+        let mut jpeg = Vec::new();
+        let mut encoder = Encoder::new(&mut jpeg, 75);
+        configure(&mut encoder);
+        encoder
+            .encode(&pixels, width, height, ColorType::Rgb)
+            .unwrap();
+        jpeg
+    }
+
+    /// Baseline, restart intervals, progressive, and an APP1 segment holding a thumbnail whose
+    /// end-of-image would end the frame early in a naive splitter.
+    fn fixtures() -> Vec<Vec<u8>> {
+        let thumbnail = encode(8, 8, 3, |_| {});
+        vec![
+            encode(32, 24, 1, |_| {}),
+            encode(40, 40, 7, |e| e.set_restart_interval(1)),
+            encode(48, 16, 11, |e| e.set_restart_interval(3)),
+            encode(24, 24, 13, |e| e.set_progressive(true)),
+            encode(16, 16, 17, |e| e.add_app_segment(1, thumbnail).unwrap()),
+        ]
+    }
+
+    fn parse_chunked(
+        stream: &[u8],
+        chunks: &[usize],
+        max_frame_len: usize,
+    ) -> (JpegParser, Vec<Vec<u8>>) {
+        let mut parser = JpegParser::new(max_frame_len);
+        let mut frames = Vec::new();
+        let mut rest = stream;
+        let mut sizes = chunks.iter().cycle();
+        while !rest.is_empty() {
+            let n = sizes
+                .next()
+                .copied()
+                .unwrap_or(rest.len())
+                .clamp(1, rest.len());
+            parser.push(&rest[..n]);
+            rest = &rest[n..];
+            frames.extend(std::iter::from_fn(|| parser.next_frame()));
+        }
+        (parser, frames)
+    }
+
+    #[test]
+    fn embedded_thumbnail_does_not_split_frame() {
+        let frame = fixtures().pop().unwrap();
+        assert!(frame.windows(2).filter(|w| *w == [0xFF, EOI]).count() > 1);
+
+        let (parser, frames) = parse_chunked(&frame, &[frame.len()], DEFAULT_MAX_FRAME_LEN);
+        assert_eq!(frames, vec![frame]);
+        assert_eq!(parser.resyncs(), 0);
+    }
+
+    #[test]
+    fn dimensions_come_from_the_frame_header() {
+        let sizes: Vec<_> = fixtures().iter().map(|f| dimensions(f)).collect();
+        assert_eq!(
+            sizes,
+            [(32, 24), (40, 40), (48, 16), (24, 24), (16, 16)].map(Some)
+        );
+        assert_eq!(dimensions(&fixtures()[0][..20]), None);
+    }
+
+    #[test]
+    fn truncated_frame_is_dropped() {
+        let fixtures = fixtures();
+        // cut in the entropy-coded data
+        let mut stream = fixtures[0][..fixtures[0].len() - 10].to_vec();
+        stream.extend_from_slice(&fixtures[1]);
+
+        let (parser, frames) = parse_chunked(&stream, &[7], DEFAULT_MAX_FRAME_LEN);
+        assert_eq!(frames, vec![fixtures[1].clone()]);
+        assert_eq!(parser.resyncs(), 1);
+    }
+
+    #[test]
+    fn oversized_frame_is_dropped() {
+        let fixtures = fixtures();
+        let (small, large) = if fixtures[0].len() < fixtures[1].len() {
+            (&fixtures[0], &fixtures[1])
+        } else {
+            (&fixtures[1], &fixtures[0])
+        };
+        let stream = [large.as_slice(), small.as_slice()].concat();
+
+        let (parser, frames) = parse_chunked(&stream, &[64], small.len());
+        assert_eq!(frames, vec![small.clone()]);
+        assert_eq!(parser.resyncs(), 1);
+        assert_eq!(parser.frame.len(), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn concatenated_frames_survive_any_chunking(
+            picks in prop::collection::vec(0..5usize, 1..12),
+            chunks in prop::collection::vec(1..300usize, 1..8),
+        ) {
+            let fixtures = fixtures();
+            let expected: Vec<Vec<u8>> = picks.iter().map(|&i| fixtures[i].clone()).collect();
+
+            let (parser, frames) = parse_chunked(&expected.concat(), &chunks, DEFAULT_MAX_FRAME_LEN);
+            prop_assert_eq!(frames, expected);
+            prop_assert_eq!(parser.resyncs(), 0);
+        }
+
+        #[test]
+        fn junk_between_frames_is_skipped(
+            picks in prop::collection::vec(0..5usize, 1..8),
+            junk in prop::collection::vec(prop::collection::vec(0..0xFFu8, 0..40), 8),
+            chunks in prop::collection::vec(1..300usize, 1..8),
+        ) {
+            let fixtures = fixtures();
+            let mut stream = Vec::new();
+            let mut junk_runs = 0;
+            for (&i, junk) in picks.iter().zip(&junk) {
+                junk_runs += u64::from(!junk.is_empty());
+                stream.extend_from_slice(junk);
+                stream.extend_from_slice(&fixtures[i]);
+            }
+            let expected: Vec<Vec<u8>> = picks.iter().map(|&i| fixtures[i].clone()).collect();
+
+            let (parser, frames) = parse_chunked(&stream, &chunks, DEFAULT_MAX_FRAME_LEN);
+            prop_assert_eq!(frames, expected);
+            prop_assert_eq!(parser.resyncs(), junk_runs);
+        }
+
+        /// A frame cut anywhere costs at most the frame after it: when the cut leaves the parser
+        /// inside an application segment, that frame is dropped or spliced onto the cut one.
+        /// Leaves out the thumbnail fixture, whose embedded image is a valid frame of its own
+        /// once the parser lands inside it.
+        #[test]
+        fn truncated_frame_costs_at_most_the_next(
+            cut in any::<prop::sample::Index>(),
+            picks in prop::collection::vec(0..4usize, 2..8),
+            chunks in prop::collection::vec(1..300usize, 1..8),
+        ) {
+            let fixtures = fixtures();
+            let truncated = &fixtures[picks[0]];
+            let cut = 2 + cut.index(truncated.len() - 3);
+            let expected: Vec<Vec<u8>> = picks[1..].iter().map(|&i| fixtures[i].clone()).collect();
+            let stream = [&truncated[..cut], &expected.concat()].concat();
+
+            let (_, frames) = parse_chunked(&stream, &chunks, DEFAULT_MAX_FRAME_LEN);
+            prop_assert!(frames.len() + 1 >= expected.len(), "cut at {}", cut);
+            prop_assert!(frames.ends_with(&expected[1..]), "cut at {}", cut);
+        }
+
+        #[test]
+        fn corrupted_stream_stays_bounded(
+            picks in prop::collection::vec(0..5usize, 1..8),
+            flips in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 0..20),
+            chunks in prop::collection::vec(1..300usize, 1..8),
+            max_frame_len in 256..4096usize,
+        ) {
+            let fixtures = fixtures();
+            let mut stream: Vec<u8> = picks.iter().flat_map(|&i| fixtures[i].clone()).collect();
+            for (index, byte) in &flips {
+                let i = index.index(stream.len());
+                stream[i] = *byte;
+            }
+
+            let mut parser = JpegParser::new(max_frame_len);
+            let mut rest = stream.as_slice();
+            let mut sizes = chunks.iter().cycle();
+            while !rest.is_empty() {
+                let n = (*sizes.next().unwrap()).min(rest.len());
+                parser.push(&rest[..n]);
+                rest = &rest[n..];
+                prop_assert!(parser.frame.len() <= max_frame_len);
+
+                while let Some(frame) = parser.next_frame() {
+                    prop_assert!(frame.len() <= max_frame_len);
+                    prop_assert!(frame.starts_with(&[0xFF, SOI]));
+                    prop_assert!(frame.ends_with(&[0xFF, EOI]));
+                }
+            }
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(
+            bytes in prop::collection::vec(any::<u8>(), 0..4096),
+        ) {
+            let mut parser = JpegParser::new(1024);
+            parser.push(&bytes);
+            prop_assert!(parser.frame.len() <= 1024);
+            while let Some(frame) = parser.next_frame() {
+                prop_assert!(frame.starts_with(&[0xFF, SOI]));
+                prop_assert!(frame.ends_with(&[0xFF, EOI]));
+            }
+        }
+    }
+}
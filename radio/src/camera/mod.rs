@@ -1,9 +1,57 @@
+pub mod adaptive;
+pub mod avi;
 pub mod capture;
+pub mod h264;
+pub mod jpeg;
+pub mod recorder;
+pub mod settings;
 pub mod streaming;
+pub mod viewers;
+pub mod websocket;
 
-use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::sync::{broadcast, watch};
 
 pub use capture::CameraCapture;
 pub use streaming::MjpegStreamer;
 
-pub type FrameBuffer = Arc<Mutex<Option<Vec<u8>>>>;
+/// A captured JPEG. Cloning shares the image data.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Counts up from 1, across capture restarts
+    pub seq: u64,
+    pub captured_at: SystemTime,
+    pub jpeg: Bytes,
+}
+
+impl Frame {
+    /// Capture time in Unix milliseconds
+    pub fn timestamp_ms(&self) -> u128 {
+        self.captured_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    }
+}
+
+/// The latest frame, `None` while the camera is stopped. Each subscriber is woken once per new
+/// frame and skips the ones it was too slow to take.
+pub type FrameBuffer = watch::Sender<Option<Frame>>;
+
+/// An encoded H.264 frame, one access unit in Annex-B format.
+#[derive(Debug, Clone)]
+pub struct H264Frame {
+    /// Shares the counter of `Frame::seq`
+    pub seq: u64,
+    pub captured_at: SystemTime,
+    /// Decoding can start here
+    pub keyframe: bool,
+    pub dimensions: (u16, u16),
+    pub data: Bytes,
+}
+
+/// Every H.264 frame, in order. Unlike JPEGs they depend on each other, so a subscriber that
+/// falls behind has to wait for the next keyframe.
+pub type H264Buffer = broadcast::Sender<H264Frame>;
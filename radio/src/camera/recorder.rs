@@ -0,0 +1,525 @@
+//! Onboard recording of the camera feed, so a run can be reviewed even when the live stream
+//! stuttered.
+//!
+//! A run is written as MJPEG AVI segments, each next to a `.pts` file with the capture time of
+//! every frame in the `rpicam-vid --save-pts` format, which the `playback` camera backend
+//! reads. A new segment starts when the current one reaches its size limit or the resolution
+//! changes, and the oldest files are deleted to keep the recordings within their budget. Only
+//! the MJPEG codec can be recorded.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+use log::{error, info, warn};
+use serde::Serialize;
+use telemetry::VideoCodec;
+use tokio::{
+    sync::{Mutex, mpsc, watch},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    camera::{
+        CameraCapture, Frame, FrameBuffer,
+        avi::AviWriter,
+        jpeg,
+        viewers::{Viewer, Viewers},
+    },
+    config::RecordingSettings,
+};
+
+/// Frames waiting for the disk before newer ones are dropped
+const FRAME_QUEUE: usize = 30;
+const MB: u64 = 1024 * 1024;
+
+/// A file in the recordings directory.
+#[derive(Serialize, Debug, Clone)]
+pub struct RecordingInfo {
+    pub name: String,
+    pub bytes: u64,
+    /// Unix seconds
+    pub modified: u64,
+}
+
+#[derive(Clone)]
+pub struct Recorder {
+    settings: RecordingSettings,
+    frame_buffer: FrameBuffer,
+    camera_capture: CameraCapture,
+    viewers: Viewers,
+    active: Arc<Mutex<Option<ActiveRecording>>>,
+}
+
+struct ActiveRecording {
+    run: String,
+    cancel: CancellationToken,
+    writer: JoinHandle<Result<()>>,
+}
+
+impl Recorder {
+    /// Records the frames `camera_capture` writes to `frame_buffer`, counting as a viewer of it
+    /// while recording.
+    pub fn new(
+        settings: RecordingSettings,
+        frame_buffer: FrameBuffer,
+        camera_capture: CameraCapture,
+        viewers: Viewers,
+    ) -> Self {
+        Self {
+            settings,
+            frame_buffer,
+            camera_capture,
+            viewers,
+            active: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Starts recording a run and returns its name, which prefixes its files.
+    pub async fn start(&self) -> Result<String> {
+        let mut active = self.active.lock().await;
+        if let Some(recording) = &*active {
+            bail!("Already recording {}", recording.run);
+        }
+        // H.264 frames bypass the frame buffer, which would leave the recording empty
+        if self.camera_capture.codec() == VideoCodec::H264 {
+            bail!("Recording needs the MJPEG codec, but the camera is set to H.264");
+        }
+
+        fs::create_dir_all(&self.settings.dir).with_context(|| {
+            format!(
+                "Failed to create recordings directory {}",
+                self.settings.dir.display()
+            )
+        })?;
+
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let run = format!("run-{started}");
+        let viewer = self.viewers.join().await?;
+
+        let (tx, rx) = mpsc::channel(FRAME_QUEUE);
+        let mut frames = self.frame_buffer.subscribe();
+        frames.mark_unchanged();
+        let cancel = CancellationToken::new();
+        tokio::spawn(forward_frames(frames, tx, cancel.clone(), viewer));
+
+        let settings = self.settings.clone();
+        let writer_run = run.clone();
+        let writer = tokio::task::spawn_blocking(move || write_run(&settings, &writer_run, rx));
+
+        info!("Recording {run} to {}", self.settings.dir.display());
+        *active = Some(ActiveRecording {
+            run: run.clone(),
+            cancel,
+            writer,
+        });
+        Ok(run)
+    }
+
+    /// Stops the current recording once its files are complete, returning its name.
+    pub async fn stop(&self) -> Result<Option<String>> {
+        let Some(recording) = self.active.lock().await.take() else {
+            return Ok(None);
+        };
+
+        recording.cancel.cancel();
+        recording
+            .writer
+            .await
+            .context("Recording writer panicked")?
+            .with_context(|| format!("Recording {} failed", recording.run))?;
+
+        info!("Recording {} stopped", recording.run);
+        Ok(Some(recording.run))
+    }
+
+    /// The run being recorded, if any.
+    pub async fn current(&self) -> Option<String> {
+        let mut active = self.active.lock().await;
+        // the writer only ends early when it fails
+        if active.as_ref().is_some_and(|r| r.writer.is_finished()) {
+            let recording = active.take()?;
+            match recording.writer.await {
+                Ok(Err(e)) => error!("Recording {} failed: {e:#}", recording.run),
+                Err(e) => error!("Recording writer panicked: {e}"),
+                Ok(Ok(())) => {}
+            }
+            return None;
+        }
+        active.as_ref().map(|r| r.run.clone())
+    }
+
+    /// Recording files, oldest first.
+    pub fn list(&self) -> Result<Vec<RecordingInfo>> {
+        list_recordings(&self.settings.dir)
+    }
+
+    /// Path of the recording file `name`, if there is one.
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        // only plain file names, nothing outside the directory
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return None;
+        }
+        let path = self.settings.dir.join(name);
+        path.is_file().then_some(path)
+    }
+}
+
+/// Feeds new frames to the writer until cancelled, dropping frames when the disk falls behind.
+/// Holding `_viewer` keeps the camera running meanwhile.
+async fn forward_frames(
+    mut frames: watch::Receiver<Option<Frame>>,
+    tx: mpsc::Sender<Frame>,
+    cancel: CancellationToken,
+    _viewer: Viewer,
+) {
+    let mut dropped = 0u64;
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            changed = frames.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let Some(frame) = frames.borrow_and_update().clone() else {
+                    continue;
+                };
+                match tx.try_send(frame) {
+                    Ok(()) => {}
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        dropped += 1;
+                        if dropped.is_power_of_two() {
+                            warn!("Recording can't keep up, {dropped} frames dropped");
+                        }
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => break,
+                }
+            }
+        }
+    }
+}
+
+fn write_run(
+    settings: &RecordingSettings,
+    run: &str,
+    mut frames: mpsc::Receiver<Frame>,
+) -> Result<()> {
+    let segment_bytes = settings.segment_mb * MB;
+    // room for the segment about to be started
+    let budget = (settings.max_total_mb * MB).saturating_sub(segment_bytes);
+    let mut segment: Option<Segment> = None;
+    let mut segments = 0;
+
+    while let Some(frame) = frames.blocking_recv() {
+        let Some(dimensions) = jpeg::dimensions(&frame.jpeg) else {
+            warn!("Not recording a frame without a JPEG header");
+            continue;
+        };
+
+        if let Some(full) = segment
+            .take_if(|s| s.dimensions != dimensions || s.avi.bytes_written() >= segment_bytes)
+        {
+            full.finish()?;
+        }
+
+        let current = match &mut segment {
+            Some(current) => current,
+            None => {
+                enforce_budget(&settings.dir, budget)?;
+                let name = format!("{run}-{segments:03}");
+                segments += 1;
+                segment.insert(Segment::create(&settings.dir, &name, dimensions, &frame)?)
+            }
+        };
+        current.write(&frame)?;
+    }
+
+    if let Some(segment) = segment {
+        segment.finish()?;
+    }
+    Ok(())
+}
+
+/// One AVI file of a run and its timestamps.
+struct Segment {
+    name: String,
+    dimensions: (u16, u16),
+    avi: AviWriter,
+    pts: BufWriter<File>,
+    first_at: SystemTime,
+    last_at: SystemTime,
+}
+
+impl Segment {
+    fn create(dir: &Path, name: &str, (width, height): (u16, u16), first: &Frame) -> Result<Self> {
+        let avi = AviWriter::create(&dir.join(format!("{name}.avi")), width, height)?;
+        let pts_path = dir.join(format!("{name}.pts"));
+        let mut pts = BufWriter::new(
+            File::create(&pts_path)
+                .with_context(|| format!("Failed to create {}", pts_path.display()))?,
+        );
+        writeln!(pts, "# timecode format v2").context("Failed to write timestamps")?;
+
+        info!("Recording segment {name} at {width}x{height}");
+        Ok(Self {
+            name: name.to_string(),
+            dimensions: (width, height),
+            avi,
+            pts,
+            first_at: first.captured_at,
+            last_at: first.captured_at,
+        })
+    }
+
+    fn write(&mut self, frame: &Frame) -> Result<()> {
+        let since_start = frame
+            .captured_at
+            .duration_since(self.first_at)
+            .unwrap_or_default();
+        writeln!(self.pts, "{:.3}", since_start.as_secs_f64() * 1000.0)
+            .context("Failed to write timestamps")?;
+        self.avi.write_frame(&frame.jpeg)?;
+        self.last_at = frame.captured_at;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.pts.flush().context("Failed to write timestamps")?;
+        let duration = self
+            .last_at
+            .duration_since(self.first_at)
+            .unwrap_or_default();
+        self.avi.finish(duration)?;
+        info!("Finished recording segment {}", self.name);
+        Ok(())
+    }
+}
+
+fn list_recordings(dir: &Path) -> Result<Vec<RecordingInfo>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to list {}", dir.display()));
+        }
+    };
+
+    let mut recordings = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to list {}", dir.display()))?;
+        let metadata = entry.metadata()?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !metadata.is_file() || !(name.ends_with(".avi") || name.ends_with(".pts")) {
+            continue;
+        }
+        recordings.push(RecordingInfo {
+            name,
+            bytes: metadata.len(),
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs()),
+        });
+    }
+    // runs are named by their start time
+    recordings.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(recordings)
+}
+
+/// Deletes the oldest segments, earlier ones of the current run included, until all of the
+/// recordings fit in `budget` bytes. A segment's video and timestamps go together.
+fn enforce_budget(dir: &Path, budget: u64) -> Result<()> {
+    let recordings = list_recordings(dir)?;
+    let mut total: u64 = recordings.iter().map(|r| r.bytes).sum();
+
+    let stem = |name: &str| {
+        name.rsplit_once('.')
+            .map_or(name, |(stem, _)| stem)
+            .to_string()
+    };
+    let mut deleting = None;
+    for recording in recordings {
+        let segment = stem(&recording.name);
+        if total <= budget && deleting.as_ref() != Some(&segment) {
+            break;
+        }
+        fs::remove_file(dir.join(&recording.name))
+            .with_context(|| format!("Failed to delete old recording {}", recording.name))?;
+        info!(
+            "Deleted {} to stay within the recording budget",
+            recording.name
+        );
+        total -= recording.bytes;
+        deleting = Some(segment);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use telemetry::CameraSettings;
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::config::CameraBackend;
+
+    /// Bytes of each frame, so three of them fill a 1 MB segment
+    const FRAME_LEN: usize = 400_000;
+
+    fn recording_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("radio-rec-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A JPEG as far as `jpeg::dimensions` is concerned, padded out to `len` bytes.
+    fn frame(seq: u64, (width, height): (u16, u16), len: usize) -> Frame {
+        let [w1, w0] = width.to_be_bytes();
+        let [h1, h0] = height.to_be_bytes();
+        let mut jpeg = vec![
+            0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 8, h1, h0, w1, w0, 1, 1, 0x11, 0,
+        ];
+        jpeg.resize(len - 2, 0);
+        jpeg.extend([0xFF, 0xD9]);
+        Frame {
+            seq,
+            captured_at: UNIX_EPOCH + Duration::from_millis(seq * 50),
+            jpeg: jpeg.into(),
+        }
+    }
+
+    fn record(settings: &RecordingSettings, run: &str, frames: Vec<Frame>) {
+        let (tx, rx) = mpsc::channel(frames.len());
+        for frame in frames {
+            tx.try_send(frame).unwrap();
+        }
+        drop(tx);
+        write_run(settings, run, rx).unwrap();
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        list_recordings(dir)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.name)
+            .collect()
+    }
+
+    #[test]
+    fn segments_rotate_on_size_and_resolution() {
+        let dir = recording_dir("rotate");
+        let settings = RecordingSettings {
+            dir: dir.clone(),
+            segment_mb: 1,
+            max_total_mb: 100,
+        };
+
+        let mut frames: Vec<Frame> = (0..5).map(|seq| frame(seq, (64, 64), FRAME_LEN)).collect();
+        frames.push(frame(5, (128, 96), 1_000));
+        // not a JPEG, so skipped
+        frames.push(Frame {
+            jpeg: vec![0; 16].into(),
+            ..frame(6, (128, 96), 1_000)
+        });
+        record(&settings, "run-1", frames);
+
+        assert_eq!(
+            names(&dir),
+            [
+                "run-1-000.avi",
+                "run-1-000.pts",
+                "run-1-001.avi",
+                "run-1-001.pts",
+                "run-1-002.avi",
+                "run-1-002.pts",
+            ]
+        );
+        // the first segment filled up after three frames, and each one's times start at 0
+        let pts = |name| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(
+            pts("run-1-000.pts"),
+            "# timecode format v2\n0.000\n50.000\n100.000\n"
+        );
+        assert_eq!(
+            pts("run-1-001.pts"),
+            "# timecode format v2\n0.000\n50.000\n"
+        );
+        assert_eq!(pts("run-1-002.pts"), "# timecode format v2\n0.000\n");
+
+        let avi = fs::read(dir.join("run-1-002.avi")).unwrap();
+        assert_eq!(&avi[64..72], [128, 0, 0, 0, 96, 0, 0, 0]);
+    }
+
+    #[test]
+    fn oldest_segments_are_deleted_to_stay_in_budget() {
+        let dir = recording_dir("budget");
+        // 2 MB for finished recordings, leaving room for the segment being written
+        let settings = RecordingSettings {
+            dir: dir.clone(),
+            segment_mb: 1,
+            max_total_mb: 3,
+        };
+        fs::write(dir.join("run-1-000.avi"), vec![0; 1_500_000]).unwrap();
+        fs::write(dir.join("run-1-000.pts"), "# timecode format v2\n").unwrap();
+        fs::write(dir.join("run-2-000.avi"), vec![0; 1_000_000]).unwrap();
+        fs::write(dir.join("notes.txt"), "not a recording").unwrap();
+
+        let frames = (0..9).map(|seq| frame(seq, (64, 64), FRAME_LEN)).collect();
+        record(&settings, "run-3", frames);
+
+        // run-1 went to make room for the first segment, run-2 for the second and the
+        // run's own first segment for its third
+        assert_eq!(
+            names(&dir),
+            [
+                "run-3-001.avi",
+                "run-3-001.pts",
+                "run-3-002.avi",
+                "run-3-002.pts",
+            ]
+        );
+        assert!(dir.join("notes.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn h264_cannot_be_recorded() {
+        let dir = recording_dir("h264");
+        let (frame_buffer, _) = watch::channel(None);
+        let (h264_buffer, _) = broadcast::channel(1);
+        let (_, settings_rx) = watch::channel(CameraSettings {
+            codec: VideoCodec::H264,
+            ..CameraSettings::default()
+        });
+        let capture = CameraCapture::new(
+            frame_buffer.clone(),
+            h264_buffer,
+            CameraBackend::TestPattern,
+            settings_rx,
+        );
+        let viewers = Viewers::new(capture.clone(), Duration::from_secs(1));
+        let settings = RecordingSettings {
+            dir: dir.clone(),
+            segment_mb: 1,
+            max_total_mb: 100,
+        };
+        let recorder = Recorder::new(settings, frame_buffer, capture.clone(), viewers.clone());
+
+        let error = recorder.start().await.unwrap_err();
+        assert!(error.to_string().contains("MJPEG"), "{error:#}");
+        assert_eq!(recorder.current().await, None);
+        assert_eq!(viewers.count(), 0);
+        assert!(!capture.is_running().unwrap());
+    }
+}
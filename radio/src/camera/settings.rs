@@ -0,0 +1,106 @@
+use anyhow::Result;
+use log::info;
+use telemetry::{CameraSettings, ExposureMode, VideoCodec};
+use tokio::sync::Mutex;
+
+use crate::config::ConfigManager;
+
+const MIN_WIDTH: u16 = 64;
+const MAX_WIDTH: u16 = 1920;
+const MIN_HEIGHT: u16 = 64;
+const MAX_HEIGHT: u16 = 1080;
+const MAX_FPS: u8 = 60;
+
+/// Clamps `requested` to what the camera and encoder support. The result is what the capture
+/// actually runs with.
+pub fn effective(requested: &CameraSettings) -> CameraSettings {
+    // the ISP wants even dimensions
+    let even = |v: u16| v & !1;
+
+    CameraSettings {
+        width: even(requested.width.clamp(MIN_WIDTH, MAX_WIDTH)),
+        height: even(requested.height.clamp(MIN_HEIGHT, MAX_HEIGHT)),
+        fps: requested.fps.clamp(1, MAX_FPS),
+        quality: requested.quality.clamp(1, 100),
+        // the sensor can only flip, so 180 is the only rotation on offer
+        rotation: if requested.rotation % 360 == 180 {
+            180
+        } else {
+            0
+        },
+        ..requested.clone()
+    }
+}
+
+/// `rpicam-vid` arguments for a stream on stdout in the configured codec.
+pub fn rpicam_args(settings: &CameraSettings) -> Vec<String> {
+    let exposure = match settings.exposure {
+        ExposureMode::Normal => "normal",
+        ExposureMode::Sport => "sport",
+        ExposureMode::Short => "short",
+        ExposureMode::Long => "long",
+    };
+
+    let mut args: Vec<String> = [
+        "-t",
+        "0",
+        "--width",
+        &settings.width.to_string(),
+        "--height",
+        &settings.height.to_string(),
+        "--framerate",
+        &settings.fps.to_string(),
+        "--rotation",
+        &settings.rotation.to_string(),
+        "--exposure",
+        exposure,
+        "--nopreview",
+    ]
+    .map(str::to_string)
+    .into();
+    let fps = settings.fps.to_string();
+    let quality = settings.quality.to_string();
+    let codec_args: &[&str] = match settings.codec {
+        VideoCodec::Mjpeg => &["--codec", "mjpeg", "--quality", &quality],
+        // parameter sets before every keyframe and a keyframe every second, so a viewer can
+        // join at any time; `--flush` hands each frame over as soon as it is encoded
+        VideoCodec::H264 => &[
+            "--codec",
+            "h264",
+            "--inline",
+            "--intra",
+            &fps,
+            "--profile",
+            "baseline",
+            "--flush",
+        ],
+    };
+    args.extend(codec_args.iter().map(|arg| arg.to_string()));
+    if settings.hflip {
+        args.push("--hflip".to_string());
+    }
+    if settings.vflip {
+        args.push("--vflip".to_string());
+    }
+    args.extend(["-o".to_string(), "-".to_string()]);
+    args
+}
+
+/// Persists new camera settings, which restarts the capture with them, and returns the
+/// effective settings.
+pub async fn apply(
+    config_manager: &Mutex<ConfigManager>,
+    requested: CameraSettings,
+) -> Result<CameraSettings> {
+    let settings = effective(&requested);
+    if settings != requested {
+        info!("Adjusted requested camera settings {requested:?} to {settings:?}");
+    }
+
+    config_manager
+        .lock()
+        .await
+        .update_camera(settings.clone())
+        .await?;
+    Ok(settings)
+}
@@ -1,71 +1,475 @@
-use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
-};
+use std::{sync::Arc, time::Duration};
 
 use anyhow::{Error, Result};
 use axum::{
-    Router,
+    Json, Router,
     body::{Body, Bytes},
-    extract::Query,
-    http::{Response, StatusCode},
+    extract::{Path, Query, ws::WebSocketUpgrade},
+    http::{
+        HeaderMap, HeaderValue, Method, Response, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+    },
     response::IntoResponse,
     routing::get,
 };
 use log::info;
-use serde::Deserialize;
-use tokio::net::TcpListener;
-use tokio_stream::{StreamExt, wrappers::IntervalStream};
-use tower_http::cors::{Any, CorsLayer};
+use serde::{Deserialize, Serialize};
+use telemetry::{CameraSettings, ControlMessage, LinkQuality, VideoQuality};
+use tokio::{
+    net::TcpSocket,
+    sync::{Mutex as AsyncMutex, broadcast, watch},
+};
+use tokio_stream::{StreamExt, wrappers::WatchStream};
+use tokio_util::io::ReaderStream;
+use tower_http::cors::CorsLayer;
+
+use crate::{
+    camera::{Frame, FrameBuffer, H264Buffer, settings},
+    config::{CameraBackend, ConfigManager},
+};
+
+use super::{
+    CameraCapture,
+    adaptive::{AdaptiveQuality, DriverLink, RequestedCamera, StreamStats},
+    recorder::Recorder,
+    viewers::Viewers,
+    websocket,
+};
+
+/// How long the camera keeps running after the last viewer leaves, so a reconnecting viewer
+/// does not wait for it to start up again
+const VIEWER_GRACE: Duration = Duration::from_secs(10);
+
+const PART_FOOTER: &[u8] = b"\r\n";
 
-use crate::camera::FrameBuffer;
+/// Kernel send buffer of each client. Small, so a client on a slow link backs up into skipped
+/// frames, which adaptive quality reacts to, rather than seconds of video queued in the kernel.
+const CLIENT_SEND_BUFFER: u32 = 256 * 1024;
 
-use super::CameraCapture;
+/// H.264 frames a WebSocket viewer may fall behind by before it skips to the next keyframe
+const H264_QUEUE: usize = 60;
+
+/// How long `/snapshot` waits for a frame, enough for the camera to start
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the cockpit's webview loads from: Tauri on Linux and macOS, on Windows and Android,
+/// and the `bun dev` server
+const COCKPIT_ORIGINS: [&str; 4] = [
+    "tauri://localhost",
+    "http://tauri.localhost",
+    "https://tauri.localhost",
+    "http://localhost:5173",
+];
+
+/// Multipart headers for `frame`, with its sequence number and capture time in Unix
+/// milliseconds.
+fn part_header(frame: &Frame) -> String {
+    format!(
+        "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\nX-Frame-Seq: {}\r\nX-Timestamp: {}\r\n\r\n",
+        frame.jpeg.len(),
+        frame.seq,
+        frame.timestamp_ms()
+    )
+}
 
 #[derive(Deserialize)]
 struct StreamQuery {
     action: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct SnapshotQuery {
+    /// Wait for a frame captured after the request rather than returning the latest
+    #[serde(default)]
+    wait_for_new: bool,
+}
+
+#[derive(Serialize)]
+struct StreamStatus {
+    status: &'static str,
+    viewers: usize,
+    fps: f32,
+    uptime_secs: u64,
+}
+
+#[derive(Serialize)]
+struct RecordingStatus {
+    /// The run being recorded
+    recording: Option<String>,
+}
+
 pub struct MjpegStreamer {
     frame_buffer: FrameBuffer,
+    h264_buffer: H264Buffer,
     camera_capture: CameraCapture,
+    viewers: Viewers,
+    recorder: Recorder,
+    stream_stats: StreamStats,
+    requested: RequestedCamera,
+    quality_tx: watch::Sender<Option<VideoQuality>>,
+    /// Taken by `start`, which runs it
+    adaptive: Option<AdaptiveQuality>,
+    config_manager: Arc<AsyncMutex<ConfigManager>>,
+}
+
+/// Lets the request through if it carries `Authorization: Bearer <token>` with the session token
+/// of a paired client, or if the car doesn't require pairing.
+async fn authorize(
+    config_manager: &AsyncMutex<ConfigManager>,
+    headers: &HeaderMap,
+) -> Result<(), Response<Body>> {
+    let config_manager = config_manager.lock().await;
+    if !config_manager.get_settings().pairing.required {
+        return Ok(());
+    }
+
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token.and_then(|token| config_manager.find_session(token)) {
+        Some(_) => Ok(()),
+        None => Err((StatusCode::UNAUTHORIZED, "Pair with the car first").into_response()),
+    }
+}
+
+/// The latest frame, or with `wait_for_new` the next one captured. Counts as a viewer while
+/// waiting, so a stopped camera starts for it.
+async fn snapshot(
+    viewers: &Viewers,
+    frame_buffer: &FrameBuffer,
+    wait_for_new: bool,
+) -> Response<Body> {
+    let mut frames = frame_buffer.subscribe();
+    let _viewer = match viewers.join().await {
+        Ok(viewer) => viewer,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to start camera: {}", e),
+            )
+                .into_response();
+        }
+    };
+
+    if !wait_for_new {
+        frames.mark_changed();
+    }
+    let frame = tokio::time::timeout(SNAPSHOT_TIMEOUT, async {
+        loop {
+            frames.changed().await.ok()?;
+            if let Some(frame) = frames.borrow_and_update().clone() {
+                return Some(frame);
+            }
+        }
+    })
+    .await;
+
+    match frame {
+        Ok(Some(frame)) => Response::builder()
+            .header("Content-Type", "image/jpeg")
+            .header("Cache-Control", "no-store")
+            .header("X-Frame-Seq", frame.seq)
+            .header("X-Timestamp", frame.timestamp_ms().to_string())
+            .body(Body::from(frame.jpeg))
+            .unwrap()
+            .into_response(),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, "No frame from the camera").into_response(),
+    }
 }
 
 impl MjpegStreamer {
-    pub async fn new() -> Result<Self> {
-        let frame_buffer = Arc::new(Mutex::new(None));
-        let camera_capture = CameraCapture::new(frame_buffer.clone());
+    /// Streams from `backend`, adapting the video quality to the driver's link in `link_rx`
+    /// and their input in `control_rx`.
+    pub async fn new(
+        backend: CameraBackend,
+        config_manager: Arc<AsyncMutex<ConfigManager>>,
+        link_rx: watch::Receiver<LinkQuality>,
+        control_rx: broadcast::Receiver<ControlMessage>,
+    ) -> Result<Self> {
+        let (frame_buffer, _) = watch::channel(None);
+        let (h264_buffer, _) = broadcast::channel(H264_QUEUE);
+        let (requested_rx, recording, adaptive_video) = {
+            let config_manager = config_manager.lock().await;
+            let settings = config_manager.get_settings();
+            (
+                config_manager.subscribe_camera(),
+                settings.recording.clone(),
+                settings.adaptive_video.clone(),
+            )
+        };
+        let mut requested = RequestedCamera::new(requested_rx);
+        let (camera_tx, settings_rx) = watch::channel(requested.settings());
+        let camera_capture = CameraCapture::new(
+            frame_buffer.clone(),
+            h264_buffer.clone(),
+            backend,
+            settings_rx,
+        );
+        let viewers = Viewers::new(camera_capture.clone(), VIEWER_GRACE);
+        let recorder = Recorder::new(
+            recording,
+            frame_buffer.clone(),
+            camera_capture.clone(),
+            viewers.clone(),
+        );
+        let stream_stats = StreamStats::default();
+        let (quality_tx, _) = watch::channel(None);
+        let adaptive = AdaptiveQuality::new(
+            adaptive_video,
+            stream_stats.clone(),
+            camera_capture.clone(),
+            DriverLink::new(link_rx, control_rx),
+            requested.clone(),
+            camera_tx,
+            quality_tx.clone(),
+        );
 
         let streamer = Self {
             frame_buffer,
+            h264_buffer,
             camera_capture,
+            viewers,
+            recorder,
+            stream_stats,
+            requested,
+            quality_tx,
+            adaptive: Some(adaptive),
+            config_manager,
         };
 
         Ok(streamer)
     }
 
+    /// Handle for starting and stopping onboard recordings from elsewhere.
+    pub fn recorder(&self) -> Recorder {
+        self.recorder.clone()
+    }
+
+    /// The adaptive video quality in effect, for telemetry.
+    pub fn subscribe_video_quality(&self) -> watch::Receiver<Option<VideoQuality>> {
+        self.quality_tx.subscribe()
+    }
+
     pub async fn start(&mut self) -> Result<()> {
+        if let Some(adaptive) = self.adaptive.take() {
+            tokio::spawn(adaptive.run());
+        }
         self.start_http_server().await?;
         info!("MJPEG streamer started on http://0.0.0.0:8081/stream");
 
         Ok(())
     }
 
-    async fn start_http_server(&self) -> Result<()> {
+    /// The HTTP API over this streamer's camera and recorder.
+    fn router(&self) -> Router {
         let frame_buffer = self.frame_buffer.clone();
         let camera_capture = self.camera_capture.clone();
+        let viewers = self.viewers.clone();
+        let snapshot_viewers = self.viewers.clone();
+        let snapshot_frame_buffer = self.frame_buffer.clone();
+        let video_viewers = self.viewers.clone();
+        let video_capture = self.camera_capture.clone();
+        let video_frame_buffer = self.frame_buffer.clone();
+        let video_h264_buffer = self.h264_buffer.clone();
+        let video_requested = self.requested.clone();
+        let video_stream_stats = self.stream_stats.clone();
+        let stream_stats = self.stream_stats.clone();
+        let recorder = self.recorder.clone();
+        let control_recorder = self.recorder.clone();
+        let list_recorder = self.recorder.clone();
+        let download_recorder = self.recorder.clone();
+        let config_manager = self.config_manager.clone();
+        let update_config_manager = self.config_manager.clone();
+        let recording_config_manager = self.config_manager.clone();
+        let control_config_manager = self.config_manager.clone();
+        let list_config_manager = self.config_manager.clone();
+        let download_config_manager = self.config_manager.clone();
+
+        Router::new()
+            .route(
+                "/camera/settings",
+                get(move || {
+                    let config_manager = config_manager.clone();
+                    async move {
+                        let camera = config_manager.lock().await.get_config().camera.clone();
+                        Json(settings::effective(&camera))
+                    }
+                })
+                .put(
+                    move |headers: HeaderMap, Json(requested): Json<CameraSettings>| {
+                        let config_manager = update_config_manager.clone();
+                        async move {
+                            if let Err(denied) = authorize(&config_manager, &headers).await {
+                                return denied;
+                            }
+                            match settings::apply(&config_manager, requested).await {
+                                Ok(effective) => Json(effective).into_response(),
+                                Err(e) => (
+                                    StatusCode::INTERNAL_SERVER_ERROR,
+                                    format!("Failed to apply camera settings: {}", e),
+                                )
+                                    .into_response(),
+                            }
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/recording",
+                get(move |headers: HeaderMap| {
+                    let recorder = recorder.clone();
+                    let config_manager = recording_config_manager.clone();
+                    async move {
+                        if let Err(denied) = authorize(&config_manager, &headers).await {
+                            return denied;
+                        }
+                        Json(RecordingStatus {
+                            recording: recorder.current().await,
+                        })
+                        .into_response()
+                    }
+                })
+                .post(
+                    move |headers: HeaderMap, Query(query): Query<StreamQuery>| {
+                        let recorder = control_recorder.clone();
+                        let config_manager = control_config_manager.clone();
+                        async move {
+                            if let Err(denied) = authorize(&config_manager, &headers).await {
+                                return denied;
+                            }
+                            match query.action.as_deref() {
+                                Some("start") => match recorder.start().await {
+                                    Ok(run) => {
+                                        (StatusCode::OK, format!("Recording {run}")).into_response()
+                                    }
+                                    Err(e) => (
+                                        StatusCode::CONFLICT,
+                                        format!("Failed to start recording: {e:#}"),
+                                    )
+                                        .into_response(),
+                                },
+                                Some("stop") => match recorder.stop().await {
+                                    Ok(Some(run)) => {
+                                        (StatusCode::OK, format!("Recorded {run}")).into_response()
+                                    }
+                                    Ok(None) => (StatusCode::OK, "Not recording").into_response(),
+                                    Err(e) => (
+                                        StatusCode::INTERNAL_SERVER_ERROR,
+                                        format!("Failed to stop recording: {e:#}"),
+                                    )
+                                        .into_response(),
+                                },
+                                _ => (StatusCode::BAD_REQUEST, "Expected ?action=start or stop")
+                                    .into_response(),
+                            }
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/recordings",
+                get(move |headers: HeaderMap| {
+                    let recorder = list_recorder.clone();
+                    let config_manager = list_config_manager.clone();
+                    async move {
+                        if let Err(denied) = authorize(&config_manager, &headers).await {
+                            return denied;
+                        }
+                        match recorder.list() {
+                            Ok(recordings) => Json(recordings).into_response(),
+                            Err(e) => (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                format!("Failed to list recordings: {e:#}"),
+                            )
+                                .into_response(),
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/recordings/{name}",
+                get(move |headers: HeaderMap, Path(name): Path<String>| {
+                    let recorder = download_recorder.clone();
+                    let config_manager = download_config_manager.clone();
+                    async move {
+                        if let Err(denied) = authorize(&config_manager, &headers).await {
+                            return denied;
+                        }
+                        let Some(path) = recorder.path(&name) else {
+                            return (StatusCode::NOT_FOUND, "No such recording").into_response();
+                        };
+                        let file = match tokio::fs::File::open(&path).await {
+                            Ok(file) => file,
+                            Err(e) => {
+                                return (
+                                    StatusCode::INTERNAL_SERVER_ERROR,
+                                    format!("Failed to open recording: {e}"),
+                                )
+                                    .into_response();
+                            }
+                        };
 
-        let app = Router::new()
+                        let content_type = if name.ends_with(".avi") {
+                            "video/x-msvideo"
+                        } else {
+                            "text/plain"
+                        };
+                        Response::builder()
+                            .header("Content-Type", content_type)
+                            .header(
+                                "Content-Disposition",
+                                format!("attachment; filename=\"{name}\""),
+                            )
+                            .body(Body::from_stream(ReaderStream::new(file)))
+                            .unwrap()
+                            .into_response()
+                    }
+                }),
+            )
+            .route(
+                "/snapshot",
+                get(move |Query(query): Query<SnapshotQuery>| {
+                    let viewers = snapshot_viewers.clone();
+                    let frame_buffer = snapshot_frame_buffer.clone();
+                    async move { snapshot(&viewers, &frame_buffer, query.wait_for_new).await }
+                }),
+            )
+            .route(
+                "/video",
+                get(move |ws: WebSocketUpgrade| {
+                    let viewers = video_viewers.clone();
+                    let camera_capture = video_capture.clone();
+                    let frame_buffer = video_frame_buffer.clone();
+                    let h264_buffer = video_h264_buffer.clone();
+                    let stream_stats = video_stream_stats.clone();
+                    let requested = video_requested.clone();
+                    async move {
+                        ws.on_upgrade(move |socket| {
+                            websocket::serve(
+                                socket,
+                                viewers,
+                                camera_capture,
+                                frame_buffer,
+                                h264_buffer,
+                                stream_stats,
+                                requested,
+                            )
+                        })
+                    }
+                }),
+            )
             .route(
                 "/stream",
                 get(move |Query(query): Query<StreamQuery>| {
                     let frame_buffer = frame_buffer.clone();
                     let camera_capture = camera_capture.clone();
+                    let viewers = viewers.clone();
+                    let stream_stats = stream_stats.clone();
 
                     async move {
                         match query.action.as_deref() {
-                            Some("start") => match camera_capture.start().await {
+                            Some("start") => match viewers.warm_up().await {
                                 Ok(_) => (StatusCode::OK, "Camera started").into_response(),
                                 Err(e) => (
                                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -73,6 +477,11 @@ impl MjpegStreamer {
                                 )
                                     .into_response(),
                             },
+                            Some("stop") if viewers.count() > 0 => (
+                                StatusCode::CONFLICT,
+                                format!("Camera still has {} viewers", viewers.count()),
+                            )
+                                .into_response(),
                             Some("stop") => match camera_capture.stop().await {
                                 Ok(_) => (StatusCode::OK, "Camera stopped").into_response(),
                                 Err(e) => (
@@ -83,9 +492,16 @@ impl MjpegStreamer {
                             },
                             Some("status") => match camera_capture.is_running() {
                                 Ok(running) => {
-                                    let status = if running { "Running" } else { "Stopped" };
-                                    (StatusCode::OK, format!("Camera status: {}", status))
-                                        .into_response()
+                                    let stats = camera_capture.stats();
+                                    Json(StreamStatus {
+                                        status: if running { "Running" } else { "Stopped" },
+                                        viewers: viewers.count(),
+                                        fps: stats.fps,
+                                        uptime_secs: stats
+                                            .started_at
+                                            .map_or(0, |started_at| started_at.elapsed().as_secs()),
+                                    })
+                                    .into_response()
                                 }
                                 Err(e) => (
                                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -94,44 +510,38 @@ impl MjpegStreamer {
                                     .into_response(),
                             },
                             None | Some(_) => {
-                                if let Err(e) = camera_capture.start().await {
-                                    return (
-                                        StatusCode::INTERNAL_SERVER_ERROR,
-                                        format!("Failed to start camera: {}", e),
-                                    )
-                                        .into_response();
-                                }
-
-                                let stream = IntervalStream::new(tokio::time::interval(
-                                    Duration::from_millis(33),
-                                ))
-                                .then(move |_| {
-                                    let buffer = frame_buffer.clone();
-                                    async move {
-                                        if let Ok(frame_lock) = buffer.lock() {
-                                            if let Some(frame) = &*frame_lock {
-                                                const PART_HEADER: &[u8] = b"--frame\r\nContent-Type: image/jpeg\r\n\r\n";
-                                                const PART_FOOTER: &[u8] = b"\r\n";
-
-                                                let mut data = Vec::with_capacity(
-                                                    PART_HEADER.len()
-                                                        + frame.len()
-                                                        + PART_FOOTER.len(),
-                                                );
-                                                data.extend_from_slice(PART_HEADER);
-                                                data.extend_from_slice(frame);
-                                                data.extend_from_slice(PART_FOOTER);
-
-                                                Some(Ok::<_, Error>(Bytes::from(data)))
-                                            } else {
-                                                None
-                                            }
-                                        } else {
-                                            None
-                                        }
+                                let viewer = match viewers.join().await {
+                                    Ok(viewer) => viewer,
+                                    Err(e) => {
+                                        return (
+                                            StatusCode::INTERNAL_SERVER_ERROR,
+                                            format!("Failed to start camera: {}", e),
+                                        )
+                                            .into_response();
                                     }
-                                })
-                                .filter_map(|x| x); // Filter out None values
+                                };
+
+                                let client_stats = stream_stats.client();
+                                // Only frames captured from here on, skipping any this client
+                                // is too slow for
+                                let stream = WatchStream::from_changes(frame_buffer.subscribe())
+                                    .filter_map(|frame| frame)
+                                    .map(move |frame| {
+                                        // The viewer leaves when the client disconnects and
+                                        // the body, with this closure, is dropped
+                                        let _ = &viewer;
+
+                                        client_stats.sent(frame.jpeg.len());
+                                        let header = part_header(&frame);
+                                        let mut data = Vec::with_capacity(
+                                            header.len() + frame.jpeg.len() + PART_FOOTER.len(),
+                                        );
+                                        data.extend_from_slice(header.as_bytes());
+                                        data.extend_from_slice(&frame.jpeg);
+                                        data.extend_from_slice(PART_FOOTER);
+
+                                        Ok::<_, Error>(Bytes::from(data))
+                                    });
 
                                 let body = Body::from_stream(stream);
                                 Response::builder()
@@ -149,16 +559,151 @@ impl MjpegStreamer {
             )
             .layer(
                 CorsLayer::new()
-                    .allow_origin(Any)
-                    .allow_methods(Any)
-                    .allow_headers(Any)
+                    .allow_origin(COCKPIT_ORIGINS.map(HeaderValue::from_static))
+                    .allow_methods([Method::GET, Method::PUT, Method::POST])
+                    .allow_headers([AUTHORIZATION, CONTENT_TYPE])
                     .allow_credentials(false),
-            );
+            )
+    }
 
+    async fn start_http_server(&self) -> Result<()> {
+        let app = self.router();
         let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 8081));
-        let listener = TcpListener::bind(addr).await?;
+        let socket = TcpSocket::new_v4()?;
+        socket.set_reuseaddr(true)?;
+        // inherited by every accepted client
+        socket.set_send_buffer_size(CLIENT_SEND_BUFFER)?;
+        socket.bind(addr)?;
+        let listener = socket.listen(1024)?;
         axum::serve(listener, app).await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, http::Request};
+    use telemetry::VideoCodec;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::camera::jpeg;
+
+    async fn config_manager(name: &str, required: bool) -> AsyncMutex<ConfigManager> {
+        let dir = std::env::temp_dir().join(format!("radio-http-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("radio.toml"),
+            format!("[pairing]\nrequired = {required}\npin = \"123456\"\n"),
+        )
+        .unwrap();
+        let mut config_manager = ConfigManager::with_config_dir(&dir).await.unwrap();
+        config_manager
+            .add_session("pit-wall", "s3cret")
+            .await
+            .unwrap();
+        AsyncMutex::new(config_manager)
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, format!("Bearer {token}").parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn only_paired_sessions_are_authorized() {
+        let config_manager = config_manager("paired", true).await;
+
+        assert!(authorize(&config_manager, &bearer("s3cret")).await.is_ok());
+        for headers in [HeaderMap::new(), bearer("guess"), bearer("")] {
+            let denied = authorize(&config_manager, &headers).await.unwrap_err();
+            assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn anyone_is_authorized_without_pairing() {
+        let config_manager = config_manager("unpaired", false).await;
+        assert!(authorize(&config_manager, &HeaderMap::new()).await.is_ok());
+    }
+
+    /// A streamer over the test pattern at 64x64, or in `codec`.
+    async fn streamer(name: &str, codec: VideoCodec) -> MjpegStreamer {
+        let config_manager = config_manager(name, false).await;
+        let camera = CameraSettings {
+            width: 64,
+            height: 64,
+            fps: 10,
+            codec,
+            ..CameraSettings::default()
+        };
+        config_manager
+            .lock()
+            .await
+            .update_camera(camera)
+            .await
+            .unwrap();
+        let (_, link_rx) = watch::channel(LinkQuality::default());
+        let (_, control_rx) = broadcast::channel(1);
+        MjpegStreamer::new(
+            CameraBackend::TestPattern,
+            Arc::new(config_manager),
+            link_rx,
+            control_rx,
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn get(router: Router, uri: &str) -> Response<Body> {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        router.oneshot(request).await.unwrap()
+    }
+
+    fn frame_seq(response: &Response<Body>) -> u64 {
+        response.headers()["X-Frame-Seq"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn snapshot_starts_the_camera_for_a_jpeg() {
+        let streamer = streamer("snapshot", VideoCodec::Mjpeg).await;
+        assert!(!streamer.camera_capture.is_running().unwrap());
+
+        let response = get(streamer.router(), "/snapshot").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/jpeg");
+        let first = frame_seq(&response);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(jpeg::dimensions(&body), Some((64, 64)));
+
+        // the viewer left with the response, the camera runs on through the grace period
+        assert_eq!(streamer.viewers.count(), 0);
+        assert!(streamer.camera_capture.is_running().unwrap());
+
+        let response = get(streamer.router(), "/snapshot?wait_for_new=true").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(frame_seq(&response) > first);
+        assert_eq!(streamer.viewers.count(), 0);
+
+        streamer.camera_capture.stop().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn snapshot_without_a_frame_is_unavailable() {
+        // the test pattern has no H.264 mode, so no frame ever comes
+        let streamer = streamer("no-snapshot", VideoCodec::H264).await;
+
+        let response = get(streamer.router(), "/snapshot").await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(streamer.viewers.count(), 0);
+
+        streamer.camera_capture.stop().await.unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::{error, info};
+
+use super::CameraCapture;
+
+/// Counts stream viewers so the camera runs only while someone watches: the first viewer starts
+/// it and it stops once nobody has watched for the grace period.
+#[derive(Clone)]
+pub struct Viewers {
+    camera_capture: CameraCapture,
+    grace: Duration,
+    state: Arc<Mutex<ViewerState>>,
+}
+
+#[derive(Default)]
+struct ViewerState {
+    count: usize,
+    /// Bumped whenever the camera gets a reason to keep running, cancelling pending stops
+    generation: u64,
+}
+
+/// A watching viewer, which leaves when this is dropped.
+pub struct Viewer {
+    viewers: Viewers,
+}
+
+impl Viewers {
+    pub fn new(camera_capture: CameraCapture, grace: Duration) -> Self {
+        Self {
+            camera_capture,
+            grace,
+            state: Arc::new(Mutex::new(ViewerState::default())),
+        }
+    }
+
+    /// Adds a viewer, starting the camera for the first one.
+    pub async fn join(&self) -> Result<Viewer> {
+        let first = {
+            let mut state = self.state.lock().unwrap();
+            state.count += 1;
+            state.generation += 1;
+            state.count == 1
+        };
+        let viewer = Viewer {
+            viewers: self.clone(),
+        };
+
+        if first {
+            info!("First viewer joined, starting camera");
+            self.camera_capture.start().await?;
+        }
+        Ok(viewer)
+    }
+
+    /// Starts the camera without a viewer. It stops after the grace period unless one joins.
+    pub async fn warm_up(&self) -> Result<()> {
+        self.state.lock().unwrap().generation += 1;
+        self.camera_capture.start().await?;
+        self.stop_when_idle();
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        self.state.lock().unwrap().count
+    }
+
+    fn leave(&self) {
+        self.state.lock().unwrap().count -= 1;
+        self.stop_when_idle();
+    }
+
+    fn stop_when_idle(&self) {
+        let generation = {
+            let state = self.state.lock().unwrap();
+            if state.count > 0 {
+                return;
+            }
+            state.generation
+        };
+
+        let viewers = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(viewers.grace).await;
+
+            // checked again once no start is under way, or a viewer joining meanwhile would
+            // find the camera running only to have it stopped under them
+            let idle = || {
+                let state = viewers.state.lock().unwrap();
+                state.count == 0 && state.generation == generation
+            };
+            match viewers.camera_capture.stop_if(idle).await {
+                Ok(true) => info!("No viewers for {:?}, stopped camera", viewers.grace),
+                Ok(false) => {}
+                Err(e) => error!("Failed to stop camera: {e:#}"),
+            }
+        });
+    }
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        self.viewers.leave();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use telemetry::CameraSettings;
+    use tokio::sync::{broadcast, watch};
+
+    use super::*;
+    use crate::config::CameraBackend;
+
+    const GRACE: Duration = Duration::from_secs(10);
+
+    fn viewers() -> (Viewers, CameraCapture) {
+        let (frame_buffer, _) = watch::channel(None);
+        let (h264_buffer, _) = broadcast::channel(1);
+        let (_, settings_rx) = watch::channel(CameraSettings {
+            width: 64,
+            height: 64,
+            fps: 10,
+            ..CameraSettings::default()
+        });
+        let capture = CameraCapture::new(
+            frame_buffer,
+            h264_buffer,
+            CameraBackend::TestPattern,
+            settings_rx,
+        );
+        (Viewers::new(capture.clone(), GRACE), capture)
+    }
+
+    fn running(capture: &CameraCapture) -> bool {
+        capture.is_running().unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn camera_stops_a_grace_period_after_the_last_viewer() {
+        let (viewers, capture) = viewers();
+        let first = viewers.join().await.unwrap();
+        let second = viewers.join().await.unwrap();
+        assert!(running(&capture));
+        assert_eq!(viewers.count(), 2);
+
+        drop(first);
+        tokio::time::sleep(GRACE * 2).await;
+        assert!(running(&capture));
+
+        drop(second);
+        assert_eq!(viewers.count(), 0);
+        tokio::time::sleep(GRACE - Duration::from_secs(1)).await;
+        assert!(running(&capture));
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert!(!running(&capture));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejoining_within_the_grace_period_keeps_the_camera() {
+        let (viewers, capture) = viewers();
+        drop(viewers.join().await.unwrap());
+        let started_at = capture.stats().started_at;
+
+        tokio::time::sleep(GRACE / 2).await;
+        let viewer = viewers.join().await.unwrap();
+        // well past when the first viewer's grace period ran out
+        tokio::time::sleep(GRACE * 2).await;
+        assert!(running(&capture));
+        assert_eq!(capture.stats().started_at, started_at);
+
+        drop(viewer);
+        tokio::time::sleep(GRACE + Duration::from_secs(1)).await;
+        assert!(!running(&capture));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn joining_while_the_idle_stop_waits_keeps_the_camera() {
+        let (viewers, capture) = viewers();
+        drop(viewers.join().await.unwrap());
+        let started_at = capture.stats().started_at;
+
+        // the grace period runs out while a start or stop is under way elsewhere
+        let held = capture.hold_lifecycle().await;
+        tokio::time::sleep(GRACE + Duration::from_secs(1)).await;
+        let joining = tokio::spawn({
+            let viewers = viewers.clone();
+            async move { viewers.join().await.unwrap() }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(viewers.count(), 1);
+        drop(held);
+
+        let viewer = joining.await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(running(&capture));
+        assert_eq!(capture.stats().started_at, started_at);
+        drop(viewer);
+    }
+}
@@ -0,0 +1,379 @@
+//! WebSocket video: each frame is a binary message with a small header in front of the JPEG
+//! or H.264 access unit, so the client knows how old every frame is. Text messages carry
+//! control both ways.
+//!
+//! The header is little endian:
+//!
+//! | bytes  | field                                   |
+//! |--------|-----------------------------------------|
+//! | 0      | version, 1                              |
+//! | 1      | header length, 24                       |
+//! | 2..4   | width                                   |
+//! | 4..6   | height                                  |
+//! | 6      | codec, 0 for JPEG and 1 for H.264       |
+//! | 7      | flags, bit 0 set on keyframes           |
+//! | 8..16  | frame sequence number                   |
+//! | 16..24 | capture time in Unix microseconds       |
+//!
+//! H.264 comes in Annex-B format, ready for a WebCodecs `VideoDecoder`. A viewer's first
+//! H.264 frame is always a keyframe carrying the parameter sets, and so is the first after
+//! resuming or falling behind.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::ws::{Message, WebSocket};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::camera::{
+    CameraCapture, Frame, FrameBuffer, H264Buffer, H264Frame,
+    adaptive::{QualityCap, RequestedCamera, StreamStats},
+    jpeg,
+    viewers::{Viewer, Viewers},
+};
+
+const HEADER_VERSION: u8 = 1;
+const HEADER_LEN: usize = 24;
+const CODEC_JPEG: u8 = 0;
+const CODEC_H264: u8 = 1;
+const FLAG_KEYFRAME: u8 = 1;
+
+/// Sent by the client as JSON text messages.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum VideoControl {
+    /// Stop sending frames, letting the camera stop if nobody else watches
+    Pause,
+    Resume,
+    /// Caps the JPEG quality, 1-100, until this viewer disconnects. There is one camera, so
+    /// every viewer gets the lowest cap in force. H.264 ignores it.
+    Quality {
+        quality: u8,
+    },
+}
+
+/// Sent back as JSON text messages.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum VideoEvent {
+    /// On connecting and after each control message
+    State {
+        paused: bool,
+        /// The JPEG quality asked of the camera, configured or capped
+        quality: u8,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// `frame` as a binary message.
+fn encode(frame: &Frame) -> Vec<u8> {
+    let dimensions = jpeg::dimensions(&frame.jpeg).unwrap_or_default();
+    message(
+        [CODEC_JPEG, FLAG_KEYFRAME],
+        dimensions,
+        frame.seq,
+        frame.captured_at,
+        &frame.jpeg,
+    )
+}
+
+/// `frame` as a binary message.
+fn encode_h264(frame: &H264Frame) -> Vec<u8> {
+    let flags = if frame.keyframe { FLAG_KEYFRAME } else { 0 };
+    message(
+        [CODEC_H264, flags],
+        frame.dimensions,
+        frame.seq,
+        frame.captured_at,
+        &frame.data,
+    )
+}
+
+fn message(
+    [codec, flags]: [u8; 2],
+    (width, height): (u16, u16),
+    seq: u64,
+    captured_at: SystemTime,
+    payload: &[u8],
+) -> Vec<u8> {
+    let captured_us = captured_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+
+    let mut message = Vec::with_capacity(HEADER_LEN + payload.len());
+    message.extend_from_slice(&[HEADER_VERSION, HEADER_LEN as u8]);
+    message.extend_from_slice(&width.to_le_bytes());
+    message.extend_from_slice(&height.to_le_bytes());
+    message.extend_from_slice(&[codec, flags]);
+    message.extend_from_slice(&seq.to_le_bytes());
+    message.extend_from_slice(&captured_us.to_le_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Streams frames to `socket` until the client goes away. Only the latest JPEG is ever
+/// queued, so a slow client skips frames instead of falling behind. H.264 frames are queued
+/// up to a limit, past which the client skips to the next keyframe.
+pub async fn serve(
+    mut socket: WebSocket,
+    viewers: Viewers,
+    camera_capture: CameraCapture,
+    frame_buffer: FrameBuffer,
+    h264_buffer: H264Buffer,
+    stream_stats: StreamStats,
+    mut requested: RequestedCamera,
+) {
+    let mut frames = frame_buffer.subscribe();
+    let mut units = h264_buffer.subscribe();
+    // H.264 can only be decoded from a keyframe on
+    let mut need_keyframe = true;
+    camera_capture.request_keyframe();
+    let mut viewer = match viewers.join().await {
+        Ok(viewer) => Some(viewer),
+        Err(e) => {
+            error!("Failed to start camera: {e:#}");
+            let message = format!("Failed to start camera: {e}");
+            send_event(&mut socket, &VideoEvent::Error { message }).await;
+            return;
+        }
+    };
+    // counted only while frames are wanted
+    let mut client_stats = Some(stream_stats.client());
+    let mut quality_cap = None;
+    info!("WebSocket video viewer connected");
+
+    let quality = requested.settings().quality;
+    if !send_event(
+        &mut socket,
+        &VideoEvent::State {
+            paused: false,
+            quality,
+        },
+    )
+    .await
+    {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            changed = frames.changed(), if viewer.is_some() => {
+                if changed.is_err() {
+                    break;
+                }
+                let Some(frame) = frames.borrow_and_update().clone() else {
+                    continue;
+                };
+                if let Some(stats) = &client_stats {
+                    stats.sent(frame.jpeg.len());
+                }
+                if socket.send(Message::Binary(encode(&frame).into())).await.is_err() {
+                    break;
+                }
+            }
+            unit = units.recv(), if viewer.is_some() => {
+                let unit = match unit {
+                    Ok(unit) => unit,
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!("WebSocket video viewer missed {skipped} H.264 frames");
+                        need_keyframe = true;
+                        camera_capture.request_keyframe();
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if need_keyframe && !unit.keyframe {
+                    continue;
+                }
+                need_keyframe = false;
+                if let Some(stats) = &client_stats {
+                    stats.sent(unit.data.len());
+                }
+                if socket.send(Message::Binary(encode_h264(&unit).into())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+
+                let was_paused = viewer.is_none();
+                let event = match serde_json::from_str::<VideoControl>(&text) {
+                    Ok(control) => {
+                        debug!("WebSocket video control: {control:?}");
+                        control_video(
+                            control,
+                            &mut viewer,
+                            &mut quality_cap,
+                            &viewers,
+                            &mut requested,
+                        )
+                        .await
+                    }
+                    Err(e) => VideoEvent::Error {
+                        message: format!("Invalid control message: {e}"),
+                    },
+                };
+                if viewer.is_none() {
+                    client_stats = None;
+                } else if was_paused {
+                    client_stats = Some(stream_stats.client());
+                    // drop what queued up while paused
+                    units = units.resubscribe();
+                    need_keyframe = true;
+                    camera_capture.request_keyframe();
+                }
+                if !send_event(&mut socket, &event).await {
+                    break;
+                }
+            }
+        }
+    }
+
+    info!("WebSocket video viewer disconnected");
+}
+
+async fn control_video(
+    control: VideoControl,
+    viewer: &mut Option<Viewer>,
+    quality_cap: &mut Option<QualityCap>,
+    viewers: &Viewers,
+    requested: &mut RequestedCamera,
+) -> VideoEvent {
+    match control {
+        VideoControl::Pause => {
+            viewer.take();
+        }
+        VideoControl::Resume if viewer.is_none() => match viewers.join().await {
+            Ok(joined) => *viewer = Some(joined),
+            Err(e) => {
+                return VideoEvent::Error {
+                    message: format!("Failed to start camera: {e}"),
+                };
+            }
+        },
+        VideoControl::Resume => {}
+        VideoControl::Quality { quality } => {
+            quality_cap
+                .get_or_insert_with(|| requested.cap())
+                .set(quality);
+        }
+    }
+
+    VideoEvent::State {
+        paused: viewer.is_none(),
+        quality: requested.settings().quality,
+    }
+}
+
+/// Sends `event`, returning whether the client is still there.
+async fn send_event(socket: &mut WebSocket, event: &VideoEvent) -> bool {
+    let Ok(json) = serde_json::to_string(event) else {
+        return true;
+    };
+    socket.send(Message::Text(json.into())).await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const SEQ: u64 = 0x0102_0304_0506_0708;
+    const CAPTURED_US: u64 = 0x0011_2233_4455_6677;
+
+    fn captured_at() -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(CAPTURED_US)
+    }
+
+    /// The header fields after the dimensions, which every test frame shares.
+    fn seq_and_time() -> [u8; 16] {
+        [
+            0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, // seq
+            0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00, // captured_us
+        ]
+    }
+
+    #[test]
+    fn jpeg_frames_get_a_keyframe_header_with_their_dimensions() {
+        // a baseline SOF0 for 640x480
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x0B, 8, 0x01, 0xE0, 0x02, 0x80, 1, 1, 0x11, 0, 0xFF,
+            0xD9,
+        ];
+        let frame = Frame {
+            seq: SEQ,
+            captured_at: captured_at(),
+            jpeg: jpeg.to_vec().into(),
+        };
+
+        let message = encode(&frame);
+        assert_eq!(message.len(), HEADER_LEN + jpeg.len());
+        assert_eq!(
+            message[..8],
+            [
+                1, 24, // version, header length
+                0x80, 0x02, // width 640
+                0xE0, 0x01, // height 480
+                0, 1, // JPEG, keyframe
+            ]
+        );
+        assert_eq!(message[8..HEADER_LEN], seq_and_time());
+        assert_eq!(message[HEADER_LEN..], jpeg);
+    }
+
+    #[test]
+    fn h264_frames_flag_only_keyframes() {
+        let data = [0, 0, 0, 1, 0x65, 0xAA];
+        let frame = H264Frame {
+            seq: SEQ,
+            captured_at: captured_at(),
+            keyframe: true,
+            dimensions: (1920, 1080),
+            data: data.to_vec().into(),
+        };
+
+        let message = encode_h264(&frame);
+        assert_eq!(message.len(), HEADER_LEN + data.len());
+        assert_eq!(
+            message[..8],
+            [
+                1, 24, // version, header length
+                0x80, 0x07, // width 1920
+                0x38, 0x04, // height 1080
+                1, 1, // H.264, keyframe
+            ]
+        );
+        assert_eq!(message[8..HEADER_LEN], seq_and_time());
+        assert_eq!(message[HEADER_LEN..], data);
+
+        let delta = encode_h264(&H264Frame {
+            keyframe: false,
+            ..frame
+        });
+        assert_eq!(delta[6..8], [1, 0]);
+        assert_eq!(delta[..6], message[..6]);
+        assert_eq!(delta[8..], message[8..]);
+    }
+
+    #[test]
+    fn frames_from_before_the_epoch_are_stamped_zero() {
+        let message = message(
+            [CODEC_JPEG, FLAG_KEYFRAME],
+            (0, 0),
+            0,
+            UNIX_EPOCH - Duration::from_secs(1),
+            &[],
+        );
+        assert_eq!(message.len(), HEADER_LEN);
+        assert_eq!(message[16..24], [0; 8]);
+    }
+}
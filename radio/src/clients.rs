@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+/// What a client may do once connected. Every client receives telemetry and config broadcasts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientRole {
+    /// May claim control of the car
+    #[default]
+    Driver,
+    /// Read-only
+    Spectator,
+    /// May change the car config and list clients, but never drives
+    PitEngineer,
+}
+
+impl ClientRole {
+    pub fn can_drive(self) -> bool {
+        self == ClientRole::Driver
+    }
+}
+
+#[derive(Debug)]
+struct ClientEntry {
+    role: ClientRole,
+    connected_at: Instant,
+    last_seen: Instant,
+}
+
+/// Entry of the `clients` admin reply.
+#[derive(Serialize, Debug)]
+pub struct ClientInfo {
+    pub addr: SocketAddr,
+    pub client_id: Option<String>,
+    pub role: ClientRole,
+    pub driving: bool,
+    pub connected_for_ms: u64,
+    pub last_seen_ms: u64,
+}
+
+/// Every client the radio currently talks to. Clients join implicitly with their first packet
+/// and are dropped after `timeout` without one.
+pub struct ClientRegistry {
+    clients: HashMap<SocketAddr, ClientEntry>,
+    timeout: Duration,
+}
+
+impl ClientRegistry {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            clients: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Records a packet from `client`. Returns true if the client just joined.
+    pub fn touch(&mut self, client: SocketAddr, now: Instant) -> bool {
+        if let Some(entry) = self.clients.get_mut(&client) {
+            entry.last_seen = now;
+            return false;
+        }
+
+        info!("Client connected: {client}");
+        self.clients.insert(
+            client,
+            ClientEntry {
+                role: ClientRole::default(),
+                connected_at: now,
+                last_seen: now,
+            },
+        );
+        true
+    }
+
+    pub fn role(&self, client: SocketAddr) -> ClientRole {
+        self.clients
+            .get(&client)
+            .map_or(ClientRole::default(), |entry| entry.role)
+    }
+
+    pub fn set_role(&mut self, client: SocketAddr, role: ClientRole, now: Instant) {
+        self.touch(client, now);
+        if let Some(entry) = self.clients.get_mut(&client)
+            && entry.role != role
+        {
+            info!("Client {client} is now {role:?}");
+            entry.role = role;
+        }
+    }
+
+    pub fn remove(&mut self, client: SocketAddr) -> bool {
+        let removed = self.clients.remove(&client).is_some();
+        if removed {
+            info!("Client disconnected: {client}");
+        }
+        removed
+    }
+
+    /// Drops clients that went silent and returns them.
+    pub fn expire(&mut self, now: Instant) -> Vec<SocketAddr> {
+        let stale: Vec<SocketAddr> = self
+            .clients
+            .iter()
+            .filter(|(_, entry)| now.saturating_duration_since(entry.last_seen) >= self.timeout)
+            .map(|(addr, _)| *addr)
+            .collect();
+
+        for client in &stale {
+            info!(
+                "Client {client} timed out after {}ms of silence",
+                self.timeout.as_millis()
+            );
+            self.clients.remove(client);
+        }
+        stale
+    }
+
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.clients.keys().copied().collect()
+    }
+
+    pub fn list(
+        &self,
+        now: Instant,
+        driver: Option<SocketAddr>,
+        client_id: impl Fn(SocketAddr) -> Option<String>,
+    ) -> Vec<ClientInfo> {
+        let mut clients: Vec<ClientInfo> = self
+            .clients
+            .iter()
+            .map(|(addr, entry)| ClientInfo {
+                addr: *addr,
+                client_id: client_id(*addr),
+                role: entry.role,
+                driving: driver == Some(*addr),
+                connected_for_ms: now
+                    .saturating_duration_since(entry.connected_at)
+                    .as_millis() as u64,
+                last_seen_ms: now.saturating_duration_since(entry.last_seen).as_millis() as u64,
+            })
+            .collect();
+        clients.sort_by_key(|c| std::cmp::Reverse(c.connected_for_ms));
+        clients
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn addr(host: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, host)), 5000)
+    }
+
+    #[test]
+    fn clients_join_with_their_first_packet() {
+        let mut registry = ClientRegistry::new(TIMEOUT);
+        let t0 = Instant::now();
+
+        assert!(registry.touch(addr(1), t0));
+        assert!(!registry.touch(addr(1), t0));
+        assert!(registry.touch(addr(2), t0));
+        let mut addrs = registry.addrs();
+        addrs.sort();
+        assert_eq!(addrs, vec![addr(1), addr(2)]);
+        assert_eq!(registry.role(addr(1)), ClientRole::Driver);
+    }
+
+    #[test]
+    fn roles_change_on_join() {
+        let mut registry = ClientRegistry::new(TIMEOUT);
+        let t0 = Instant::now();
+
+        // joining also registers an unseen client
+        registry.set_role(addr(1), ClientRole::Spectator, t0);
+        assert_eq!(registry.role(addr(1)), ClientRole::Spectator);
+        assert!(!registry.touch(addr(1), t0));
+
+        registry.set_role(addr(1), ClientRole::PitEngineer, t0);
+        assert_eq!(registry.role(addr(1)), ClientRole::PitEngineer);
+        assert!(!ClientRole::PitEngineer.can_drive());
+        assert!(!ClientRole::Spectator.can_drive());
+        assert!(ClientRole::Driver.can_drive());
+    }
+
+    #[test]
+    fn leaving_forgets_the_role() {
+        let mut registry = ClientRegistry::new(TIMEOUT);
+        let t0 = Instant::now();
+        registry.set_role(addr(1), ClientRole::Spectator, t0);
+
+        assert!(registry.remove(addr(1)));
+        assert!(!registry.remove(addr(1)));
+        assert!(registry.addrs().is_empty());
+        assert_eq!(registry.role(addr(1)), ClientRole::Driver);
+    }
+
+    #[test]
+    fn silent_clients_expire() {
+        let mut registry = ClientRegistry::new(TIMEOUT);
+        let t0 = Instant::now();
+        registry.touch(addr(1), t0);
+        registry.touch(addr(2), t0);
+        registry.touch(addr(2), t0 + Duration::from_secs(3));
+
+        assert!(
+            registry
+                .expire(t0 + TIMEOUT - Duration::from_millis(1))
+                .is_empty()
+        );
+        assert_eq!(registry.expire(t0 + TIMEOUT), vec![addr(1)]);
+        assert_eq!(registry.addrs(), vec![addr(2)]);
+    }
+
+    #[test]
+    fn lists_clients_oldest_first() {
+        let mut registry = ClientRegistry::new(TIMEOUT);
+        let t0 = Instant::now();
+        registry.touch(addr(1), t0);
+        registry.set_role(
+            addr(2),
+            ClientRole::PitEngineer,
+            t0 + Duration::from_secs(1),
+        );
+        registry.touch(addr(1), t0 + Duration::from_secs(2));
+
+        let now = t0 + Duration::from_secs(4);
+        let clients = registry.list(now, Some(addr(1)), |addr| {
+            (addr == self::addr(2)).then(|| "pit".to_string())
+        });
+
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients[0].addr, addr(1));
+        assert!(clients[0].driving);
+        assert_eq!(clients[0].client_id, None);
+        assert_eq!(clients[0].connected_for_ms, 4_000);
+        assert_eq!(clients[0].last_seen_ms, 2_000);
+
+        assert_eq!(clients[1].addr, addr(2));
+        assert_eq!(clients[1].role, ClientRole::PitEngineer);
+        assert_eq!(clients[1].client_id.as_deref(), Some("pit"));
+        assert!(!clients[1].driving);
+    }
+}
@@ -1,13 +1,292 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{Context, Result};
 use log::info;
-use telemetry::CarConfiguration;
-use tokio::fs;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use telemetry::{CameraSettings, CarConfiguration, VideoProfile};
+use tokio::{fs, sync::watch};
+
+use crate::pairing;
+
+const MAX_SESSIONS: usize = 16;
+
+/// Radio-local settings that never leave the Pi (hardware wiring, timings).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RadioSettings {
+    pub uart: UartSettings,
+    pub hardware: HardwareSettings,
+    pub failsafe: FailsafeSettings,
+    pub telemetry: TelemetrySettings,
+    pub pairing: PairingSettings,
+    pub ownership: OwnershipSettings,
+    pub clients: ClientSettings,
+    pub recording: RecordingSettings,
+    pub adaptive_video: AdaptiveVideoSettings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UartSettings {
+    pub enabled: bool,
+    pub device: String,
+    pub baud_rate: u32,
+}
+
+impl Default for UartSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            device: "/dev/serial0".into(),
+            baud_rate: 115_200,
+        }
+    }
+}
+
+/// Which backend stands behind each trait in `hal`. The defaults are the Pi hardware; the
+/// mock and recorded backends give deterministic data on a dev machine.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct HardwareSettings {
+    pub imu: ImuBackend,
+    pub camera: CameraBackend,
+    pub motor: MotorBackend,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum ImuBackend {
+    /// ADXL345 on the Pi's I2C bus
+    #[default]
+    Adxl345,
+    /// A car sitting still
+    Mock,
+    /// Replays `x,y,z` lines in m/s^2 from a CSV file, looping
+    Recorded { path: PathBuf },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum CameraBackend {
+    /// `rpicam-vid` on the Pi camera
+    #[default]
+    Rpicam,
+    /// Generated colour bars with the frame number and time burned in
+    #[serde(alias = "mock")]
+    TestPattern,
+    /// Plays the `.jpg` files in `dir` in name order, looping
+    Recorded { dir: PathBuf },
+    /// Plays a raw MJPEG recording, looping. `pts` is its `rpicam-vid --save-pts` file, without
+    /// one frames play at 30fps
+    Playback {
+        path: PathBuf,
+        #[serde(default)]
+        pts: Option<PathBuf>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum MotorBackend {
+    /// Powertrain MCU over the `uart` settings
+    #[default]
+    Uart,
+    /// Logs commands and drops them
+    Mock,
+    /// Appends every command to a CSV file
+    Recorded { path: PathBuf },
+}
+
+/// Time since the driver's last control packet before each failsafe level kicks in.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FailsafeSettings {
+    pub coast_after_ms: u64,
+    pub brake_after_ms: u64,
+    pub disarm_after_ms: u64,
+}
+
+impl Default for FailsafeSettings {
+    fn default() -> Self {
+        Self {
+            coast_after_ms: 300,
+            brake_after_ms: 1_000,
+            disarm_after_ms: 5_000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TelemetrySettings {
+    /// Telemetry messages per second sent to the connected client, 0 disables them
+    pub rate_hz: u32,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self { rate_hz: 10 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PairingSettings {
+    /// Reject control and config from clients that have not paired
+    pub required: bool,
+    /// Generated on first start when empty
+    pub pin: String,
+}
+
+impl Default for PairingSettings {
+    fn default() -> Self {
+        Self {
+            required: true,
+            pin: String::new(),
+        }
+    }
+}
+
+/// Who may take the wheel from the client currently driving.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OwnershipPolicy {
+    /// The driver keeps control until it releases it or goes silent
+    #[default]
+    Exclusive,
+    /// Like `Exclusive`, but any client may take control with a `takeover` request
+    Takeover,
+    /// Like `Exclusive`, but clients listed in `admins` pre-empt everyone else
+    Priority,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OwnershipSettings {
+    pub policy: OwnershipPolicy,
+    /// Silence from the driver after which control is free to claim
+    pub lock_timeout_ms: u64,
+    /// Paired client ids of marshals allowed to override under `Priority`
+    pub admins: Vec<String>,
+}
+
+impl Default for OwnershipSettings {
+    fn default() -> Self {
+        Self {
+            policy: OwnershipPolicy::default(),
+            lock_timeout_ms: 3_000,
+            admins: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClientSettings {
+    /// Silence after which a client is dropped and stops receiving broadcasts
+    pub liveness_timeout_ms: u64,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            liveness_timeout_ms: 5_000,
+        }
+    }
+}
+
+/// Onboard recordings of the camera feed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RecordingSettings {
+    pub dir: PathBuf,
+    /// A run is split into a new file past this size
+    pub segment_mb: u64,
+    /// The oldest recordings are deleted to keep all of them under this size
+    pub max_total_mb: u64,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        Self {
+            dir: ".f1-car/recordings".into(),
+            segment_mb: 256,
+            max_total_mb: 4_096,
+        }
+    }
+}
+
+/// Steps the camera down a ladder of profiles while video crowds out the link, so control
+/// packets never starve behind it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AdaptiveVideoSettings {
+    pub enabled: bool,
+    /// Caps from best to worst, each a step below the configured camera settings
+    pub ladder: Vec<VideoProfile>,
+    /// Fraction of frames stream clients may miss by falling behind before video counts as
+    /// congested
+    pub max_skipped: f32,
+    /// Fraction of the driver's control packets that may go missing
+    pub max_control_loss: f32,
+    /// Age of the driver's last control packet past which control counts as starved
+    pub max_control_age_ms: u32,
+    /// Congestion lasting this long steps quality down
+    pub step_down_after_secs: u32,
+    /// A healthy link for this long steps quality back up
+    pub step_up_after_secs: u32,
+}
+
+impl Default for AdaptiveVideoSettings {
+    fn default() -> Self {
+        let profile = |width, height, fps, quality| VideoProfile {
+            width,
+            height,
+            fps,
+            quality,
+        };
+        Self {
+            enabled: true,
+            ladder: vec![
+                profile(1280, 720, 30, 80),
+                profile(960, 540, 30, 70),
+                profile(640, 360, 24, 60),
+                profile(480, 270, 15, 50),
+                profile(320, 180, 10, 40),
+            ],
+            max_skipped: 0.2,
+            max_control_loss: 0.05,
+            max_control_age_ms: 500,
+            step_down_after_secs: 2,
+            step_up_after_secs: 15,
+        }
+    }
+}
+
+/// Session tokens handed out by successful pairings, kept across restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PairedSessions {
+    pub sessions: Vec<PairedSession>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PairedSession {
+    pub token: String,
+    pub client_id: String,
+    pub paired_at: u64,
+}
 
 pub struct ConfigManager {
     config_path: PathBuf,
     config: CarConfiguration,
+    camera_tx: watch::Sender<CameraSettings>,
+    settings: RadioSettings,
+    sessions_path: PathBuf,
+    sessions: PairedSessions,
 }
 
 impl ConfigManager {
@@ -18,6 +297,8 @@ impl ConfigManager {
     pub async fn with_config_dir<P: AsRef<Path>>(config_dir: P) -> Result<Self> {
         let config_dir = config_dir.as_ref();
         let config_path = config_dir.join("car_config.toml");
+        let settings_path = config_dir.join("radio.toml");
+        let sessions_path = config_dir.join("sessions.toml");
 
         if !config_dir.exists() {
             fs::create_dir_all(config_dir).await.with_context(|| {
@@ -29,31 +310,34 @@ impl ConfigManager {
             info!("Created config directory at {}", config_dir.display());
         }
 
-        let config = if config_path.exists() {
-            Self::load_config(&config_path).await?
-        } else {
-            info!(
-                "No existing config found, creating default configuration at {}",
-                config_path.display()
-            );
-            let default_config = CarConfiguration::default();
-            Self::save_config(&config_path, &default_config)
-                .await
-                .with_context(|| {
-                    format!("Failed to save default config to {}", config_path.display())
-                })?;
+        let config: CarConfiguration = Self::load_or_create(&config_path).await?;
+        let mut settings: RadioSettings = Self::load_or_create(&settings_path).await?;
+        let sessions: PairedSessions = Self::load_or_create(&sessions_path).await?;
 
-            default_config
-        };
+        if settings.pairing.pin.is_empty() {
+            settings.pairing.pin = pairing::generate_pin();
+            Self::save_config(&settings_path, &settings)
+                .await
+                .context("Failed to save generated pairing PIN")?;
+        }
+        if settings.pairing.required {
+            info!("Pairing PIN: {}", settings.pairing.pin);
+        }
 
         info!(
             "Loaded car config: #{} {} ({})",
             config.number, config.driver_name, config.team_name
         );
 
+        let (camera_tx, _) = watch::channel(config.camera.clone());
+
         Ok(Self {
             config_path,
             config,
+            camera_tx,
+            settings,
+            sessions_path,
+            sessions,
         })
     }
 
@@ -61,6 +345,40 @@ impl ConfigManager {
         &self.config
     }
 
+    /// Follows the camera settings as they are changed, so the capture can restart with them.
+    pub fn subscribe_camera(&self) -> watch::Receiver<CameraSettings> {
+        self.camera_tx.subscribe()
+    }
+
+    pub fn get_settings(&self) -> &RadioSettings {
+        &self.settings
+    }
+
+    pub fn find_session(&self, token: &str) -> Option<&PairedSession> {
+        self.sessions.sessions.iter().find(|s| s.token == token)
+    }
+
+    /// Stores a new session token, replacing any earlier one for the same client.
+    pub async fn add_session(&mut self, client_id: &str, token: &str) -> Result<()> {
+        let paired_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let sessions = &mut self.sessions.sessions;
+        sessions.retain(|s| s.client_id != client_id);
+        sessions.push(PairedSession {
+            token: token.to_string(),
+            client_id: client_id.to_string(),
+            paired_at,
+        });
+        if sessions.len() > MAX_SESSIONS {
+            sessions.sort_by_key(|s| s.paired_at);
+            sessions.drain(..sessions.len() - MAX_SESSIONS);
+        }
+
+        Self::save_config(&self.sessions_path, &self.sessions).await
+    }
+
     pub async fn update_config(&mut self, new_config: CarConfiguration) -> Result<()> {
         info!(
             "Updating car config: #{} {} ({}) -> #{} {} ({})",
@@ -74,22 +392,62 @@ impl ConfigManager {
 
         self.config = new_config;
         Self::save_config(&self.config_path, &self.config).await?;
+        self.publish_camera();
+
+        Ok(())
+    }
+
+    pub async fn update_camera(&mut self, camera: CameraSettings) -> Result<()> {
+        info!("Updating camera settings: {camera:?}");
+
+        self.config.camera = camera;
+        Self::save_config(&self.config_path, &self.config).await?;
+        self.publish_camera();
 
         Ok(())
     }
 
-    async fn load_config(path: &Path) -> Result<CarConfiguration> {
+    fn publish_camera(&self) {
+        let camera = &self.config.camera;
+        self.camera_tx.send_if_modified(|current| {
+            let changed = current != camera;
+            current.clone_from(camera);
+            changed
+        });
+    }
+
+    async fn load_or_create<T>(path: &Path) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        if path.exists() {
+            return Self::load_config(path).await;
+        }
+
+        info!(
+            "No existing config found, creating default configuration at {}",
+            path.display()
+        );
+        let default_config = T::default();
+        Self::save_config(path, &default_config)
+            .await
+            .with_context(|| format!("Failed to save default config to {}", path.display()))?;
+
+        Ok(default_config)
+    }
+
+    async fn load_config<T: DeserializeOwned>(path: &Path) -> Result<T> {
         let content = fs::read_to_string(path)
             .await
             .with_context(|| format!("Failed to read config file: {}", path.display()))?;
 
-        let config: CarConfiguration = toml::from_str(&content)
+        let config: T = toml::from_str(&content)
             .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
 
         Ok(config)
     }
 
-    async fn save_config(path: &Path, config: &CarConfiguration) -> Result<()> {
+    async fn save_config<T: Serialize>(path: &Path, config: &T) -> Result<()> {
         let content = toml::to_string_pretty(config)
             .with_context(|| format!("Failed to serialize config: {}", path.display()))?;
 
@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use telemetry::{ControlMessage, FailsafeState};
+
+use crate::config::FailsafeSettings;
+
+/// Escalates Armed -> Coast -> Brake -> Disarmed as the controlling client's packets go stale.
+///
+/// Time only ever escalates the state. A fresh control packet clears Coast and Brake, but
+/// leaving Disarmed additionally requires the driver to centre the throttle so the car never
+/// lurches forward when a link comes back.
+pub struct Failsafe {
+    settings: FailsafeSettings,
+    state: FailsafeState,
+    last_control: HashMap<SocketAddr, Instant>,
+}
+
+impl Failsafe {
+    pub fn new(settings: FailsafeSettings) -> Self {
+        Self {
+            settings,
+            state: FailsafeState::Disarmed,
+            last_control: HashMap::new(),
+        }
+    }
+
+    pub fn state(&self) -> FailsafeState {
+        self.state
+    }
+
+    pub fn since_last_control(&self, client: SocketAddr, now: Instant) -> Option<Duration> {
+        self.last_control
+            .get(&client)
+            .map(|t| now.saturating_duration_since(*t))
+    }
+
+    /// Records a valid control packet and returns whether it may be forwarded to the motors.
+    pub fn on_control(&mut self, client: SocketAddr, ctrl: &ControlMessage, now: Instant) -> bool {
+        self.last_control.insert(client, now);
+
+        match self.state {
+            FailsafeState::Armed => true,
+            FailsafeState::Coast | FailsafeState::Brake => {
+                info!("Control from {client} resumed, failsafe cleared");
+                self.state = FailsafeState::Armed;
+                true
+            }
+            FailsafeState::Disarmed if ctrl.throttle == 0 => {
+                info!("Neutral throttle from {client}, armed");
+                self.state = FailsafeState::Armed;
+                true
+            }
+            FailsafeState::Disarmed => false,
+        }
+    }
+
+    /// Re-evaluates the state for the controlling client. Returns the new state on a transition.
+    pub fn update(&mut self, active: Option<SocketAddr>, now: Instant) -> Option<FailsafeState> {
+        let elapsed = active
+            .and_then(|client| self.since_last_control(client, now))
+            .unwrap_or(Duration::MAX);
+
+        let level = if elapsed >= Duration::from_millis(self.settings.disarm_after_ms) {
+            FailsafeState::Disarmed
+        } else if elapsed >= Duration::from_millis(self.settings.brake_after_ms) {
+            FailsafeState::Brake
+        } else if elapsed >= Duration::from_millis(self.settings.coast_after_ms) {
+            FailsafeState::Coast
+        } else {
+            FailsafeState::Armed
+        };
+
+        if level <= self.state {
+            return None;
+        }
+
+        match active {
+            Some(client) if elapsed != Duration::MAX => warn!(
+                "Failsafe {:?} -> {:?}: no control from {client} for {}ms",
+                self.state,
+                level,
+                elapsed.as_millis()
+            ),
+            _ => warn!(
+                "Failsafe {:?} -> {:?}: no controlling client",
+                self.state, level
+            ),
+        }
+        self.state = level;
+
+        Some(level)
+    }
+
+    pub fn forget(&mut self, client: SocketAddr) {
+        self.last_control.remove(&client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRIVER: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 9000);
+    const SPECTATOR: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 9001);
+
+    fn ctrl(throttle: i8) -> ControlMessage {
+        ControlMessage {
+            steering: 0,
+            throttle,
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// A failsafe armed by a neutral packet from `DRIVER` at the returned instant.
+    fn armed() -> (Failsafe, Instant) {
+        let mut failsafe = Failsafe::new(FailsafeSettings::default());
+        let t0 = Instant::now();
+        assert!(failsafe.on_control(DRIVER, &ctrl(0), t0));
+        assert_eq!(failsafe.state(), FailsafeState::Armed);
+        (failsafe, t0)
+    }
+
+    #[test]
+    fn starts_disarmed_and_ignores_throttle() {
+        let mut failsafe = Failsafe::new(FailsafeSettings::default());
+        let t0 = Instant::now();
+        assert_eq!(failsafe.state(), FailsafeState::Disarmed);
+
+        assert!(!failsafe.on_control(DRIVER, &ctrl(60), t0));
+        assert_eq!(failsafe.update(Some(DRIVER), t0), None);
+        assert_eq!(failsafe.state(), FailsafeState::Disarmed);
+    }
+
+    #[test]
+    fn escalates_at_each_threshold() {
+        let (mut failsafe, t0) = armed();
+        assert!(failsafe.on_control(DRIVER, &ctrl(80), t0));
+
+        let steps = [
+            (299, None),
+            (300, Some(FailsafeState::Coast)),
+            (999, None),
+            (1_000, Some(FailsafeState::Brake)),
+            (4_999, None),
+            (5_000, Some(FailsafeState::Disarmed)),
+            (60_000, None),
+        ];
+        for (elapsed, transition) in steps {
+            assert_eq!(
+                failsafe.update(Some(DRIVER), t0 + ms(elapsed)),
+                transition,
+                "at {elapsed}ms"
+            );
+        }
+        assert_eq!(failsafe.state(), FailsafeState::Disarmed);
+    }
+
+    #[test]
+    fn skips_levels_after_a_long_gap() {
+        let (mut failsafe, t0) = armed();
+        assert_eq!(
+            failsafe.update(Some(DRIVER), t0 + ms(2_000)),
+            Some(FailsafeState::Brake)
+        );
+    }
+
+    #[test]
+    fn control_clears_coast_and_brake() {
+        for gap in [300, 1_000] {
+            let (mut failsafe, t0) = armed();
+            failsafe.update(Some(DRIVER), t0 + ms(gap));
+            assert_ne!(failsafe.state(), FailsafeState::Armed);
+
+            // any throttle resumes driving, the car was armed a moment ago
+            assert!(failsafe.on_control(DRIVER, &ctrl(50), t0 + ms(gap + 10)));
+            assert_eq!(failsafe.state(), FailsafeState::Armed);
+            assert_eq!(failsafe.update(Some(DRIVER), t0 + ms(gap + 20)), None);
+        }
+    }
+
+    #[test]
+    fn rearming_needs_neutral_throttle() {
+        let (mut failsafe, t0) = armed();
+        failsafe.update(Some(DRIVER), t0 + ms(5_000));
+
+        let back = t0 + ms(6_000);
+        assert!(!failsafe.on_control(DRIVER, &ctrl(50), back));
+        assert!(!failsafe.on_control(DRIVER, &ctrl(-20), back + ms(20)));
+        // fresh packets don't lower the state on their own
+        assert_eq!(failsafe.update(Some(DRIVER), back + ms(30)), None);
+        assert_eq!(failsafe.state(), FailsafeState::Disarmed);
+
+        assert!(failsafe.on_control(DRIVER, &ctrl(0), back + ms(40)));
+        assert_eq!(failsafe.state(), FailsafeState::Armed);
+        assert!(failsafe.on_control(DRIVER, &ctrl(50), back + ms(60)));
+    }
+
+    #[test]
+    fn only_the_driver_keeps_the_car_armed() {
+        let (mut failsafe, t0) = armed();
+        failsafe.on_control(SPECTATOR, &ctrl(0), t0 + ms(250));
+        assert_eq!(
+            failsafe.update(Some(DRIVER), t0 + ms(300)),
+            Some(FailsafeState::Coast)
+        );
+    }
+
+    #[test]
+    fn disarms_without_a_driver() {
+        let (mut failsafe, t0) = armed();
+        assert_eq!(
+            failsafe.update(None, t0 + ms(10)),
+            Some(FailsafeState::Disarmed)
+        );
+
+        let (mut failsafe, t0) = armed();
+        failsafe.forget(DRIVER);
+        assert_eq!(failsafe.since_last_control(DRIVER, t0), None);
+        assert_eq!(
+            failsafe.update(Some(DRIVER), t0 + ms(10)),
+            Some(FailsafeState::Disarmed)
+        );
+    }
+}
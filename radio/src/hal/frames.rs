@@ -0,0 +1,327 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{info, warn};
+
+use telemetry::CameraSettings;
+
+use super::{FrameSource, pattern::TestPattern};
+use crate::{
+    camera::{jpeg::JpegParser, settings},
+    config::CameraBackend,
+};
+
+/// Opens `backend` with the given settings. Recordings can't change resolution, so they only
+/// take the frame rate.
+pub fn open_frame_source(
+    backend: &CameraBackend,
+    settings: &CameraSettings,
+) -> Result<Box<dyn FrameSource>> {
+    let fps = settings.fps.into();
+    let source: Box<dyn FrameSource> = match backend {
+        CameraBackend::Rpicam => Box::new(RpicamSource::spawn(settings)?),
+        CameraBackend::TestPattern => Box::new(TestPattern::new(settings)),
+        CameraBackend::Recorded { dir } => Box::new(RecordedFrames::open(dir, fps)?),
+        CameraBackend::Playback { path, pts } => {
+            Box::new(MjpegPlayback::open(path, pts.as_deref(), fps)?)
+        }
+    };
+    info!("Camera backend: {backend:?}");
+    Ok(source)
+}
+
+/// MJPEG from `rpicam-vid` on the Pi camera. The process is killed when the source is dropped.
+pub struct RpicamSource {
+    child: Child,
+    stdout: ChildStdout,
+    parser: JpegParser,
+}
+
+/// Starts `rpicam-vid` with `settings`, streaming to the returned stdout.
+pub(super) fn spawn_rpicam(settings: &CameraSettings) -> Result<(Child, ChildStdout)> {
+    let mut child = Command::new("rpicam-vid")
+        .args(settings::rpicam_args(settings))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to start rpicam-vid")?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("rpicam-vid has no stdout"))?;
+    Ok((child, stdout))
+}
+
+impl RpicamSource {
+    pub fn spawn(settings: &CameraSettings) -> Result<Self> {
+        let (child, stdout) = spawn_rpicam(settings)?;
+        Ok(Self {
+            child,
+            stdout,
+            parser: JpegParser::default(),
+        })
+    }
+}
+
+impl FrameSource for RpicamSource {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        // EOF - process ended
+        read_frame(&mut self.stdout, &mut self.parser).context("Error reading from camera")
+    }
+}
+
+impl Drop for RpicamSource {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Reads from `reader` until `parser` has a whole frame. `Ok(None)` at the end of the stream.
+fn read_frame(reader: &mut impl Read, parser: &mut JpegParser) -> io::Result<Option<Vec<u8>>> {
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        if let Some(frame) = parser.next_frame() {
+            return Ok(Some(frame));
+        }
+
+        let resyncs = parser.resyncs();
+        match reader.read(&mut chunk)? {
+            0 => return Ok(None),
+            n => parser.push(&chunk[..n]),
+        }
+        if parser.resyncs() > resyncs {
+            warn!(
+                "Lost sync in MJPEG stream, {} resyncs so far",
+                parser.resyncs()
+            );
+        }
+    }
+}
+
+/// Sleeps between frames so generated and recorded sources play at camera speed.
+#[derive(Debug)]
+pub(super) struct Pacer {
+    pub(super) interval: Duration,
+    next_at: Instant,
+}
+
+impl Pacer {
+    pub(super) fn new(fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / fps.max(1),
+            next_at: Instant::now(),
+        }
+    }
+
+    pub(super) fn wait(&mut self) {
+        self.wait_then(self.interval);
+    }
+
+    /// Waits for the current frame's slot and schedules the next one `interval` later.
+    pub(super) fn wait_then(&mut self, interval: Duration) {
+        let now = Instant::now();
+        if self.next_at > now {
+            thread::sleep(self.next_at - now);
+        }
+        // don't try to catch up after a stall
+        self.next_at = self.next_at.max(now) + interval;
+    }
+}
+
+/// Plays a directory of `.jpg` files in name order, looping at the end.
+pub struct RecordedFrames {
+    files: Vec<PathBuf>,
+    next: usize,
+    pacer: Pacer,
+}
+
+impl RecordedFrames {
+    pub fn open(dir: &Path, fps: u32) -> Result<Self> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read frame directory {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| {
+                        ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg")
+                    })
+            })
+            .collect();
+        if files.is_empty() {
+            bail!("No .jpg frames in {}", dir.display());
+        }
+        files.sort();
+
+        info!(
+            "Playing {} recorded frames from {}",
+            files.len(),
+            dir.display()
+        );
+        Ok(Self {
+            files,
+            next: 0,
+            pacer: Pacer::new(fps),
+        })
+    }
+}
+
+impl FrameSource for RecordedFrames {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        self.pacer.wait();
+
+        // one pass over the directory at most, in case none of it is readable any more
+        for _ in 0..self.files.len() {
+            let path = &self.files[self.next];
+            self.next = (self.next + 1) % self.files.len();
+            match std::fs::read(path) {
+                Ok(frame) => return Ok(Some(frame)),
+                Err(e) => warn!("Skipping unreadable frame {}: {e}", path.display()),
+            }
+        }
+        bail!(
+            "None of the {} recorded frames are readable",
+            self.files.len()
+        )
+    }
+}
+
+/// Plays a raw MJPEG recording such as `rpicam-vid --codec mjpeg -o clip.mjpeg`, looping at
+/// the end.
+///
+/// A raw MJPEG stream carries no timing, so frames are paced from the `--save-pts` timestamps
+/// file when one is given and at the configured frame rate otherwise.
+pub struct MjpegPlayback {
+    reader: BufReader<File>,
+    parser: JpegParser,
+    intervals: Vec<Duration>,
+    frame: usize,
+    pacer: Pacer,
+}
+
+impl MjpegPlayback {
+    pub fn open(path: &Path, pts: Option<&Path>, fps: u32) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open MJPEG recording {}", path.display()))?;
+        let intervals = match pts {
+            Some(pts) => {
+                let text = std::fs::read_to_string(pts)
+                    .with_context(|| format!("Failed to read timestamps {}", pts.display()))?;
+                Self::parse_pts(&text)
+                    .with_context(|| format!("Invalid timestamps {}", pts.display()))?
+            }
+            None => Vec::new(),
+        };
+
+        info!(
+            "Playing MJPEG recording {} ({})",
+            path.display(),
+            if intervals.is_empty() {
+                format!("{fps}fps")
+            } else {
+                "recorded timing".to_string()
+            }
+        );
+        Ok(Self {
+            reader: BufReader::new(file),
+            parser: JpegParser::default(),
+            intervals,
+            frame: 0,
+            pacer: Pacer::new(fps),
+        })
+    }
+
+    /// Gaps between frames from a `# timecode format v2` file of millisecond timestamps.
+    pub fn parse_pts(text: &str) -> Result<Vec<Duration>> {
+        let stamps = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("expected one timestamp in ms per line")?;
+
+        Ok(stamps
+            .windows(2)
+            .map(|w| Duration::from_secs_f64((w[1] - w[0]).max(0.0) / 1000.0))
+            .collect())
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        read_frame(&mut self.reader, &mut self.parser).context("Error reading MJPEG recording")
+    }
+}
+
+impl FrameSource for MjpegPlayback {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        let frame = match self.read_frame()? {
+            Some(frame) => frame,
+            None => {
+                // loop back to the start
+                self.reader
+                    .rewind()
+                    .context("Failed to rewind MJPEG recording")?;
+                self.parser = JpegParser::default();
+                self.frame = 0;
+                match self.read_frame()? {
+                    Some(frame) => frame,
+                    None => bail!("No JPEG frames in MJPEG recording"),
+                }
+            }
+        };
+
+        let interval = self
+            .intervals
+            .get(self.frame)
+            .copied()
+            .unwrap_or(self.pacer.interval);
+        self.frame += 1;
+        self.pacer.wait_then(interval);
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("radio-frames-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn skips_unreadable_frames() {
+        let dir = frame_dir("skip");
+        std::fs::write(dir.join("0.jpg"), b"first").unwrap();
+        // reading a directory fails
+        std::fs::create_dir(dir.join("1.jpg")).unwrap();
+        std::fs::write(dir.join("2.jpg"), b"third").unwrap();
+
+        let mut frames = RecordedFrames::open(&dir, 1000).unwrap();
+        assert_eq!(frames.next_frame().unwrap().as_deref(), Some(&b"first"[..]));
+        assert_eq!(frames.next_frame().unwrap().as_deref(), Some(&b"third"[..]));
+        assert_eq!(frames.next_frame().unwrap().as_deref(), Some(&b"first"[..]));
+    }
+
+    #[test]
+    fn gives_up_when_nothing_is_readable() {
+        let dir = frame_dir("unreadable");
+        std::fs::create_dir(dir.join("0.jpg")).unwrap();
+        std::fs::create_dir(dir.join("1.jpg")).unwrap();
+
+        let mut frames = RecordedFrames::open(&dir, 1000).unwrap();
+        assert!(frames.next_frame().is_err());
+    }
+}
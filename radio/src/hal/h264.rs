@@ -0,0 +1,189 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    path::Path,
+    process::{Child, ChildStdout},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+
+use telemetry::CameraSettings;
+
+use super::{
+    H264Source,
+    frames::{MjpegPlayback, Pacer, spawn_rpicam},
+};
+use crate::{
+    camera::h264::{AccessUnit, AnnexBParser},
+    config::CameraBackend,
+};
+
+/// Frames a keyframe request may skip in a recording, in case it has no keyframes at all
+const MAX_KEYFRAME_SKIP: usize = 300;
+
+/// Opens `backend` in H.264 mode. Only the Pi camera and `.h264` recordings can do it.
+pub fn open_h264_source(
+    backend: &CameraBackend,
+    settings: &CameraSettings,
+) -> Result<Box<dyn H264Source>> {
+    let source: Box<dyn H264Source> = match backend {
+        CameraBackend::Rpicam => Box::new(RpicamH264::spawn(settings)?),
+        CameraBackend::Playback { path, pts } => Box::new(H264Playback::open(
+            path,
+            pts.as_deref(),
+            settings.fps.into(),
+        )?),
+        other => bail!("The {other:?} camera backend has no H.264 mode"),
+    };
+    info!("Camera backend: {backend:?}, H.264");
+    Ok(source)
+}
+
+/// H.264 from `rpicam-vid` on the Pi camera. The process is killed when the source is dropped.
+///
+/// `rpicam-vid` can't be asked for a keyframe, so new viewers rely on the one it sends every
+/// second.
+pub struct RpicamH264 {
+    child: Child,
+    stdout: ChildStdout,
+    parser: AnnexBParser,
+}
+
+impl RpicamH264 {
+    pub fn spawn(settings: &CameraSettings) -> Result<Self> {
+        let (child, stdout) = spawn_rpicam(settings)?;
+        Ok(Self {
+            child,
+            stdout,
+            parser: AnnexBParser::default(),
+        })
+    }
+}
+
+impl H264Source for RpicamH264 {
+    fn next_access_unit(&mut self) -> Result<Option<AccessUnit>> {
+        read_unit(&mut self.stdout, &mut self.parser).context("Error reading from camera")
+    }
+}
+
+impl Drop for RpicamH264 {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Reads from `reader` until `parser` has a whole access unit. `Ok(None)` at the end of the
+/// stream.
+fn read_unit(reader: &mut impl Read, parser: &mut AnnexBParser) -> io::Result<Option<AccessUnit>> {
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        if let Some(unit) = parser.next_unit() {
+            return Ok(Some(unit));
+        }
+
+        let resyncs = parser.resyncs();
+        match reader.read(&mut chunk)? {
+            0 => {
+                parser.finish();
+                return Ok(parser.next_unit());
+            }
+            n => parser.push(&chunk[..n]),
+        }
+        if parser.resyncs() > resyncs {
+            warn!(
+                "Lost sync in H.264 stream, {} resyncs so far",
+                parser.resyncs()
+            );
+        }
+    }
+}
+
+/// Plays a raw H.264 recording such as `rpicam-vid --codec h264 --inline -o clip.h264`,
+/// looping at the end. Paced like `MjpegPlayback`.
+///
+/// A keyframe request skips ahead to the next keyframe, as a live encoder would send one.
+pub struct H264Playback {
+    reader: BufReader<File>,
+    parser: AnnexBParser,
+    intervals: Vec<Duration>,
+    frame: usize,
+    pacer: Pacer,
+    skip_to_keyframe: bool,
+}
+
+impl H264Playback {
+    pub fn open(path: &Path, pts: Option<&Path>, fps: u32) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open H.264 recording {}", path.display()))?;
+        let intervals = match pts {
+            Some(pts) => {
+                let text = std::fs::read_to_string(pts)
+                    .with_context(|| format!("Failed to read timestamps {}", pts.display()))?;
+                MjpegPlayback::parse_pts(&text)
+                    .with_context(|| format!("Invalid timestamps {}", pts.display()))?
+            }
+            None => Vec::new(),
+        };
+
+        info!("Playing H.264 recording {}", path.display());
+        Ok(Self {
+            reader: BufReader::new(file),
+            parser: AnnexBParser::default(),
+            intervals,
+            frame: 0,
+            pacer: Pacer::new(fps),
+            skip_to_keyframe: false,
+        })
+    }
+
+    fn read_unit(&mut self) -> Result<AccessUnit> {
+        if let Some(unit) = read_unit(&mut self.reader, &mut self.parser)
+            .context("Error reading H.264 recording")?
+        {
+            return Ok(unit);
+        }
+
+        // loop back to the start
+        self.reader
+            .rewind()
+            .context("Failed to rewind H.264 recording")?;
+        self.parser = AnnexBParser::default();
+        self.frame = 0;
+        read_unit(&mut self.reader, &mut self.parser)
+            .context("Error reading H.264 recording")?
+            .context("No frames in H.264 recording")
+    }
+}
+
+impl H264Source for H264Playback {
+    fn next_access_unit(&mut self) -> Result<Option<AccessUnit>> {
+        let mut unit = self.read_unit()?;
+        self.frame += 1;
+        if self.skip_to_keyframe {
+            self.skip_to_keyframe = false;
+            for _ in 0..MAX_KEYFRAME_SKIP {
+                if unit.keyframe {
+                    break;
+                }
+                unit = self.read_unit()?;
+                self.frame += 1;
+            }
+        }
+
+        let interval = self
+            .intervals
+            .get(self.frame - 1)
+            .copied()
+            .unwrap_or(self.pacer.interval);
+        self.pacer.wait_then(interval);
+        Ok(Some(unit))
+    }
+
+    fn request_keyframe(&mut self) {
+        self.skip_to_keyframe = true;
+    }
+}
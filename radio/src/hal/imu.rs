@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use log::info;
+use telemetry::Vector3;
+
+use super::ImuSource;
+use crate::{accelerometer::Accelerometer, config::ImuBackend};
+
+const EARTH_GRAVITY_MS2: f32 = 9.80665;
+
+pub fn open_imu(backend: &ImuBackend) -> Result<Box<dyn ImuSource>> {
+    let source: Box<dyn ImuSource> = match backend {
+        ImuBackend::Adxl345 => Box::new(Accelerometer::new()?),
+        ImuBackend::Mock => Box::new(MockImu),
+        ImuBackend::Recorded { path } => Box::new(RecordedImu::open(path)?),
+    };
+    info!("IMU backend: {backend:?}");
+    Ok(source)
+}
+
+/// A car sitting still on level ground.
+#[derive(Debug, Default)]
+pub struct MockImu;
+
+impl ImuSource for MockImu {
+    fn read(&mut self) -> Result<Vector3> {
+        Ok(Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: EARTH_GRAVITY_MS2,
+        })
+    }
+}
+
+/// Replays samples from a CSV file of `x,y,z` lines, looping at the end. Blank lines and
+/// lines starting with `#` are skipped.
+#[derive(Debug)]
+pub struct RecordedImu {
+    samples: Vec<Vector3>,
+    next: usize,
+}
+
+impl RecordedImu {
+    pub fn open(path: &Path) -> Result<Self> {
+        let csv = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read IMU recording {}", path.display()))?;
+        let samples = Self::parse(&csv)
+            .with_context(|| format!("Invalid IMU recording {}", path.display()))?;
+
+        info!(
+            "Loaded {} IMU samples from {}",
+            samples.len(),
+            path.display()
+        );
+        Ok(Self { samples, next: 0 })
+    }
+
+    pub fn parse(csv: &str) -> Result<Vec<Vector3>> {
+        let mut samples = Vec::new();
+
+        for (number, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("line {}: expected numbers", number + 1))?;
+            let [x, y, z] = values[..] else {
+                bail!("line {}: expected x,y,z", number + 1);
+            };
+            samples.push(Vector3 { x, y, z });
+        }
+
+        if samples.is_empty() {
+            bail!("no samples");
+        }
+        Ok(samples)
+    }
+}
+
+impl ImuSource for RecordedImu {
+    fn read(&mut self) -> Result<Vector3> {
+        let sample = self.samples[self.next];
+        self.next = (self.next + 1) % self.samples.len();
+        Ok(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3 {
+        Vector3 { x, y, z }
+    }
+
+    #[test]
+    fn parses_samples_around_comments_and_blank_lines() {
+        let csv = "# x,y,z in m/s^2\n0.5,-1,9.8\n\n  1.25 , 0 , 9.75  \n# braking\n-3,0.1,9.81\n";
+        assert_eq!(
+            RecordedImu::parse(csv).unwrap(),
+            [v(0.5, -1.0, 9.8), v(1.25, 0.0, 9.75), v(-3.0, 0.1, 9.81)]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_by_number() {
+        for (csv, expected) in [
+            ("0,0,9.8\n0,x,9.8\n", "line 2: expected numbers"),
+            ("0,0,9.8\n\n0,9.8\n", "line 3: expected x,y,z"),
+            ("0,0,9.8,1\n", "line 1: expected x,y,z"),
+            ("0,,9.8\n", "line 1: expected numbers"),
+            ("# nothing recorded\n\n", "no samples"),
+            ("", "no samples"),
+        ] {
+            let error = RecordedImu::parse(csv).unwrap_err();
+            assert!(
+                format!("{error:#}").starts_with(expected),
+                "{csv:?}: {error:#}"
+            );
+        }
+    }
+
+    #[test]
+    fn replay_loops_at_the_end() {
+        let dir = std::env::temp_dir().join(format!("radio-imu-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("loop.csv");
+        std::fs::write(&path, "1,0,9.8\n2,0,9.8\n").unwrap();
+
+        let mut imu = RecordedImu::open(&path).unwrap();
+        let xs: Vec<f32> = (0..5).map(|_| imu.read().unwrap().x).collect();
+        assert_eq!(xs, [1.0, 2.0, 1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn missing_recordings_fail_to_open() {
+        let path = std::env::temp_dir().join("radio-imu-missing.csv");
+        let error = RecordedImu::open(&path).unwrap_err();
+        assert!(format!("{error:#}").starts_with("Failed to read IMU recording"));
+    }
+}
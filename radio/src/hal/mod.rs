@@ -0,0 +1,52 @@
+//! Hardware behind traits, so the radio runs and can be tested without the Pi.
+//!
+//! Each trait has the Pi hardware as one backend and mock or recorded backends beside it.
+//! `RadioSettings.hardware` picks which one gets built.
+
+pub mod frames;
+pub mod h264;
+pub mod imu;
+pub mod motor;
+pub mod pattern;
+
+use anyhow::Result;
+use telemetry::{ControlMessage, FailsafeState, Vector3};
+
+use crate::camera::h264::AccessUnit;
+
+pub use frames::open_frame_source;
+pub use h264::open_h264_source;
+pub use imu::open_imu;
+
+/// Accelerometer readings in m/s^2 in the car's body frame: x forward, y left, z up.
+pub trait ImuSource: Send {
+    fn read(&mut self) -> Result<Vector3>;
+
+    /// Called once when the poller stops.
+    fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A camera producing complete JPEG frames.
+pub trait FrameSource: Send {
+    /// Blocks until the next frame. `Ok(None)` means the source ended and should be reopened.
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>>;
+}
+
+/// A camera producing H.264, one access unit per frame.
+pub trait H264Source: Send {
+    /// Blocks until the next frame. `Ok(None)` means the source ended and should be reopened.
+    fn next_access_unit(&mut self) -> Result<Option<AccessUnit>>;
+
+    /// Asks for a keyframe soon, for a viewer that just joined. Sources that send one often
+    /// enough anyway can ignore it.
+    fn request_keyframe(&mut self) {}
+}
+
+/// Whatever turns control messages into motion.
+pub trait MotorSink: Send {
+    fn control(&mut self, ctrl: &ControlMessage) -> Result<()>;
+
+    fn failsafe(&mut self, state: FailsafeState) -> Result<()>;
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use telemetry::{ControlMessage, FailsafeState};
+use tokio::sync::{broadcast, watch};
+use tokio_util::sync::CancellationToken;
+
+use super::MotorSink;
+
+/// Feeds the server's control and failsafe streams into `sink` until cancelled, then disarms it.
+pub async fn drive(
+    mut sink: Box<dyn MotorSink>,
+    mut control_rx: broadcast::Receiver<ControlMessage>,
+    mut failsafe_rx: watch::Receiver<FailsafeState>,
+    cancel_token: CancellationToken,
+) {
+    let initial = *failsafe_rx.borrow_and_update();
+    if let Err(e) = sink.failsafe(initial) {
+        error!("Motor sink error: {e:#}");
+    }
+
+    loop {
+        // the server publishes a failsafe change before the control that follows it, and the
+        // powertrain ignores throttle until it has seen the car armed
+        let result = tokio::select! {
+            biased;
+            _ = cancel_token.cancelled() => break,
+            changed = failsafe_rx.changed() => match changed {
+                Ok(()) => sink.failsafe(*failsafe_rx.borrow_and_update()),
+                Err(_) => break,
+            },
+            msg = control_rx.recv() => match msg {
+                Ok(ctrl) => sink.control(&ctrl),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Motor sink lagged, skipped {skipped} control messages");
+                    Ok(())
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+
+        if let Err(e) = result {
+            error!("Motor sink error: {e:#}");
+        }
+    }
+
+    if let Err(e) = sink.failsafe(FailsafeState::Disarmed) {
+        error!("Motor sink error: {e:#}");
+    }
+    debug!("Motor sink stopped");
+}
+
+/// Logs commands and drops them.
+#[derive(Debug, Default)]
+pub struct MockMotor {
+    last_failsafe: Option<FailsafeState>,
+}
+
+impl MotorSink for MockMotor {
+    fn control(&mut self, ctrl: &ControlMessage) -> Result<()> {
+        debug!(
+            "mock motor: steering {} throttle {}",
+            ctrl.steering, ctrl.throttle
+        );
+        Ok(())
+    }
+
+    fn failsafe(&mut self, state: FailsafeState) -> Result<()> {
+        if self.last_failsafe.replace(state) != Some(state) {
+            info!("mock motor: failsafe {state:?}");
+        }
+        Ok(())
+    }
+}
+
+/// Appends every command as `elapsed_ms,kind,steering,throttle` to a CSV file, with failsafe
+/// transitions as `elapsed_ms,failsafe,<state>,`.
+pub struct RecordingMotor {
+    out: BufWriter<File>,
+    started_at: Instant,
+}
+
+impl RecordingMotor {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open motor recording {}", path.display()))?;
+
+        info!("Recording motor commands to {}", path.display());
+        Ok(Self {
+            out: BufWriter::new(file),
+            started_at: Instant::now(),
+        })
+    }
+
+    fn elapsed_ms(&self) -> u128 {
+        self.started_at.elapsed().as_millis()
+    }
+}
+
+impl MotorSink for RecordingMotor {
+    fn control(&mut self, ctrl: &ControlMessage) -> Result<()> {
+        let elapsed = self.elapsed_ms();
+        writeln!(
+            self.out,
+            "{elapsed},control,{},{}",
+            ctrl.steering, ctrl.throttle
+        )
+        .context("Failed to write motor recording")
+    }
+
+    fn failsafe(&mut self, state: FailsafeState) -> Result<()> {
+        let elapsed = self.elapsed_ms();
+        writeln!(self.out, "{elapsed},failsafe,{state:?},")
+            .and_then(|()| self.out.flush())
+            .context("Failed to write motor recording")
+    }
+}
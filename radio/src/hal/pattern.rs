@@ -0,0 +1,189 @@
+//! Generated test card for running the video pipeline without a camera.
+//!
+//! Each frame has colour bars, a bar sweeping across the picture so motion is obvious, and the
+//! frame number and wall-clock time (UTC) burned in, so dropped or stale frames show up on the
+//! cockpit.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use jpeg_encoder::{ColorType, Encoder};
+use telemetry::CameraSettings;
+
+use super::FrameSource;
+use super::frames::Pacer;
+
+/// Characters in the frame label, and cells per glyph with spacing
+const LABEL_CHARS: usize = 19;
+const GLYPH_COLUMNS: usize = 4;
+const BANNER_ROWS: usize = 9;
+
+const BARS: [[u8; 3]; 7] = [
+    [192, 192, 192],
+    [192, 192, 0],
+    [0, 192, 192],
+    [0, 192, 0],
+    [192, 0, 192],
+    [192, 0, 0],
+    [0, 0, 192],
+];
+
+/// 3x5 glyphs, one row per byte with the leftmost pixel in bit 2.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        _ => [0; 5],
+    }
+}
+
+pub struct TestPattern {
+    width: usize,
+    height: usize,
+    quality: u8,
+    text_scale: usize,
+    frame: u64,
+    pixels: Vec<u8>,
+    pacer: Pacer,
+}
+
+impl TestPattern {
+    pub fn new(settings: &CameraSettings) -> Self {
+        let width = settings.width.max(1) as usize;
+        let height = settings.height.max(1) as usize;
+        // label across three quarters of the width, banner at most half the height
+        let text_scale = (width * 3 / 4 / (LABEL_CHARS * GLYPH_COLUMNS))
+            .min(height / 2 / BANNER_ROWS)
+            .max(1);
+
+        Self {
+            width,
+            height,
+            quality: settings.quality.clamp(1, 100),
+            text_scale,
+            frame: 0,
+            pixels: vec![0; width * height * 3],
+            pacer: Pacer::new(settings.fps.into()),
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, w: usize, h: usize, rgb: [u8; 3]) {
+        for row in y..(y + h).min(self.height) {
+            for col in x..(x + w).min(self.width) {
+                let i = (row * self.width + col) * 3;
+                self.pixels[i..i + 3].copy_from_slice(&rgb);
+            }
+        }
+    }
+
+    fn text(&mut self, x: usize, y: usize, text: &str) {
+        let scale = self.text_scale;
+        for (n, c) in text.chars().enumerate() {
+            let left = x + n * GLYPH_COLUMNS * scale;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) != 0 {
+                        self.fill(
+                            left + col * scale,
+                            y + row * scale,
+                            scale,
+                            scale,
+                            [255, 255, 255],
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn render(&mut self) -> Result<Vec<u8>> {
+        let (width, height) = (self.width, self.height);
+        let bar_width = width.div_ceil(BARS.len());
+        for (n, rgb) in BARS.iter().enumerate() {
+            self.fill(n * bar_width, 0, bar_width, height, *rgb);
+        }
+
+        // crosses the frame in about four seconds at 30fps
+        let sweep_width = (width / 40).max(1);
+        let sweep_x = (self.frame as usize * width / 120) % width;
+        self.fill(sweep_x, 0, sweep_width, height, [255, 255, 255]);
+
+        let banner_height = BANNER_ROWS * self.text_scale;
+        let banner_y = height.saturating_sub(banner_height);
+        self.fill(0, banner_y, width, banner_height, [0, 0, 0]);
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let secs = millis / 1000;
+        let label = format!(
+            "#{:06} {:02}:{:02}:{:02}.{:03}",
+            self.frame % 1_000_000,
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60,
+            millis % 1000
+        );
+        self.text(2 * self.text_scale, banner_y + 2 * self.text_scale, &label);
+
+        let mut jpeg = Vec::new();
+        Encoder::new(&mut jpeg, self.quality)
+            .encode(&self.pixels, width as u16, height as u16, ColorType::Rgb)
+            .context("Failed to encode test pattern")?;
+        Ok(jpeg)
+    }
+}
+
+impl FrameSource for TestPattern {
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        self.pacer.wait();
+        let jpeg = self.render()?;
+        self.frame += 1;
+        Ok(Some(jpeg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::jpeg;
+
+    fn pattern(width: u16, height: u16) -> TestPattern {
+        TestPattern::new(&CameraSettings {
+            width,
+            height,
+            fps: 60,
+            ..CameraSettings::default()
+        })
+    }
+
+    #[test]
+    fn frames_are_jpegs_of_the_requested_size() {
+        for (width, height) in [(640, 480), (64, 64), (101, 37)] {
+            let jpeg = pattern(width, height).next_frame().unwrap().unwrap();
+            assert_eq!(jpeg[..2], [0xFF, 0xD8]);
+            assert_eq!(jpeg[jpeg.len() - 2..], [0xFF, 0xD9]);
+            assert_eq!(jpeg::dimensions(&jpeg), Some((width, height)));
+        }
+    }
+
+    #[test]
+    fn consecutive_frames_differ() {
+        let mut pattern = pattern(320, 240);
+        let first = pattern.next_frame().unwrap().unwrap();
+        let second = pattern.next_frame().unwrap().unwrap();
+        assert_ne!(first, second);
+    }
+}
@@ -1,17 +1,39 @@
-use std::thread::JoinHandle;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    thread::JoinHandle,
+};
 
+use anyhow::Result;
 use log::{LevelFilter, error, info};
+use tokio::sync::{Mutex, watch};
 use tokio_util::sync::CancellationToken;
 
 mod accelerometer;
 mod camera;
+mod clients;
 mod config;
 mod discovery;
+mod failsafe;
+mod hal;
+mod ownership;
+mod pairing;
+mod sequence;
 mod server;
+mod simulation;
+mod uart;
 
-use crate::accelerometer::Accelerometer;
+use crate::config::{CameraBackend, ConfigManager, MotorBackend, RadioSettings};
+use crate::discovery::DiscoveryService;
+use crate::hal::motor::{self, MockMotor, RecordingMotor};
+use crate::simulation::SimulatedCar;
+use crate::uart::UartBridge;
 use crate::{camera::MjpegStreamer, server::RadioServer};
 
+const IMU_POLL_MS: u64 = 100;
+const SIMULATION_STEP_MS: u64 = 20;
+const SERVER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 8080);
+
 async fn shutdown_poll(token: CancellationToken, handle: Option<JoinHandle<()>>) {
     token.cancel();
     if let Some(h) = handle {
@@ -19,6 +41,50 @@ async fn shutdown_poll(token: CancellationToken, handle: Option<JoinHandle<()>>)
     }
 }
 
+/// The radio server with the config from `.f1-car`, advertised over mDNS.
+async fn create_server() -> Result<RadioServer> {
+    let config_manager = Arc::new(Mutex::new(ConfigManager::new().await?));
+    let discovery_service = DiscoveryService::new(config_manager.clone())?;
+    let mut server = RadioServer::new(config_manager, SERVER_ADDR).await?;
+    server.set_discovery(discovery_service);
+    Ok(server)
+}
+
+/// Hooks the configured `MotorSink` up to the server's control and failsafe streams.
+fn start_motors(
+    server: &mut RadioServer,
+    settings: &RadioSettings,
+    cancel_token: &CancellationToken,
+) {
+    let sink: Box<dyn hal::MotorSink> = match &settings.hardware.motor {
+        MotorBackend::Uart if !settings.uart.enabled => {
+            info!("UART bridge disabled in radio settings");
+            return;
+        }
+        MotorBackend::Uart => {
+            let (bridge, sink) = UartBridge::new(settings.uart.clone());
+            server.set_powertrain_source(bridge.subscribe_status());
+            tokio::spawn(bridge.run(cancel_token.clone()));
+            Box::new(sink)
+        }
+        MotorBackend::Mock => Box::new(MockMotor::default()),
+        MotorBackend::Recorded { path } => match RecordingMotor::create(path) {
+            Ok(sink) => Box::new(sink),
+            Err(e) => {
+                error!("Failed to start motor recording: {e:#}");
+                return;
+            }
+        },
+    };
+
+    tokio::spawn(motor::drive(
+        sink,
+        server.subscribe_control(),
+        server.subscribe_failsafe(),
+        cancel_token.clone(),
+    ));
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::Builder::from_default_env()
@@ -27,19 +93,14 @@ async fn main() {
 
     info!("Starting F1 Car Radio with UDP Camera Streaming...");
 
-    let accel = match Accelerometer::new() {
-        Ok(a) => {
-            info!("Accelerometer initialized");
-            Some(a)
-        }
-        Err(e) => {
-            error!("Failed to initialize accelerometer: {e}");
-            None
-        }
-    };
+    // --simulate swaps the accelerometer and the powertrain for a virtual car, so the
+    // cockpit <-> radio stack runs on a machine without the Pi hardware
+    let simulate = std::env::args().skip(1).any(|arg| arg == "--simulate");
+    if simulate {
+        info!("Simulation mode: driving a virtual car");
+    }
 
     let cancel_token = CancellationToken::new();
-    let mut accel_opt = accel;
 
     let ctrl_token = cancel_token.clone();
     ctrlc::set_handler(move || {
@@ -48,26 +109,65 @@ async fn main() {
     })
     .expect("Error setting Ctrl-C handler");
 
-    let poll_handle = accel_opt
-        .take()
-        .map(|a| a.start_poller(cancel_token.clone(), 100));
+    let (imu_tx, imu_rx) = watch::channel(None);
 
-    tokio::spawn(async move {
-        match MjpegStreamer::new().await {
-            Ok(mut streamer) => {
-                info!("UDP camera streamer initialized");
-                if let Err(e) = streamer.start().await {
-                    error!("Failed to start UDP streamer: {e}");
-                }
+    match create_server().await {
+        Ok(mut server) => {
+            server.set_imu_source(imu_rx);
+            let settings = server.get_radio_settings().await;
+
+            let mut camera = settings.hardware.camera.clone();
+            if simulate && matches!(camera, CameraBackend::Rpicam) {
+                camera = CameraBackend::TestPattern;
             }
-            Err(e) => {
-                error!("Failed to create UDP streamer: {e}");
+            match MjpegStreamer::new(
+                camera,
+                server.config_manager(),
+                server.subscribe_link(),
+                server.subscribe_control(),
+            )
+            .await
+            {
+                Ok(mut streamer) => {
+                    info!("UDP camera streamer initialized");
+                    server.set_recorder(streamer.recorder());
+                    server.set_video_source(streamer.subscribe_video_quality());
+                    tokio::spawn(async move {
+                        if let Err(e) = streamer.start().await {
+                            error!("Failed to start UDP streamer: {e}");
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to create UDP streamer: {e}");
+                }
             }
-        }
-    });
 
-    match RadioServer::new().await {
-        Ok(server) => {
+            let poll_handle = if simulate {
+                let (powertrain_tx, powertrain_rx) = watch::channel(None);
+                server.set_powertrain_source(powertrain_rx);
+                Some(SimulatedCar::new(server.subscribe_control()).start(
+                    cancel_token.clone(),
+                    SIMULATION_STEP_MS,
+                    imu_tx,
+                    powertrain_tx,
+                ))
+            } else {
+                start_motors(&mut server, &settings, &cancel_token);
+                match hal::open_imu(&settings.hardware.imu) {
+                    Ok(imu) => Some(accelerometer::start_poller(
+                        imu,
+                        cancel_token.clone(),
+                        IMU_POLL_MS,
+                        imu_tx,
+                    )),
+                    Err(e) => {
+                        error!("Failed to initialize accelerometer: {e}");
+                        None
+                    }
+                }
+            };
+
             if let Err(e) = server.run(cancel_token.clone()).await {
                 error!("Radio server error: {e}");
                 shutdown_poll(cancel_token.clone(), poll_handle).await;
@@ -81,7 +181,7 @@ async fn main() {
         }
         Err(e) => {
             error!("Failed to initialize radio server: {e}");
-            shutdown_poll(cancel_token.clone(), poll_handle).await;
+            shutdown_poll(cancel_token.clone(), None).await;
             info!("Shutdown complete (init error), exiting");
 
             std::process::exit(1);
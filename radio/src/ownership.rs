@@ -0,0 +1,325 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::config::{OwnershipPolicy, OwnershipSettings};
+
+/// Outcome of a client trying to drive the car.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    /// The client already held control
+    Held,
+    /// The client now holds control, `previous` lost it
+    Granted { previous: Option<SocketAddr> },
+    /// Someone else holds control
+    Denied { owner: SocketAddr },
+}
+
+#[derive(Debug)]
+struct Owner {
+    addr: SocketAddr,
+    admin: bool,
+    last_seen: Instant,
+}
+
+/// Decides which client drives the car.
+///
+/// The driver holds an exclusive lock that every packet it sends refreshes, but only driving or
+/// changing the car claims it. The lock frees up when the driver releases it or stays silent for
+/// `lock_timeout_ms`; before that, other clients only get control as the policy allows.
+pub struct Ownership {
+    settings: OwnershipSettings,
+    owner: Option<Owner>,
+}
+
+impl Ownership {
+    pub fn new(settings: OwnershipSettings) -> Self {
+        info!("Control ownership policy: {:?}", settings.policy);
+        Self {
+            settings,
+            owner: None,
+        }
+    }
+
+    pub fn owner(&self) -> Option<SocketAddr> {
+        self.owner.as_ref().map(|o| o.addr)
+    }
+
+    pub fn is_admin(&self, client_id: Option<&str>) -> bool {
+        client_id.is_some_and(|id| self.settings.admins.iter().any(|admin| admin == id))
+    }
+
+    /// Handles a packet that drives or changes the car: refreshes the driver's lock, and hands
+    /// a free lock to the sender. Under `Priority` an admin also pre-empts a non-admin driver
+    /// this way.
+    pub fn claim(&mut self, client: SocketAddr, admin: bool, now: Instant) -> Claim {
+        let preempts = |owner: &Owner| {
+            self.settings.policy == OwnershipPolicy::Priority && admin && !owner.admin
+        };
+
+        match self.owner.as_ref() {
+            Some(owner) if owner.addr == client => self.refresh(now),
+            Some(owner) if !self.expired(owner, now) && !preempts(owner) => {
+                Claim::Denied { owner: owner.addr }
+            }
+            _ => self.grant(client, admin, now),
+        }
+    }
+
+    /// Handles any other packet from `client`, which refreshes the lock if `client` holds it.
+    /// Returns whether it does.
+    pub fn keep_alive(&mut self, client: SocketAddr, now: Instant) -> bool {
+        if self.owner() != Some(client) {
+            return false;
+        }
+        self.refresh(now);
+        true
+    }
+
+    /// Handles an explicit `takeover` request.
+    pub fn takeover(&mut self, client: SocketAddr, admin: bool, now: Instant) -> Claim {
+        let allowed = match self.settings.policy {
+            OwnershipPolicy::Exclusive => false,
+            OwnershipPolicy::Takeover => true,
+            OwnershipPolicy::Priority => admin,
+        };
+
+        match self.owner.as_ref() {
+            Some(owner) if owner.addr == client => self.refresh(now),
+            Some(owner) if !allowed && !self.expired(owner, now) => {
+                Claim::Denied { owner: owner.addr }
+            }
+            _ => self.grant(client, admin, now),
+        }
+    }
+
+    /// Frees the lock if `client` holds it. Returns whether it did.
+    pub fn release(&mut self, client: SocketAddr) -> bool {
+        if self.owner() != Some(client) {
+            return false;
+        }
+        info!("Control released by {client}");
+        self.owner = None;
+        true
+    }
+
+    /// Frees the lock of a driver that went silent. Returns that driver.
+    pub fn expire(&mut self, now: Instant) -> Option<SocketAddr> {
+        let owner = self.owner.as_ref()?;
+        if !self.expired(owner, now) {
+            return None;
+        }
+
+        let addr = owner.addr;
+        info!(
+            "Control lock of {addr} expired after {}ms of silence",
+            self.settings.lock_timeout_ms
+        );
+        self.owner = None;
+        Some(addr)
+    }
+
+    fn expired(&self, owner: &Owner, now: Instant) -> bool {
+        now.saturating_duration_since(owner.last_seen)
+            >= Duration::from_millis(self.settings.lock_timeout_ms)
+    }
+
+    fn refresh(&mut self, now: Instant) -> Claim {
+        if let Some(owner) = self.owner.as_mut() {
+            owner.last_seen = now;
+        }
+        Claim::Held
+    }
+
+    fn grant(&mut self, client: SocketAddr, admin: bool, now: Instant) -> Claim {
+        let previous = self
+            .owner
+            .replace(Owner {
+                addr: client,
+                admin,
+                last_seen: now,
+            })
+            .map(|o| o.addr);
+
+        match previous {
+            Some(previous) => info!("Control passed from {previous} to {client}"),
+            None => info!("Control granted to {client}"),
+        }
+        Claim::Granted { previous }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(3_000);
+
+    fn addr(host: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, host)), 5000)
+    }
+
+    fn ownership(policy: OwnershipPolicy) -> Ownership {
+        Ownership::new(OwnershipSettings {
+            policy,
+            lock_timeout_ms: TIMEOUT.as_millis() as u64,
+            admins: vec!["marshal".to_string()],
+        })
+    }
+
+    /// `addr(1)`, a non-admin, driving since the returned instant.
+    fn driven(policy: OwnershipPolicy) -> (Ownership, Instant) {
+        let mut ownership = ownership(policy);
+        let t0 = Instant::now();
+        assert_eq!(
+            ownership.claim(addr(1), false, t0),
+            Claim::Granted { previous: None }
+        );
+        (ownership, t0)
+    }
+
+    #[test]
+    fn first_claim_wins_and_refreshes() {
+        let (mut ownership, t0) = driven(OwnershipPolicy::Exclusive);
+        assert_eq!(ownership.owner(), Some(addr(1)));
+        assert_eq!(ownership.claim(addr(1), false, t0), Claim::Held);
+        assert_eq!(
+            ownership.claim(addr(2), false, t0),
+            Claim::Denied { owner: addr(1) }
+        );
+
+        // every packet from the driver pushes the timeout back
+        let later = t0 + TIMEOUT - Duration::from_millis(1);
+        assert_eq!(ownership.claim(addr(1), false, later), Claim::Held);
+        assert_eq!(ownership.expire(later + Duration::from_millis(1)), None);
+        assert_eq!(ownership.expire(later + TIMEOUT), Some(addr(1)));
+        assert_eq!(ownership.owner(), None);
+    }
+
+    #[test]
+    fn keep_alive_refreshes_only_a_held_lock() {
+        let mut ownership = ownership(OwnershipPolicy::Exclusive);
+        let t0 = Instant::now();
+        assert!(!ownership.keep_alive(addr(1), t0));
+        assert_eq!(ownership.owner(), None);
+
+        let (mut ownership, t0) = driven(OwnershipPolicy::Exclusive);
+        assert!(!ownership.keep_alive(addr(2), t0));
+        let later = t0 + TIMEOUT - Duration::from_millis(1);
+        assert!(ownership.keep_alive(addr(1), later));
+        assert_eq!(ownership.expire(t0 + TIMEOUT), None);
+        assert_eq!(ownership.owner(), Some(addr(1)));
+    }
+
+    #[test]
+    fn silent_driver_loses_the_lock_to_the_next_claim() {
+        let (mut ownership, t0) = driven(OwnershipPolicy::Exclusive);
+        assert_eq!(
+            ownership.claim(addr(2), false, t0 + TIMEOUT),
+            Claim::Granted {
+                previous: Some(addr(1))
+            }
+        );
+    }
+
+    #[test]
+    fn release_frees_the_lock_for_its_holder_only() {
+        let (mut ownership, t0) = driven(OwnershipPolicy::Exclusive);
+        assert!(!ownership.release(addr(2)));
+        assert_eq!(ownership.owner(), Some(addr(1)));
+        assert!(ownership.release(addr(1)));
+        assert_eq!(
+            ownership.claim(addr(2), false, t0),
+            Claim::Granted { previous: None }
+        );
+    }
+
+    #[test]
+    fn exclusive_refuses_takeovers() {
+        let (mut ownership, t0) = driven(OwnershipPolicy::Exclusive);
+        assert_eq!(
+            ownership.takeover(addr(2), false, t0),
+            Claim::Denied { owner: addr(1) }
+        );
+        assert_eq!(
+            ownership.takeover(addr(2), true, t0),
+            Claim::Denied { owner: addr(1) }
+        );
+        // an admin gets no say either
+        assert_eq!(
+            ownership.claim(addr(2), true, t0),
+            Claim::Denied { owner: addr(1) }
+        );
+        assert_eq!(
+            ownership.takeover(addr(2), false, t0 + TIMEOUT),
+            Claim::Granted {
+                previous: Some(addr(1))
+            }
+        );
+    }
+
+    #[test]
+    fn takeover_lets_anyone_take_control() {
+        let (mut ownership, t0) = driven(OwnershipPolicy::Takeover);
+        // but only when asked
+        assert_eq!(
+            ownership.claim(addr(2), false, t0),
+            Claim::Denied { owner: addr(1) }
+        );
+        assert_eq!(
+            ownership.takeover(addr(2), false, t0),
+            Claim::Granted {
+                previous: Some(addr(1))
+            }
+        );
+        assert_eq!(ownership.takeover(addr(2), false, t0), Claim::Held);
+        assert_eq!(
+            ownership.claim(addr(1), false, t0),
+            Claim::Denied { owner: addr(2) }
+        );
+    }
+
+    #[test]
+    fn priority_lets_admins_preempt_others() {
+        let (mut ownership, t0) = driven(OwnershipPolicy::Priority);
+        assert_eq!(
+            ownership.takeover(addr(2), false, t0),
+            Claim::Denied { owner: addr(1) }
+        );
+        // an admin's first packet is enough
+        assert_eq!(
+            ownership.claim(addr(3), true, t0),
+            Claim::Granted {
+                previous: Some(addr(1))
+            }
+        );
+        // and nobody takes it back, admins included
+        assert_eq!(
+            ownership.takeover(addr(1), false, t0),
+            Claim::Denied { owner: addr(3) }
+        );
+        assert_eq!(
+            ownership.claim(addr(4), true, t0),
+            Claim::Denied { owner: addr(3) }
+        );
+        assert_eq!(
+            ownership.takeover(addr(4), true, t0),
+            Claim::Granted {
+                previous: Some(addr(3))
+            }
+        );
+    }
+
+    #[test]
+    fn admins_come_from_settings() {
+        let ownership = ownership(OwnershipPolicy::Priority);
+        assert!(ownership.is_admin(Some("marshal")));
+        assert!(!ownership.is_admin(Some("phone")));
+        assert!(!ownership.is_admin(None));
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use rand::RngCore;
+use sha2::Sha256;
+
+const CHALLENGE_TTL: Duration = Duration::from_secs(30);
+/// Failed attempts from one host before it is locked out
+const MAX_FAILURES: u32 = 5;
+/// Failed attempts from all hosts together per `LOCKOUT`, for a guesser with many addresses
+const MAX_GLOBAL_FAILURES: usize = 20;
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug)]
+struct Challenge {
+    nonce: String,
+    client_id: String,
+    issued_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// PIN challenge/response between the cockpit and the radio.
+///
+/// The client asks for a nonce and answers with `hex(HMAC-SHA256(pin, nonce || client_id))`,
+/// so the PIN itself never crosses the network. A correct answer earns a session token that
+/// can later be presented instead of the PIN. Only addresses that have paired or resumed a
+/// session may drive the car or touch its config.
+///
+/// Failed attempts are counted per host rather than per address, since a new source port
+/// costs nothing, and across all hosts, which caps guessing at `MAX_GLOBAL_FAILURES` a minute.
+pub struct Pairing {
+    pin: String,
+    challenges: HashMap<SocketAddr, Challenge>,
+    failures: HashMap<IpAddr, Failures>,
+    /// When each failed attempt in the last `LOCKOUT` happened, oldest first
+    recent_failures: VecDeque<Instant>,
+    /// Client id each paired address authenticated as
+    paired: HashMap<SocketAddr, String>,
+}
+
+impl Pairing {
+    pub fn new(pin: String) -> Self {
+        Self {
+            pin,
+            challenges: HashMap::new(),
+            failures: HashMap::new(),
+            recent_failures: VecDeque::new(),
+            paired: HashMap::new(),
+        }
+    }
+
+    pub fn is_paired(&self, client: SocketAddr) -> bool {
+        self.paired.contains_key(&client)
+    }
+
+    pub fn client_id(&self, client: SocketAddr) -> Option<&str> {
+        self.paired.get(&client).map(String::as_str)
+    }
+
+    /// Returns the nonce to sign, reusing an unexpired one so a retried request does not
+    /// invalidate a response already in flight.
+    pub fn challenge(
+        &mut self,
+        client: SocketAddr,
+        client_id: &str,
+        now: Instant,
+    ) -> Result<String, String> {
+        self.check_lockout(client, now)?;
+
+        if let Some(existing) = self.challenges.get(&client)
+            && existing.client_id == client_id
+            && now.saturating_duration_since(existing.issued_at) < CHALLENGE_TTL
+        {
+            return Ok(existing.nonce.clone());
+        }
+
+        let nonce = random_hex(16);
+        self.challenges.insert(
+            client,
+            Challenge {
+                nonce: nonce.clone(),
+                client_id: client_id.to_string(),
+                issued_at: now,
+            },
+        );
+        Ok(nonce)
+    }
+
+    /// Checks a challenge response. Returns the client id that paired on success.
+    pub fn verify(
+        &mut self,
+        client: SocketAddr,
+        hmac_hex: &str,
+        now: Instant,
+    ) -> Result<String, String> {
+        self.check_lockout(client, now)?;
+
+        let challenge = self
+            .challenges
+            .remove(&client)
+            .filter(|c| now.saturating_duration_since(c.issued_at) < CHALLENGE_TTL)
+            .ok_or_else(|| "No pending challenge, request a new one".to_string())?;
+
+        let expected = decode_hex(hmac_hex).ok_or_else(|| "Malformed response".to_string())?;
+        let mac = mac_for(&self.pin, &challenge.nonce, &challenge.client_id);
+        if mac.verify_slice(&expected).is_err() {
+            return Err(self.record_failure(client, now));
+        }
+
+        self.failures.remove(&client.ip());
+        Ok(challenge.client_id)
+    }
+
+    /// Marks `client` as authenticated as `client_id` for the rest of its session.
+    pub fn bind(&mut self, client: SocketAddr, client_id: String) {
+        info!("Client {client} paired as {client_id}");
+        self.paired.insert(client, client_id);
+    }
+
+    pub fn new_token() -> String {
+        random_hex(32)
+    }
+
+    fn check_lockout(&mut self, client: SocketAddr, now: Instant) -> Result<(), String> {
+        while self
+            .recent_failures
+            .front()
+            .is_some_and(|&at| now.saturating_duration_since(at) >= LOCKOUT)
+        {
+            self.recent_failures.pop_front();
+        }
+
+        let host_locked_until = match self.failures.get(&client.ip()).and_then(|f| f.locked_until) {
+            Some(until) if now < until => Some(until),
+            Some(_) => {
+                self.failures.remove(&client.ip());
+                None
+            }
+            None => None,
+        };
+        let locked_until = if self.recent_failures.len() >= MAX_GLOBAL_FAILURES {
+            let until = self.recent_failures[0] + LOCKOUT;
+            Some(host_locked_until.map_or(until, |host| host.max(until)))
+        } else {
+            host_locked_until
+        };
+
+        match locked_until {
+            Some(until) => Err(format!(
+                "Too many failed attempts, retry in {}s",
+                until.saturating_duration_since(now).as_secs().max(1)
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn record_failure(&mut self, client: SocketAddr, now: Instant) -> String {
+        self.recent_failures.push_back(now);
+        if self.recent_failures.len() == MAX_GLOBAL_FAILURES {
+            warn!("{MAX_GLOBAL_FAILURES} failed pairing attempts within a minute, pairing paused");
+        }
+
+        let failures = self.failures.entry(client.ip()).or_default();
+        failures.count += 1;
+        warn!(
+            "Pairing attempt from {client} failed ({} of {MAX_FAILURES})",
+            failures.count
+        );
+
+        if failures.count >= MAX_FAILURES {
+            failures.locked_until = Some(now + LOCKOUT);
+            failures.count = 0;
+            return format!("Wrong PIN, locked out for {}s", LOCKOUT.as_secs());
+        }
+        "Wrong PIN".to_string()
+    }
+}
+
+fn mac_for(pin: &str, nonce: &str, client_id: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(pin.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(nonce.as_bytes());
+    mac.update(client_id.as_bytes());
+    mac
+}
+
+/// A random numeric PIN short enough to read off a terminal and type on a phone.
+pub fn generate_pin() -> String {
+    format!("{:06}", rand::rng().next_u32() % 1_000_000)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::rng().fill_bytes(&mut bytes);
+    bytes
+        .iter()
+        .fold(String::with_capacity(len * 2), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    // from_str_radix alone would take a sign, as in "+f"
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const PIN: &str = "422127";
+
+    fn addr(host: u8, port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, host)), port)
+    }
+
+    /// What the cockpit sends back for `nonce`.
+    fn sign(pin: &str, nonce: &str, client_id: &str) -> String {
+        mac_for(pin, nonce, client_id)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Answers a fresh challenge for `client` with `pin`.
+    fn attempt(
+        pairing: &mut Pairing,
+        client: SocketAddr,
+        pin: &str,
+        now: Instant,
+    ) -> Result<String, String> {
+        let nonce = pairing.challenge(client, "phone", now)?;
+        pairing.verify(client, &sign(pin, &nonce, "phone"), now)
+    }
+
+    #[test]
+    fn accepts_the_right_pin() {
+        let mut pairing = Pairing::new(PIN.to_string());
+        let now = Instant::now();
+        assert_eq!(
+            attempt(&mut pairing, addr(2, 5000), PIN, now),
+            Ok("phone".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_or_mismatched_responses() {
+        let mut pairing = Pairing::new(PIN.to_string());
+        let client = addr(2, 5000);
+        let now = Instant::now();
+
+        assert_eq!(
+            attempt(&mut pairing, client, "000000", now),
+            Err("Wrong PIN".to_string())
+        );
+
+        // signed for another client id
+        let nonce = pairing.challenge(client, "phone", now).unwrap();
+        assert!(
+            pairing
+                .verify(client, &sign(PIN, &nonce, "laptop"), now)
+                .is_err()
+        );
+
+        // the challenge is used up by a failed response
+        let nonce = pairing.challenge(client, "phone", now).unwrap();
+        assert!(pairing.verify(client, "zz", now).is_err());
+        assert!(
+            pairing
+                .verify(client, &sign(PIN, &nonce, "phone"), now)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn challenges_expire() {
+        let mut pairing = Pairing::new(PIN.to_string());
+        let client = addr(2, 5000);
+        let now = Instant::now();
+
+        let nonce = pairing.challenge(client, "phone", now).unwrap();
+        assert_eq!(pairing.challenge(client, "phone", now).unwrap(), nonce);
+        let late = now + CHALLENGE_TTL;
+        assert!(
+            pairing
+                .verify(client, &sign(PIN, &nonce, "phone"), late)
+                .is_err()
+        );
+        assert!(
+            pairing
+                .verify(client, &sign(PIN, &nonce, "phone"), now)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn locks_out_a_host_whatever_its_port() {
+        let mut pairing = Pairing::new(PIN.to_string());
+        let now = Instant::now();
+
+        for port in 0..MAX_FAILURES as u16 {
+            assert!(attempt(&mut pairing, addr(2, 5000 + port), "000000", now).is_err());
+        }
+        let locked = attempt(&mut pairing, addr(2, 6000), PIN, now).unwrap_err();
+        assert!(locked.starts_with("Too many failed attempts"), "{locked}");
+        // other hosts can still pair
+        assert!(attempt(&mut pairing, addr(3, 5000), PIN, now).is_ok());
+
+        let later = now + LOCKOUT;
+        assert!(attempt(&mut pairing, addr(2, 6000), PIN, later).is_ok());
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let mut pairing = Pairing::new(PIN.to_string());
+        let client = addr(2, 5000);
+        let now = Instant::now();
+
+        for _ in 0..MAX_FAILURES - 1 {
+            assert!(attempt(&mut pairing, client, "000000", now).is_err());
+        }
+        assert!(attempt(&mut pairing, client, PIN, now).is_ok());
+        assert_eq!(
+            attempt(&mut pairing, client, "000000", now),
+            Err("Wrong PIN".to_string())
+        );
+    }
+
+    #[test]
+    fn limits_guesses_across_hosts() {
+        let mut pairing = Pairing::new(PIN.to_string());
+        let now = Instant::now();
+
+        for i in 0..MAX_GLOBAL_FAILURES {
+            let client = addr(10 + i as u8, 5000);
+            let at = now + Duration::from_secs(i as u64);
+            assert!(attempt(&mut pairing, client, "000000", at).is_err());
+        }
+        let paused = now + Duration::from_secs(MAX_GLOBAL_FAILURES as u64);
+        let locked = attempt(&mut pairing, addr(99, 5000), PIN, paused).unwrap_err();
+        assert!(locked.starts_with("Too many failed attempts"), "{locked}");
+
+        // the oldest failure ages out a minute after it happened
+        assert!(attempt(&mut pairing, addr(99, 5000), PIN, now + LOCKOUT).is_ok());
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("00ff10"), Some(vec![0x00, 0xff, 0x10]));
+        assert_eq!(decode_hex("ABcd"), Some(vec![0xab, 0xcd]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("+f-1"), None);
+        assert_eq!(decode_hex("éé"), None);
+    }
+}
@@ -0,0 +1,165 @@
+use log::{debug, info};
+use telemetry::SeqSample;
+
+/// Packets this far behind the last accepted seq are taken as the sender restarting its
+/// counter rather than as stale, otherwise a restarted joystick would be ignored for ~2^31
+/// packets.
+const RESYNC_WINDOW: u32 = 1_000;
+/// A run of stale packets this long is a restarted counter still inside `RESYNC_WINDOW`, not
+/// reordering. About 200ms of joystick packets at 50Hz.
+const RESYNC_AFTER_STALE: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeqCheck {
+    Accept,
+    Duplicate,
+    Stale,
+}
+
+/// Orders one client's joystick packets by their wrapping u32 sequence number.
+///
+/// Comparison uses serial number arithmetic (RFC 1982), so `0` is newer than `u32::MAX`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SequenceTracker {
+    last_seq: Option<u32>,
+    received: u32,
+    dropped: u32,
+    /// Stale packets in a row
+    stale_run: u32,
+}
+
+impl SequenceTracker {
+    pub fn last_seq(&self) -> Option<u32> {
+        self.last_seq
+    }
+
+    /// Every packet that arrived, accepted or not.
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    /// Packets rejected as stale or duplicate.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Where the sequence stands, for working out loss against a later sample.
+    pub fn sample(&self) -> Option<SeqSample> {
+        Some(SeqSample {
+            last_seq: self.last_seq?,
+            received: self.received,
+        })
+    }
+
+    pub fn check(&mut self, seq: u32) -> SeqCheck {
+        self.received = self.received.wrapping_add(1);
+
+        let Some(last) = self.last_seq else {
+            self.last_seq = Some(seq);
+            return SeqCheck::Accept;
+        };
+
+        let ahead = seq.wrapping_sub(last);
+        let verdict = if ahead == 0 {
+            SeqCheck::Duplicate
+        } else if ahead < u32::MAX / 2 {
+            SeqCheck::Accept
+        } else if last.wrapping_sub(seq) > RESYNC_WINDOW {
+            info!("Joystick seq jumped back from {last} to {seq}, resyncing");
+            SeqCheck::Accept
+        } else if self.stale_run + 1 >= RESYNC_AFTER_STALE {
+            info!("Joystick seq stuck behind {last} at {seq}, resyncing");
+            SeqCheck::Accept
+        } else {
+            SeqCheck::Stale
+        };
+
+        self.stale_run = match verdict {
+            SeqCheck::Stale => self.stale_run + 1,
+            SeqCheck::Accept | SeqCheck::Duplicate => 0,
+        };
+        match verdict {
+            SeqCheck::Accept => self.last_seq = Some(seq),
+            SeqCheck::Duplicate | SeqCheck::Stale => {
+                self.dropped = self.dropped.wrapping_add(1);
+                debug!("Dropped {verdict:?} joystick packet seq={seq} (last {last})");
+            }
+        }
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker_at(seq: u32) -> SequenceTracker {
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.check(seq), SeqCheck::Accept);
+        tracker
+    }
+
+    #[test]
+    fn accepts_the_first_packet_and_newer_ones() {
+        let mut tracker = tracker_at(41);
+        assert_eq!(tracker.check(42), SeqCheck::Accept);
+        // gaps are loss, not a reason to reject
+        assert_eq!(tracker.check(50), SeqCheck::Accept);
+        assert_eq!(tracker.last_seq(), Some(50));
+    }
+
+    #[test]
+    fn rejects_duplicates_and_stale_packets() {
+        let mut tracker = tracker_at(100);
+        assert_eq!(tracker.check(100), SeqCheck::Duplicate);
+        assert_eq!(tracker.check(99), SeqCheck::Stale);
+        assert_eq!(tracker.check(101), SeqCheck::Accept);
+        assert_eq!(tracker.check(100), SeqCheck::Stale);
+
+        assert_eq!(tracker.last_seq(), Some(101));
+        assert_eq!(tracker.received(), 5);
+        assert_eq!(tracker.dropped(), 3);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut tracker = tracker_at(u32::MAX - 1);
+        assert_eq!(tracker.check(u32::MAX), SeqCheck::Accept);
+        assert_eq!(tracker.check(0), SeqCheck::Accept);
+        assert_eq!(tracker.check(1), SeqCheck::Accept);
+        assert_eq!(tracker.check(u32::MAX), SeqCheck::Stale);
+        assert_eq!(tracker.last_seq(), Some(1));
+    }
+
+    #[test]
+    fn resyncs_on_a_jump_back_past_the_window() {
+        let mut tracker = tracker_at(RESYNC_WINDOW + 500);
+        assert_eq!(tracker.check(500), SeqCheck::Stale);
+        assert_eq!(tracker.check(0), SeqCheck::Accept);
+        assert_eq!(tracker.check(1), SeqCheck::Accept);
+        assert_eq!(tracker.last_seq(), Some(1));
+    }
+
+    #[test]
+    fn resyncs_on_a_run_of_stale_packets_inside_the_window() {
+        // a joystick restarting while its old counter was still small
+        let mut tracker = tracker_at(500);
+        for seq in 0..RESYNC_AFTER_STALE - 1 {
+            assert_eq!(tracker.check(seq), SeqCheck::Stale, "seq {seq}");
+        }
+        assert_eq!(tracker.check(RESYNC_AFTER_STALE - 1), SeqCheck::Accept);
+        assert_eq!(tracker.check(RESYNC_AFTER_STALE), SeqCheck::Accept);
+        assert_eq!(tracker.last_seq(), Some(RESYNC_AFTER_STALE));
+    }
+
+    #[test]
+    fn reordering_does_not_resync() {
+        let mut tracker = tracker_at(500);
+        for seq in 501..600 {
+            assert_eq!(tracker.check(seq), SeqCheck::Accept);
+            // every packet arriving twice, the second time late
+            assert_eq!(tracker.check(seq - 1), SeqCheck::Stale);
+        }
+        assert_eq!(tracker.last_seq(), Some(599));
+    }
+}
@@ -1,16 +1,42 @@
 use anyhow::{Context, Result};
 use log::{debug, error, info, trace, warn};
 use serde::{Deserialize, Serialize};
-use std::{io::ErrorKind, net::SocketAddr, sync::Arc, time::Duration};
-use telemetry::{CarConfiguration, ControlMessage};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use telemetry::{
+    CameraSettings, CarConfiguration, CarTelemetry, ControlMessage, FailsafeState, LinkQuality,
+    SeqSample, VideoQuality,
+};
 use tokio::{
     net::UdpSocket,
-    sync::{Mutex, broadcast},
-    time::timeout,
+    sync::{Mutex, broadcast, watch},
+    time::{interval, timeout},
 };
 use tokio_util::sync::CancellationToken;
 
-use crate::{config::ConfigManager, discovery::DiscoveryService};
+use crate::{
+    accelerometer::ImuReading,
+    camera::{recorder::Recorder, settings},
+    clients::{ClientInfo, ClientRegistry, ClientRole},
+    config::{ConfigManager, RadioSettings},
+    discovery::DiscoveryService,
+    failsafe::Failsafe,
+    ownership::{Claim, Ownership},
+    pairing::Pairing,
+    sequence::{SeqCheck, SequenceTracker},
+    uart::PowertrainReport,
+};
+
+const FAILSAFE_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+const POWERTRAIN_STALE_AFTER: Duration = Duration::from_secs(1);
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "type")]
@@ -18,11 +44,56 @@ enum ClientMessage {
     #[serde(rename = "control")]
     Control(ControlMessage),
     #[serde(rename = "config_update")]
-    ConfigUpdate { config: CarConfiguration },
+    ConfigUpdate { config: ConfigUpdate },
     #[serde(rename = "config_request")]
     ConfigRequest,
+    #[serde(rename = "camera_settings")]
+    CameraSettings { settings: CameraSettings },
+    #[serde(rename = "record_start")]
+    RecordStart,
+    #[serde(rename = "record_stop")]
+    RecordStop,
     #[serde(rename = "ping")]
     Ping { timestamp: u64 },
+    #[serde(rename = "pair_request")]
+    PairRequest { client_id: String },
+    #[serde(rename = "pair_response")]
+    PairResponse { hmac: String },
+    #[serde(rename = "resume")]
+    Resume { token: String },
+    #[serde(rename = "takeover")]
+    Takeover,
+    #[serde(rename = "release")]
+    Release,
+    #[serde(rename = "join")]
+    Join { role: ClientRole },
+    #[serde(rename = "list_clients")]
+    ListClients,
+}
+
+/// The car details in a `config_update`. Without `camera` the current camera settings stay,
+/// rather than falling back to the defaults as a `CarConfiguration` would, and with it they
+/// are applied like a `camera_settings` message.
+#[derive(Deserialize, Debug)]
+struct ConfigUpdate {
+    number: u8,
+    driver_name: String,
+    team_name: String,
+    camera: Option<CameraSettings>,
+}
+
+impl ConfigUpdate {
+    /// The updated car details with the `current` camera settings, and the camera settings
+    /// asked for, if any.
+    fn split(self, current: &CarConfiguration) -> (CarConfiguration, Option<CameraSettings>) {
+        let config = CarConfiguration {
+            number: self.number,
+            driver_name: self.driver_name,
+            team_name: self.team_name,
+            camera: current.camera.clone(),
+        };
+        (config, self.camera)
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -32,30 +103,105 @@ enum ServerMessage {
     Config { config: CarConfiguration },
     #[serde(rename = "config_updated")]
     ConfigUpdated { success: bool, message: String },
+    /// `settings` are the ones the camera runs with after clamping
+    #[serde(rename = "camera_settings_updated")]
+    CameraSettingsUpdated {
+        success: bool,
+        message: String,
+        settings: CameraSettings,
+    },
+    /// `recording` is the run now being recorded
+    #[serde(rename = "recording")]
+    Recording {
+        success: bool,
+        message: String,
+        recording: Option<String>,
+    },
     #[serde(rename = "pong")]
-    Pong { timestamp: u64 },
+    Pong {
+        timestamp: u64,
+        /// Highest joystick seq seen from this client, lets it work out packet loss
+        last_seq: Option<u32>,
+        received: u32,
+    },
+    #[serde(rename = "telemetry")]
+    Telemetry(CarTelemetry),
+    #[serde(rename = "pair_challenge")]
+    PairChallenge { nonce: String },
+    #[serde(rename = "paired")]
+    Paired { token: String },
+    #[serde(rename = "pair_failed")]
+    PairFailed { reason: String },
+    #[serde(rename = "unauthorized")]
+    Unauthorized { reason: String },
+    #[serde(rename = "control_granted")]
+    ControlGranted,
+    #[serde(rename = "control_denied")]
+    ControlDenied { reason: String },
+    #[serde(rename = "control_lost")]
+    ControlLost { reason: String },
+    #[serde(rename = "joined")]
+    Joined { role: ClientRole },
+    #[serde(rename = "clients")]
+    Clients { clients: Vec<ClientInfo> },
 }
 
 pub struct RadioServer {
     control_tx: broadcast::Sender<ControlMessage>,
     config_manager: Arc<Mutex<ConfigManager>>,
-    discovery_service: Arc<Mutex<DiscoveryService>>,
-    connected_client: Arc<Mutex<Option<SocketAddr>>>,
+    bind_addr: SocketAddr,
+    discovery_service: Option<Mutex<DiscoveryService>>,
+    ownership: Mutex<Ownership>,
+    clients: Mutex<ClientRegistry>,
+    failsafe: Arc<Mutex<Failsafe>>,
+    failsafe_tx: watch::Sender<FailsafeState>,
+    imu_rx: watch::Receiver<Option<ImuReading>>,
+    powertrain_rx: watch::Receiver<Option<PowertrainReport>>,
+    video_rx: watch::Receiver<Option<VideoQuality>>,
+    link_tx: watch::Sender<LinkQuality>,
+    control_packets: AtomicU32,
+    joystick_seq: Mutex<HashMap<SocketAddr, SequenceTracker>>,
+    /// The driver's joystick sequence at the last telemetry snapshot, for control loss
+    control_seq_sample: Mutex<Option<(SocketAddr, SeqSample)>>,
+    pairing: Mutex<Pairing>,
+    pairing_required: bool,
+    recorder: Option<Recorder>,
 }
 
 impl RadioServer {
-    pub async fn new() -> Result<Self> {
+    /// A server that will listen on `bind_addr` once run.
+    pub async fn new(
+        config_manager: Arc<Mutex<ConfigManager>>,
+        bind_addr: SocketAddr,
+    ) -> Result<Self> {
         let (control_tx, _) = broadcast::channel(100);
-        let config_manager = Arc::new(Mutex::new(ConfigManager::new().await?));
-        let discovery_service =
-            Arc::new(Mutex::new(DiscoveryService::new(config_manager.clone())?));
-        let connected_client = Arc::new(Mutex::new(None));
+
+        let settings = config_manager.lock().await.get_settings().clone();
+        let failsafe_settings = settings.failsafe;
+        let failsafe = Failsafe::new(failsafe_settings);
+        let (failsafe_tx, _) = watch::channel(failsafe.state());
 
         Ok(Self {
             control_tx,
             config_manager,
-            discovery_service,
-            connected_client,
+            bind_addr,
+            discovery_service: None,
+            ownership: Mutex::new(Ownership::new(settings.ownership)),
+            clients: Mutex::new(ClientRegistry::new(Duration::from_millis(
+                settings.clients.liveness_timeout_ms,
+            ))),
+            failsafe: Arc::new(Mutex::new(failsafe)),
+            failsafe_tx,
+            imu_rx: watch::channel(None).1,
+            powertrain_rx: watch::channel(None).1,
+            video_rx: watch::channel(None).1,
+            link_tx: watch::channel(LinkQuality::default()).0,
+            control_packets: AtomicU32::new(0),
+            joystick_seq: Mutex::new(HashMap::new()),
+            control_seq_sample: Mutex::new(None),
+            pairing: Mutex::new(Pairing::new(settings.pairing.pin)),
+            pairing_required: settings.pairing.required,
+            recorder: None,
         })
     }
 
@@ -64,67 +210,674 @@ impl RadioServer {
         config_manager.get_config().clone()
     }
 
+    pub fn config_manager(&self) -> Arc<Mutex<ConfigManager>> {
+        self.config_manager.clone()
+    }
+
+    pub async fn get_radio_settings(&self) -> RadioSettings {
+        let config_manager = self.config_manager.lock().await;
+        config_manager.get_settings().clone()
+    }
+
+    pub fn subscribe_control(&self) -> broadcast::Receiver<ControlMessage> {
+        self.control_tx.subscribe()
+    }
+
+    pub fn subscribe_failsafe(&self) -> watch::Receiver<FailsafeState> {
+        self.failsafe_tx.subscribe()
+    }
+
+    /// The driver's link as of each telemetry snapshot, while any client is connected.
+    pub fn subscribe_link(&self) -> watch::Receiver<LinkQuality> {
+        self.link_tx.subscribe()
+    }
+
+    pub fn set_imu_source(&mut self, imu_rx: watch::Receiver<Option<ImuReading>>) {
+        self.imu_rx = imu_rx;
+    }
+
+    pub fn set_powertrain_source(
+        &mut self,
+        powertrain_rx: watch::Receiver<Option<PowertrainReport>>,
+    ) {
+        self.powertrain_rx = powertrain_rx;
+    }
+
+    pub fn set_video_source(&mut self, video_rx: watch::Receiver<Option<VideoQuality>>) {
+        self.video_rx = video_rx;
+    }
+
+    /// Advertises the car over mDNS while the server runs.
+    pub fn set_discovery(&mut self, discovery_service: DiscoveryService) {
+        self.discovery_service = Some(Mutex::new(discovery_service));
+    }
+
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    async fn is_authorized(&self, client_addr: SocketAddr) -> bool {
+        !self.pairing_required || self.pairing.lock().await.is_paired(client_addr)
+    }
+
+    async fn pair_request(&self, client_addr: SocketAddr, client_id: &str) -> ServerMessage {
+        let challenge = self
+            .pairing
+            .lock()
+            .await
+            .challenge(client_addr, client_id, Instant::now());
+
+        match challenge {
+            Ok(nonce) => ServerMessage::PairChallenge { nonce },
+            Err(reason) => ServerMessage::PairFailed { reason },
+        }
+    }
+
+    async fn pair_response(&self, client_addr: SocketAddr, hmac: &str) -> ServerMessage {
+        let verified = self
+            .pairing
+            .lock()
+            .await
+            .verify(client_addr, hmac, Instant::now());
+        let client_id = match verified {
+            Ok(client_id) => client_id,
+            Err(reason) => return ServerMessage::PairFailed { reason },
+        };
+
+        let token = Pairing::new_token();
+        if let Err(e) = self
+            .config_manager
+            .lock()
+            .await
+            .add_session(&client_id, &token)
+            .await
+        {
+            error!("Failed to persist session for {client_id}: {e:#}");
+            return ServerMessage::PairFailed {
+                reason: "Failed to store session".to_string(),
+            };
+        }
+
+        self.pairing.lock().await.bind(client_addr, client_id);
+        ServerMessage::Paired { token }
+    }
+
+    async fn resume(&self, client_addr: SocketAddr, token: String) -> ServerMessage {
+        let session = self
+            .config_manager
+            .lock()
+            .await
+            .find_session(&token)
+            .map(|s| s.client_id.clone());
+
+        match session {
+            Some(client_id) => {
+                info!("Resumed session for {client_id} at {client_addr}");
+                self.pairing.lock().await.bind(client_addr, client_id);
+                ServerMessage::Paired { token }
+            }
+            None => ServerMessage::PairFailed {
+                reason: "Unknown session token, pair again".to_string(),
+            },
+        }
+    }
+
+    /// Runs `client_addr` through the ownership policy and tells whoever lost control.
+    /// Returns whether `client_addr` is driving afterwards.
+    async fn claim_control(
+        &self,
+        socket: &UdpSocket,
+        client_addr: SocketAddr,
+        takeover: bool,
+    ) -> bool {
+        let role = self.clients.lock().await.role(client_addr);
+        if !role.can_drive() {
+            if takeover {
+                let reason = format!("{role:?} clients cannot drive");
+                let _ = self.deny_control(socket, client_addr, reason).await;
+            }
+            return false;
+        }
+
+        let client_id = self
+            .pairing
+            .lock()
+            .await
+            .client_id(client_addr)
+            .map(str::to_string);
+        let claim = {
+            let mut ownership = self.ownership.lock().await;
+            let admin = ownership.is_admin(client_id.as_deref());
+            if takeover {
+                ownership.takeover(client_addr, admin, Instant::now())
+            } else {
+                ownership.claim(client_addr, admin, Instant::now())
+            }
+        };
+
+        match claim {
+            Claim::Held => true,
+            Claim::Granted { previous } => {
+                if let Some(previous) = previous {
+                    self.forget_client(previous).await;
+                    let lost = ServerMessage::ControlLost {
+                        reason: format!("{client_addr} took control"),
+                    };
+                    if let Err(e) = self.send_to_client(socket, &lost, previous).await {
+                        error!("Failed to notify {previous} of lost control: {e}");
+                    }
+                }
+
+                if let Err(e) = self
+                    .send_to_client(socket, &ServerMessage::ControlGranted, client_addr)
+                    .await
+                {
+                    error!("Failed to notify {client_addr} of control: {e}");
+                }
+                self.send_initial_config(socket, client_addr).await;
+                true
+            }
+            Claim::Denied { owner } => {
+                if takeover {
+                    info!("Takeover by {client_addr} refused, {owner} keeps control");
+                    let reason = format!("{owner} is driving");
+                    let _ = self.deny_control(socket, client_addr, reason).await;
+                }
+                false
+            }
+        }
+    }
+
+    async fn deny_control(
+        &self,
+        socket: &UdpSocket,
+        client_addr: SocketAddr,
+        reason: String,
+    ) -> Result<()> {
+        let response = ServerMessage::ControlDenied { reason };
+        self.send_to_client(socket, &response, client_addr).await
+    }
+
+    async fn expire_clients(&self) {
+        let stale = self.clients.lock().await.expire(Instant::now());
+        for client_addr in stale {
+            self.disconnect(client_addr).await;
+        }
+    }
+
+    async fn join(
+        &self,
+        socket: &UdpSocket,
+        client_addr: SocketAddr,
+        role: ClientRole,
+    ) -> Result<()> {
+        self.clients
+            .lock()
+            .await
+            .set_role(client_addr, role, Instant::now());
+        // a re-joining client starts its seq over
+        self.joystick_seq.lock().await.remove(&client_addr);
+
+        if !role.can_drive() && self.ownership.lock().await.release(client_addr) {
+            self.forget_client(client_addr).await;
+            let lost = ServerMessage::ControlLost {
+                reason: format!("Joined as {role:?}"),
+            };
+            self.send_to_client(socket, &lost, client_addr).await?;
+        }
+
+        self.send_to_client(socket, &ServerMessage::Joined { role }, client_addr)
+            .await
+    }
+
+    async fn list_clients(&self, client_addr: SocketAddr, role: ClientRole) -> ServerMessage {
+        let pairing = self.pairing.lock().await;
+        let ownership = self.ownership.lock().await;
+
+        if role != ClientRole::PitEngineer && !ownership.is_admin(pairing.client_id(client_addr)) {
+            return ServerMessage::Unauthorized {
+                reason: "Only admins and pit engineers may list clients".to_string(),
+            };
+        }
+
+        let clients = self
+            .clients
+            .lock()
+            .await
+            .list(Instant::now(), ownership.owner(), |addr| {
+                pairing.client_id(addr).map(str::to_string)
+            });
+        ServerMessage::Clients { clients }
+    }
+
+    /// Sends `message` to every registered client, drivers and spectators alike.
+    async fn broadcast(&self, socket: &UdpSocket, message: &ServerMessage) {
+        let clients = self.clients.lock().await.addrs();
+        for client_addr in clients {
+            if let Err(e) = self.send_to_client(socket, message, client_addr).await {
+                error!("Failed to broadcast to {client_addr}: {e}");
+            }
+        }
+    }
+
+    async fn expire_control(&self, socket: &UdpSocket) {
+        let Some(previous) = self.ownership.lock().await.expire(Instant::now()) else {
+            return;
+        };
+
+        self.forget_client(previous).await;
+        let lost = ServerMessage::ControlLost {
+            reason: "Control lock timed out".to_string(),
+        };
+        if let Err(e) = self.send_to_client(socket, &lost, previous).await {
+            debug!("Failed to notify {previous} of expired control: {e}");
+        }
+    }
+
+    /// Drops a client that went away, along with everything kept for it.
+    async fn disconnect(&self, client_addr: SocketAddr) {
+        self.ownership.lock().await.release(client_addr);
+        self.clients.lock().await.remove(client_addr);
+        self.forget_client(client_addr).await;
+    }
+
+    async fn forget_client(&self, client_addr: SocketAddr) {
+        self.failsafe.lock().await.forget(client_addr);
+        self.joystick_seq.lock().await.remove(&client_addr);
+    }
+
+    async fn accept_control(&self, client_addr: SocketAddr, ctrl: &ControlMessage) -> bool {
+        let mut failsafe = self.failsafe.lock().await;
+        let accepted = failsafe.on_control(client_addr, ctrl, Instant::now());
+        self.publish_failsafe(failsafe.state());
+
+        if accepted {
+            self.control_packets.fetch_add(1, Ordering::Relaxed);
+        } else {
+            trace!("Failsafe disarmed, ignoring non-neutral control from {client_addr}");
+        }
+        accepted
+    }
+
+    async fn check_failsafe(&self) {
+        let active = self.ownership.lock().await.owner();
+        let transition = self.failsafe.lock().await.update(active, Instant::now());
+
+        if let Some(state) = transition {
+            self.publish_failsafe(state);
+            self.publish_control(ControlMessage {
+                steering: 0,
+                throttle: 0,
+            });
+        }
+    }
+
+    async fn telemetry_snapshot(
+        &self,
+        driver: Option<SocketAddr>,
+        period: Duration,
+    ) -> CarTelemetry {
+        let now = Instant::now();
+        let (control_age, failsafe) = {
+            let failsafe = self.failsafe.lock().await;
+            (
+                driver.and_then(|addr| failsafe.since_last_control(addr, now)),
+                failsafe.state(),
+            )
+        };
+        let imu = self.imu_rx.borrow().unwrap_or_default();
+        let powertrain = self.powertrain_rx.borrow().filter(|report| {
+            now.saturating_duration_since(report.received_at) < POWERTRAIN_STALE_AFTER
+        });
+        let control_packets = self.control_packets.swap(0, Ordering::Relaxed);
+        let (control_dropped, control_loss) = {
+            let joystick_seq = self.joystick_seq.lock().await;
+            let tracker = driver.and_then(|addr| Some((addr, *joystick_seq.get(&addr)?)));
+            let current = tracker.and_then(|(addr, tracker)| Some((addr, tracker.sample()?)));
+            let mut sample = self.control_seq_sample.lock().await;
+            let loss = match (*sample, current) {
+                (Some((before_addr, before)), Some((addr, after))) if before_addr == addr => {
+                    after.loss_since(&before)
+                }
+                _ => 0.0,
+            };
+            *sample = current;
+            (tracker.map_or(0, |(_, tracker)| tracker.dropped()), loss)
+        };
+
+        CarTelemetry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            acceleration: imu.acceleration,
+            speed_estimate: imu.speed_estimate,
+            link: LinkQuality {
+                control_age_ms: control_age.map(|d| d.as_millis().min(u32::MAX as u128) as u32),
+                control_rate_hz: control_packets as f32 / period.as_secs_f32(),
+                control_dropped,
+                control_loss,
+                uart_connected: powertrain.is_some(),
+            },
+            battery_mv: powertrain
+                .map(|report| report.status.battery_mv)
+                .filter(|&mv| mv != 0),
+            failsafe,
+            video: *self.video_rx.borrow(),
+        }
+    }
+
+    async fn send_telemetry(&self, socket: &UdpSocket, period: Duration) {
+        if self.clients.lock().await.addrs().is_empty() {
+            self.control_packets.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        // link stats describe the driver's connection, whoever is watching
+        let driver = self.ownership.lock().await.owner();
+        let snapshot = self.telemetry_snapshot(driver, period).await;
+        self.link_tx.send_replace(snapshot.link);
+        self.broadcast(socket, &ServerMessage::Telemetry(snapshot))
+            .await;
+    }
+
+    fn publish_failsafe(&self, state: FailsafeState) {
+        self.failsafe_tx.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+            changed
+        });
+    }
+
+    fn publish_control(&self, ctrl: ControlMessage) {
+        if self.control_tx.receiver_count() > 0 {
+            let _ = self.control_tx.send(ctrl);
+        } else {
+            trace!("No control subscribers; skipping send");
+        }
+    }
+
     pub async fn update_car_config(&self, config: CarConfiguration) -> Result<()> {
         {
             let mut config_manager = self.config_manager.lock().await;
             config_manager.update_config(config).await?;
         }
 
-        let mut discovery_service = self.discovery_service.lock().await;
-        discovery_service.update_service(8080).await?;
+        if let Some(discovery_service) = &self.discovery_service {
+            let mut discovery_service = discovery_service.lock().await;
+            discovery_service
+                .update_service(self.bind_addr.port())
+                .await?;
+        }
 
         Ok(())
     }
 
+    /// Clamps, saves and applies camera settings, which restarts the capture with them, and
+    /// reports the settings in effect.
+    async fn apply_camera(&self, settings: CameraSettings) -> ServerMessage {
+        match settings::apply(&self.config_manager, settings).await {
+            Ok(settings) => ServerMessage::CameraSettingsUpdated {
+                success: true,
+                message: "Camera settings updated".to_string(),
+                settings,
+            },
+            Err(e) => {
+                error!("Failed to update camera settings: {e}");
+                ServerMessage::CameraSettingsUpdated {
+                    success: false,
+                    message: format!("Failed to update camera settings: {e}"),
+                    settings: self.get_car_config().await.camera,
+                }
+            }
+        }
+    }
+
     async fn handle_client_message(
         &self,
         message: ClientMessage,
         client_addr: SocketAddr,
         socket: &UdpSocket,
+        authorized: bool,
     ) -> Result<()> {
+        if !authorized
+            && matches!(
+                message,
+                ClientMessage::Control(_)
+                    | ClientMessage::ConfigUpdate { .. }
+                    | ClientMessage::ConfigRequest
+                    | ClientMessage::CameraSettings { .. }
+                    | ClientMessage::RecordStart
+                    | ClientMessage::RecordStop
+                    | ClientMessage::Takeover
+                    | ClientMessage::Join { .. }
+                    | ClientMessage::ListClients
+            )
+        {
+            warn!("Rejected {message:?} from unpaired client {client_addr}");
+            let response = ServerMessage::Unauthorized {
+                reason: "Pair with the car first".to_string(),
+            };
+            return self.send_to_client(socket, &response, client_addr).await;
+        }
+
+        // driving or changing the car claims a free lock, other traffic only keeps the driver's
+        // lock alive
+        let driving = match message {
+            ClientMessage::Control(_)
+            | ClientMessage::ConfigUpdate { .. }
+            | ClientMessage::CameraSettings { .. }
+            | ClientMessage::RecordStart
+            | ClientMessage::RecordStop
+                if authorized =>
+            {
+                self.claim_control(socket, client_addr, false).await
+            }
+            ClientMessage::ConfigRequest | ClientMessage::Ping { .. } if authorized => self
+                .ownership
+                .lock()
+                .await
+                .keep_alive(client_addr, Instant::now()),
+            _ => false,
+        };
+
+        let role = self.clients.lock().await.role(client_addr);
+        let denied = match message {
+            ClientMessage::Control(_) if !role.can_drive() => {
+                Some(format!("{role:?} clients cannot drive"))
+            }
+            ClientMessage::ConfigUpdate { .. } | ClientMessage::CameraSettings { .. }
+                if role == ClientRole::Spectator =>
+            {
+                Some("Spectators cannot change the config".to_string())
+            }
+            ClientMessage::RecordStart | ClientMessage::RecordStop
+                if role == ClientRole::Spectator =>
+            {
+                Some("Spectators cannot record".to_string())
+            }
+            // pit engineers configure the car whoever is driving
+            ClientMessage::Control(_)
+            | ClientMessage::ConfigUpdate { .. }
+            | ClientMessage::CameraSettings { .. }
+            | ClientMessage::RecordStart
+            | ClientMessage::RecordStop
+                if !driving && role == ClientRole::Driver =>
+            {
+                let owner = self.ownership.lock().await.owner();
+                Some(owner.map_or("Control unavailable".to_string(), |owner| {
+                    format!("{owner} is driving")
+                }))
+            }
+            _ => None,
+        };
+        if let Some(reason) = denied {
+            trace!("Ignoring {message:?} from {client_addr}: {reason}");
+            return self.deny_control(socket, client_addr, reason).await;
+        }
+
         match message {
             ClientMessage::Control(control_msg) => {
-                // TODO: Broadcast control message to all UART
                 debug!("Received control message: {control_msg:?}");
+                if self.accept_control(client_addr, &control_msg).await {
+                    self.publish_control(control_msg);
+                }
             }
             ClientMessage::ConfigUpdate { config } => {
+                let (config, camera) = config.split(&self.get_car_config().await);
                 info!(
                     "Received config update from {}: #{} {} ({})",
                     client_addr, config.number, config.driver_name, config.team_name
                 );
 
-                let response = match self.update_car_config(config).await {
+                let (response, camera_response) = match self.update_car_config(config).await {
                     Ok(()) => {
                         info!("Car configuration updated successfully");
-                        ServerMessage::ConfigUpdated {
+                        let camera_response = match camera {
+                            Some(camera) => Some(self.apply_camera(camera).await),
+                            None => None,
+                        };
+                        let config = self.get_car_config().await;
+                        self.broadcast(socket, &ServerMessage::Config { config })
+                            .await;
+                        let response = ServerMessage::ConfigUpdated {
                             success: true,
                             message: "Configuration updated successfully".to_string(),
-                        }
+                        };
+                        (response, camera_response)
                     }
                     Err(e) => {
                         error!("Failed to update car configuration: {e}",);
-                        ServerMessage::ConfigUpdated {
+                        let response = ServerMessage::ConfigUpdated {
                             success: false,
                             message: format!("Failed to update configuration: {e}"),
-                        }
+                        };
+                        (response, None)
                     }
                 };
 
                 self.send_to_client(socket, &response, client_addr).await?;
+                if let Some(camera_response) = camera_response {
+                    self.send_to_client(socket, &camera_response, client_addr)
+                        .await?;
+                }
+            }
+            ClientMessage::CameraSettings { settings } => {
+                info!("Received camera settings from {client_addr}: {settings:?}");
+
+                let response = self.apply_camera(settings).await;
+                if matches!(
+                    response,
+                    ServerMessage::CameraSettingsUpdated { success: true, .. }
+                ) {
+                    let config = self.get_car_config().await;
+                    self.broadcast(socket, &ServerMessage::Config { config })
+                        .await;
+                }
+
+                self.send_to_client(socket, &response, client_addr).await?;
+            }
+            ClientMessage::RecordStart | ClientMessage::RecordStop => {
+                let start = matches!(message, ClientMessage::RecordStart);
+                let response = self.record(start).await;
+                self.send_to_client(socket, &response, client_addr).await?;
             }
             ClientMessage::ConfigRequest => {
+                // a handshake, the cockpit's joystick may have restarted its seq
+                self.joystick_seq.lock().await.remove(&client_addr);
+
                 let car_config = self.get_car_config().await;
                 let response = ServerMessage::Config { config: car_config };
                 self.send_to_client(socket, &response, client_addr).await?;
+
+                if !driving
+                    && role.can_drive()
+                    && let Some(owner) = self.ownership.lock().await.owner()
+                {
+                    self.deny_control(socket, client_addr, format!("{owner} is driving"))
+                        .await?;
+                }
             }
             ClientMessage::Ping { timestamp } => {
-                let response = ServerMessage::Pong { timestamp };
+                let stats = self
+                    .joystick_seq
+                    .lock()
+                    .await
+                    .get(&client_addr)
+                    .copied()
+                    .unwrap_or_default();
+                let response = ServerMessage::Pong {
+                    timestamp,
+                    last_seq: stats.last_seq(),
+                    received: stats.received(),
+                };
                 self.send_to_client(socket, &response, client_addr).await?;
             }
+            ClientMessage::PairRequest { client_id } => {
+                let response = self.pair_request(client_addr, &client_id).await;
+                self.send_to_client(socket, &response, client_addr).await?;
+            }
+            ClientMessage::PairResponse { hmac } => {
+                let response = self.pair_response(client_addr, &hmac).await;
+                self.send_to_client(socket, &response, client_addr).await?;
+            }
+            ClientMessage::Resume { token } => {
+                let response = self.resume(client_addr, token).await;
+                self.send_to_client(socket, &response, client_addr).await?;
+            }
+            ClientMessage::Takeover => {
+                self.claim_control(socket, client_addr, true).await;
+            }
+            ClientMessage::Join { role } => {
+                self.join(socket, client_addr, role).await?;
+            }
+            ClientMessage::ListClients => {
+                let response = self.list_clients(client_addr, role).await;
+                self.send_to_client(socket, &response, client_addr).await?;
+            }
+            ClientMessage::Release => {
+                if self.ownership.lock().await.release(client_addr) {
+                    self.forget_client(client_addr).await;
+                    let response = ServerMessage::ControlLost {
+                        reason: "Control released".to_string(),
+                    };
+                    self.send_to_client(socket, &response, client_addr).await?;
+                }
+            }
         }
         Ok(())
     }
 
+    async fn record(&self, start: bool) -> ServerMessage {
+        let Some(recorder) = &self.recorder else {
+            return ServerMessage::Recording {
+                success: false,
+                message: "The car has no camera".to_string(),
+                recording: None,
+            };
+        };
+
+        let result = if start {
+            recorder.start().await.map(|run| format!("Recording {run}"))
+        } else {
+            recorder.stop().await.map(|run| match run {
+                Some(run) => format!("Recorded {run}"),
+                None => "Not recording".to_string(),
+            })
+        };
+        let (success, message) = match result {
+            Ok(message) => (true, message),
+            Err(e) => {
+                error!("Recording request failed: {e:#}");
+                (false, format!("{e:#}"))
+            }
+        };
+        ServerMessage::Recording {
+            success,
+            message,
+            recording: recorder.current().await,
+        }
+    }
+
     async fn send_to_client(
         &self,
         socket: &UdpSocket,
@@ -152,16 +905,12 @@ impl RadioServer {
             Ok(Err(e)) => {
                 error!("Failed to send to {client_addr}: {e}");
                 info!("Client disconnected (send failed) : {client_addr}");
-
-                let mut client = self.connected_client.lock().await;
-                *client = None;
+                self.disconnect(client_addr).await;
             }
             Err(_) => {
                 warn!("Send to {client_addr} timed out");
                 info!("Client disconnected (timeout): {client_addr}");
-
-                let mut client = self.connected_client.lock().await;
-                *client = None;
+                self.disconnect(client_addr).await;
             }
         }
         Ok(())
@@ -185,21 +934,31 @@ impl RadioServer {
             car_config.number, car_config.driver_name, car_config.team_name
         );
 
-        info!("Camera streaming is handled by UDP streamer on port 8080");
-
-        {
-            let mut discovery_service = self.discovery_service.lock().await;
-            discovery_service.start_advertising(8080).await?;
+        if let Some(discovery_service) = &self.discovery_service {
+            let mut discovery_service = discovery_service.lock().await;
+            discovery_service
+                .start_advertising(self.bind_addr.port())
+                .await?;
         }
 
-        let socket = UdpSocket::bind("0.0.0.0:8080")
+        let socket = UdpSocket::bind(self.bind_addr)
             .await
-            .context("Failed to bind UDP socket to port 8080")?;
+            .with_context(|| format!("Failed to bind UDP socket to {}", self.bind_addr))?;
 
-        info!("UDP server listening on 0.0.0.0:8080");
+        info!("UDP server listening on {}", self.bind_addr);
 
         let socket = Arc::new(socket);
         let mut buffer = vec![0u8; 65507]; // Max UDP payload size
+        let mut failsafe_interval = interval(FAILSAFE_CHECK_INTERVAL);
+
+        let telemetry_rate = self.get_radio_settings().await.telemetry.rate_hz;
+        let telemetry_period = Duration::from_secs(1) / telemetry_rate.max(1);
+        let mut telemetry_interval = interval(telemetry_period);
+        if telemetry_rate == 0 {
+            info!("Telemetry stream disabled in radio settings");
+        } else {
+            info!("Sending telemetry at {telemetry_rate} Hz");
+        }
 
         loop {
             tokio::select! {
@@ -207,36 +966,35 @@ impl RadioServer {
                     info!("Cancellation requested, breaking server loop");
                     break;
                 }
+                _ = failsafe_interval.tick() => {
+                    self.expire_clients().await;
+                    self.expire_control(&socket).await;
+                    self.check_failsafe().await;
+                }
+                _ = telemetry_interval.tick(), if telemetry_rate > 0 => {
+                    self.send_telemetry(&socket, telemetry_period).await;
+                }
                 result = socket.recv_from(&mut buffer) => {
                     match result {
                         Ok((len, client_addr)) => {
                             let data_str = str::from_utf8(&buffer[..len]).unwrap_or("<invalid UTF-8>");
                             trace!("UDP message received from {client_addr}: {data_str}");
 
-                            // Add client to connected list if not already present
-                            {
-                                let mut client = self.connected_client.lock().await;
-                                match *client {
-                                    None => {
-                                        *client = Some(client_addr);
-                                        info!("Client connected: {client_addr}");
-
-                                        self.send_initial_config(&socket, client_addr).await;
-                                    }
-                                    Some(existing_addr) => {
-                                        if existing_addr != client_addr {
-                                            info!(
-                                                "Previous client {existing_addr} replaced by new client {client_addr}"
-                                            );
-                                            *client = Some(client_addr);
-
-                                            self.send_initial_config(&socket, client_addr).await;
-                                        }
-                                    }
-                                }
+                            let authorized = self.is_authorized(client_addr).await;
+                            if authorized {
+                                self.clients.lock().await.touch(client_addr, Instant::now());
                             }
 
                             if len == 8 {
+                                if !authorized {
+                                    trace!("Dropping joystick packet from unpaired client {client_addr}");
+                                    continue;
+                                }
+                                if !self.claim_control(&socket, client_addr, false).await {
+                                    trace!("Dropping joystick packet from {client_addr}, not driving");
+                                    continue;
+                                }
+
                                 let seq = u32::from_le_bytes([
                                     buffer[0], buffer[1], buffer[2], buffer[3],
                                 ]);
@@ -250,10 +1008,19 @@ impl RadioServer {
                                 let ctrl = ControlMessage { steering, throttle };
                                 debug!("Received joystick from {client_addr}: seq={} {:?}", seq, ctrl);
 
-                                if self.control_tx.receiver_count() > 0 {
-                                    let _ = self.control_tx.send(ctrl);
-                                } else {
-                                    trace!("No control subscribers; skipping send");
+                                let check = self
+                                    .joystick_seq
+                                    .lock()
+                                    .await
+                                    .entry(client_addr)
+                                    .or_default()
+                                    .check(seq);
+                                if check != SeqCheck::Accept {
+                                    continue;
+                                }
+
+                                if self.accept_control(client_addr, &ctrl).await {
+                                    self.publish_control(ctrl);
                                 }
                                 continue;
                             }
@@ -270,7 +1037,7 @@ impl RadioServer {
                             match serde_json::from_str::<ClientMessage>(message_str) {
                                 Ok(client_message) => {
                                     if let Err(e) = self
-                                        .handle_client_message(client_message, client_addr, &socket)
+                                        .handle_client_message(client_message, client_addr, &socket, authorized)
                                         .await
                                     {
                                         error!("Error handling message from {client_addr}: {e}");
@@ -296,6 +1063,12 @@ impl RadioServer {
         }
 
         info!("Radio Server shutting down.");
+        // finish the recording's files so they stay playable
+        if let Some(recorder) = &self.recorder
+            && let Err(e) = recorder.stop().await
+        {
+            error!("{e:#}");
+        }
         Ok(())
     }
 }
@@ -304,10 +1077,375 @@ impl Drop for RadioServer {
     fn drop(&mut self) {
         info!("RadioServer dropped, cleaning up resources");
 
-        if let Ok(mut discovery_service) = self.discovery_service.try_lock()
-            && let Err(e) = discovery_service.stop_advertising()
+        if let Some(discovery_service) = &mut self.discovery_service
+            && let Err(e) = discovery_service.get_mut().stop_advertising()
         {
             error!("Failed to stop mDNS advertising: {e}");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use telemetry::Vector3;
+
+    use super::*;
+    use crate::{
+        accelerometer,
+        hal::{ImuSource, MotorSink, motor},
+    };
+
+    const FORWARD_MS2: f32 = 1.5;
+
+    /// An IMU on a car accelerating steadily.
+    struct FakeImu;
+
+    impl ImuSource for FakeImu {
+        fn read(&mut self) -> Result<Vector3> {
+            Ok(Vector3 {
+                x: FORWARD_MS2,
+                y: 0.0,
+                z: 9.8,
+            })
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum MotorCommand {
+        Control(ControlMessage),
+        Failsafe(FailsafeState),
+    }
+
+    /// Keeps every command the server sends to the motors.
+    #[derive(Clone, Default)]
+    struct FakeMotor {
+        commands: Arc<std::sync::Mutex<Vec<MotorCommand>>>,
+    }
+
+    impl FakeMotor {
+        fn commands(&self) -> Vec<MotorCommand> {
+            self.commands.lock().unwrap().clone()
+        }
+
+        /// Waits for `command`, returning everything sent up to it.
+        async fn wait_for(&self, command: MotorCommand) -> Vec<MotorCommand> {
+            timeout(Duration::from_secs(5), async {
+                loop {
+                    let commands = self.commands();
+                    if let Some(at) = commands.iter().position(|c| *c == command) {
+                        return commands[..=at].to_vec();
+                    }
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            })
+            .await
+            .unwrap_or_else(|_| panic!("no {command:?} in {:?}", self.commands()))
+        }
+    }
+
+    impl MotorSink for FakeMotor {
+        fn control(&mut self, ctrl: &ControlMessage) -> Result<()> {
+            let command = MotorCommand::Control(ctrl.clone());
+            self.commands.lock().unwrap().push(command);
+            Ok(())
+        }
+
+        fn failsafe(&mut self, state: FailsafeState) -> Result<()> {
+            let command = MotorCommand::Failsafe(state);
+            self.commands.lock().unwrap().push(command);
+            Ok(())
+        }
+    }
+
+    /// The failsafe states and the control messages among `commands`, each in order.
+    fn split(commands: &[MotorCommand]) -> (Vec<FailsafeState>, Vec<ControlMessage>) {
+        let mut failsafes = Vec::new();
+        let mut controls = Vec::new();
+        for command in commands {
+            match command {
+                MotorCommand::Failsafe(state) => failsafes.push(*state),
+                MotorCommand::Control(ctrl) => controls.push(ctrl.clone()),
+            }
+        }
+        (failsafes, controls)
+    }
+
+    fn joystick(seq: u32, throttle: i16, steering: i16) -> [u8; 8] {
+        let mut packet = [0u8; 8];
+        packet[0..4].copy_from_slice(&seq.to_le_bytes());
+        packet[4..6].copy_from_slice(&throttle.to_le_bytes());
+        packet[6..8].copy_from_slice(&steering.to_le_bytes());
+        packet
+    }
+
+    /// Reads server messages until one of type `kind` arrives.
+    async fn recv_message(client: &UdpSocket, kind: &str) -> Value {
+        let mut buf = vec![0u8; 65507];
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let len = client.recv(&mut buf).await.unwrap();
+                let message: Value = serde_json::from_slice(&buf[..len]).unwrap();
+                if message["type"] == kind {
+                    return message;
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("no {kind} message"))
+    }
+
+    async fn config_manager(name: &str) -> Arc<Mutex<ConfigManager>> {
+        let dir = std::env::temp_dir().join(format!("radio-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("radio.toml"),
+            "[pairing]\nrequired = false\npin = \"123456\"\n\n\
+             [telemetry]\nrate_hz = 50\n\n\
+             [failsafe]\ncoast_after_ms = 150\nbrake_after_ms = 300\ndisarm_after_ms = 2000\n",
+        )
+        .unwrap();
+        let config_manager = ConfigManager::with_config_dir(&dir).await.unwrap();
+        Arc::new(Mutex::new(config_manager))
+    }
+
+    fn free_addr() -> SocketAddr {
+        std::net::UdpSocket::bind("127.0.0.1:0")
+            .and_then(|socket| socket.local_addr())
+            .unwrap()
+    }
+
+    /// A client of the server at `addr`, and the config it answered with.
+    async fn connect(addr: SocketAddr) -> (UdpSocket, Value) {
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+        // the server may not be listening yet
+        let config = timeout(Duration::from_secs(5), async {
+            loop {
+                client.send(br#"{"type":"config_request"}"#).await.unwrap();
+                if let Ok(config) =
+                    timeout(Duration::from_millis(100), recv_message(&client, "config")).await
+                {
+                    return config;
+                }
+            }
+        })
+        .await
+        .expect("no config from the server");
+        (client, config)
+    }
+
+    #[tokio::test]
+    async fn drives_the_motors_and_reports_telemetry() {
+        let bind_addr = free_addr();
+        let mut server = RadioServer::new(config_manager("server").await, bind_addr)
+            .await
+            .unwrap();
+        let cancel_token = CancellationToken::new();
+
+        let (imu_tx, mut imu_rx) = watch::channel(None);
+        server.set_imu_source(imu_rx.clone());
+        let poller =
+            accelerometer::start_poller(Box::new(FakeImu), cancel_token.clone(), 10, imu_tx);
+        imu_rx.wait_for(Option::is_some).await.unwrap();
+
+        let motor = FakeMotor::default();
+        let drive = tokio::spawn(motor::drive(
+            Box::new(motor.clone()),
+            server.subscribe_control(),
+            server.subscribe_failsafe(),
+            cancel_token.clone(),
+        ));
+        let run = tokio::spawn({
+            let cancel_token = cancel_token.clone();
+            async move { server.run(cancel_token).await }
+        });
+
+        let (client, config) = connect(bind_addr).await;
+        assert_eq!(config["config"]["number"], 0);
+
+        // neutral first to arm, then half throttle at full left lock
+        client.send(&joystick(0, 0, 0)).await.unwrap();
+        client.send(&joystick(1, 16_384, -32_767)).await.unwrap();
+
+        let drive_command = ControlMessage {
+            steering: -100,
+            throttle: 50,
+        };
+        let commands = motor
+            .wait_for(MotorCommand::Control(drive_command.clone()))
+            .await;
+        let neutral = ControlMessage {
+            steering: 0,
+            throttle: 0,
+        };
+        let (failsafes, controls) = split(&commands);
+        assert_eq!(failsafes, [FailsafeState::Disarmed, FailsafeState::Armed]);
+        assert_eq!(controls, [neutral.clone(), drive_command]);
+
+        // skipping snapshots queued before the car armed
+        let telemetry = loop {
+            let telemetry = recv_message(&client, "telemetry").await;
+            if telemetry["failsafe"] == "Armed" {
+                break telemetry;
+            }
+        };
+        assert_eq!(telemetry["acceleration"]["x"], FORWARD_MS2);
+        assert_eq!(telemetry["link"]["controlDropped"], 0);
+
+        // stale and duplicate packets don't reach the motors
+        client.send(&joystick(0, 32_767, 0)).await.unwrap();
+        client.send(&joystick(1, 32_767, 0)).await.unwrap();
+
+        // then the driver goes quiet, and the car coasts and brakes to a stop
+        let commands = motor
+            .wait_for(MotorCommand::Failsafe(FailsafeState::Brake))
+            .await;
+        let (failsafes, controls) = split(&commands);
+        assert_eq!(failsafes[2..], [FailsafeState::Coast, FailsafeState::Brake]);
+        assert!(controls[2..].iter().all(|ctrl| *ctrl == neutral));
+
+        cancel_token.cancel();
+        run.await.unwrap().unwrap();
+        drive.await.unwrap();
+        poller.join().unwrap();
+        assert_eq!(
+            motor.commands().last(),
+            Some(&MotorCommand::Failsafe(FailsafeState::Disarmed))
+        );
+    }
+
+    #[test]
+    fn config_update_without_camera_keeps_the_camera() {
+        let current = CarConfiguration {
+            camera: CameraSettings {
+                fps: 60,
+                ..CameraSettings::default()
+            },
+            ..CarConfiguration::default()
+        };
+        let message: ClientMessage = serde_json::from_str(
+            r#"{"type":"config_update","config":{"number":44,"driver_name":"Lewis","team_name":"Ferrari"}}"#,
+        )
+        .unwrap();
+        let ClientMessage::ConfigUpdate { config } = message else {
+            panic!("not a config update: {message:?}");
+        };
+
+        let (updated, camera) = config.split(&current);
+        assert_eq!(camera, None);
+        assert_eq!(
+            (
+                updated.number,
+                updated.driver_name.as_str(),
+                updated.team_name.as_str()
+            ),
+            (44, "Lewis", "Ferrari")
+        );
+        assert_eq!(updated.camera, current.camera);
+    }
+
+    #[test]
+    fn config_update_with_camera_asks_for_it() {
+        let message: ClientMessage = serde_json::from_str(
+            r#"{"type":"config_update","config":{"number":1,"driver_name":"Max","team_name":"Red Bull","camera":{"fps":15}}}"#,
+        )
+        .unwrap();
+        let ClientMessage::ConfigUpdate { config } = message else {
+            panic!("not a config update: {message:?}");
+        };
+
+        let (updated, camera) = config.split(&CarConfiguration::default());
+        assert_eq!(updated.camera, CameraSettings::default());
+        assert_eq!(
+            camera,
+            Some(CameraSettings {
+                fps: 15,
+                ..CameraSettings::default()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn config_update_clamps_the_camera() {
+        let bind_addr = free_addr();
+        let config_manager = config_manager("config-update-camera").await;
+        let mut camera_rx = config_manager.lock().await.subscribe_camera();
+        let server = RadioServer::new(config_manager.clone(), bind_addr)
+            .await
+            .unwrap();
+        let cancel_token = CancellationToken::new();
+        let run = tokio::spawn({
+            let cancel_token = cancel_token.clone();
+            async move { server.run(cancel_token).await }
+        });
+
+        let (client, _) = connect(bind_addr).await;
+        client
+            .send(
+                br#"{"type":"config_update","config":{"number":7,"driver_name":"Ayrton","team_name":"McLaren","camera":{"width":5000,"fps":200,"quality":0}}}"#,
+            )
+            .await
+            .unwrap();
+
+        let clamped = CameraSettings {
+            width: 1920,
+            fps: 60,
+            quality: 1,
+            ..CameraSettings::default()
+        };
+        let reply = recv_message(&client, "camera_settings_updated").await;
+        assert_eq!(reply["success"], true);
+        let settings: CameraSettings = serde_json::from_value(reply["settings"].clone()).unwrap();
+        assert_eq!(settings, clamped);
+        let saved = config_manager.lock().await.get_config().clone();
+        assert_eq!((saved.number, saved.driver_name.as_str()), (7, "Ayrton"));
+        assert_eq!(saved.camera, clamped);
+        assert_eq!(*camera_rx.borrow_and_update(), clamped);
+
+        cancel_token.cancel();
+        run.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn pinging_does_not_take_a_free_lock() {
+        let bind_addr = free_addr();
+        let server = RadioServer::new(config_manager("ping-lock").await, bind_addr)
+            .await
+            .unwrap();
+        let cancel_token = CancellationToken::new();
+        let run = tokio::spawn({
+            let cancel_token = cancel_token.clone();
+            async move { server.run(cancel_token).await }
+        });
+
+        let (spectating, _) = connect(bind_addr).await;
+        spectating
+            .send(br#"{"type":"ping","timestamp":1}"#)
+            .await
+            .unwrap();
+        recv_message(&spectating, "pong").await;
+
+        let (driving, _) = connect(bind_addr).await;
+        driving
+            .send(br#"{"type":"control","steering":0,"throttle":10}"#)
+            .await
+            .unwrap();
+        recv_message(&driving, "control_granted").await;
+
+        // the lock is taken now, so the first client can't drive either
+        spectating
+            .send(br#"{"type":"control","steering":0,"throttle":10}"#)
+            .await
+            .unwrap();
+        let denied = recv_message(&spectating, "control_denied").await;
+        assert_eq!(
+            denied["reason"],
+            format!("{} is driving", driving.local_addr().unwrap())
+        );
+
+        cancel_token.cancel();
+        run.await.unwrap().unwrap();
+    }
+}
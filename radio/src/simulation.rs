@@ -0,0 +1,287 @@
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use telemetry::{ControlMessage, Vector3, frame::PowertrainStatus};
+use tokio::sync::{broadcast, broadcast::error::TryRecvError, watch};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    accelerometer::{ImuReading, SpeedEstimator},
+    uart::PowertrainReport,
+};
+
+const EARTH_GRAVITY_MS2: f64 = 9.80665;
+const WHEELBASE_M: f64 = 0.26;
+const MAX_STEER_RAD: f64 = 0.45;
+const MAX_DRIVE_MS2: f64 = 4.0;
+const MAX_REVERSE_MS2: f64 = 2.0;
+/// Quadratic air drag and linear rolling resistance, tuned for a ~6.7 m/s top speed
+const DRAG_PER_MS: f64 = 0.06;
+const ROLLING_PER_S: f64 = 0.2;
+const NOMINAL_BATTERY_MV: f64 = 7_400.0;
+const BATTERY_SAG_MV: f64 = 600.0;
+const LOG_EVERY: Duration = Duration::from_secs(2);
+
+/// Kinematic bicycle model of the car, driven by the same control stream as the motors.
+///
+/// Position is in metres from the start point, `heading` in radians. Accelerations are reported
+/// in the body frame the accelerometer uses: x forward, y left, z up including gravity.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VehicleModel {
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+    pub speed: f64,
+    pub forward_accel: f64,
+    pub lateral_accel: f64,
+}
+
+impl VehicleModel {
+    pub fn step(&mut self, ctrl: &ControlMessage, dt: Duration) {
+        let dt = dt.as_secs_f64();
+        let throttle = (ctrl.throttle as f64 / 100.0).clamp(-1.0, 1.0);
+        let steer = (ctrl.steering as f64 / 100.0).clamp(-1.0, 1.0) * MAX_STEER_RAD;
+
+        let drive = if throttle >= 0.0 {
+            throttle * MAX_DRIVE_MS2
+        } else {
+            throttle * MAX_REVERSE_MS2
+        };
+        let resistance = DRAG_PER_MS * self.speed * self.speed.abs() + ROLLING_PER_S * self.speed;
+        let accel = drive - resistance;
+
+        let previous_speed = self.speed;
+        self.speed += accel * dt;
+        // resistance alone never reverses the car
+        if throttle == 0.0 && previous_speed.signum() != self.speed.signum() {
+            self.speed = 0.0;
+        }
+
+        // steering positive is right, heading is counter-clockwise
+        let yaw_rate = self.speed / WHEELBASE_M * (-steer).tan();
+        self.heading = (self.heading + yaw_rate * dt).rem_euclid(std::f64::consts::TAU);
+        self.x += self.speed * self.heading.cos() * dt;
+        self.y += self.speed * self.heading.sin() * dt;
+
+        self.forward_accel = (self.speed - previous_speed) / dt;
+        self.lateral_accel = self.speed * yaw_rate;
+    }
+}
+
+/// Stand-in for the accelerometer and the powertrain MCU when running off the Pi.
+pub struct SimulatedCar {
+    model: VehicleModel,
+    control_rx: broadcast::Receiver<ControlMessage>,
+}
+
+impl SimulatedCar {
+    pub fn new(control_rx: broadcast::Receiver<ControlMessage>) -> Self {
+        Self {
+            model: VehicleModel::default(),
+            control_rx,
+        }
+    }
+
+    /// Steps the model every `interval_ms` and publishes IMU readings and powertrain reports
+    /// as the real poller and UART bridge would.
+    pub fn start(
+        self,
+        cancel_token: CancellationToken,
+        interval_ms: u64,
+        reading_tx: watch::Sender<Option<ImuReading>>,
+        powertrain_tx: watch::Sender<Option<PowertrainReport>>,
+    ) -> JoinHandle<()> {
+        info!("Simulated car running at {} Hz", 1000 / interval_ms.max(1));
+
+        thread::spawn(move || {
+            let SimulatedCar {
+                mut model,
+                mut control_rx,
+            } = self;
+            let mut ctrl = ControlMessage {
+                steering: 0,
+                throttle: 0,
+            };
+            let mut speed = SpeedEstimator::default();
+            let mut last_step = Instant::now();
+            let mut last_log = last_step;
+
+            while !cancel_token.is_cancelled() {
+                loop {
+                    match control_rx.try_recv() {
+                        Ok(latest) => ctrl = latest,
+                        Err(TryRecvError::Lagged(_)) => continue,
+                        Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+                    }
+                }
+
+                let now = Instant::now();
+                let dt = now - last_step;
+                last_step = now;
+                if !dt.is_zero() {
+                    model.step(&ctrl, dt);
+                }
+
+                // run the same estimator as the real IMU so telemetry shows its behaviour too
+                let speed_estimate = speed.update(model.forward_accel, dt);
+                reading_tx.send_replace(Some(ImuReading {
+                    acceleration: Vector3 {
+                        x: model.forward_accel as f32,
+                        y: model.lateral_accel as f32,
+                        z: EARTH_GRAVITY_MS2 as f32,
+                    },
+                    speed_estimate: speed_estimate as f32,
+                }));
+
+                let load = (ctrl.throttle as f64 / 100.0).abs();
+                powertrain_tx.send_replace(Some(PowertrainReport {
+                    status: PowertrainStatus {
+                        battery_mv: (NOMINAL_BATTERY_MV - BATTERY_SAG_MV * load) as u16,
+                        motor_output: ctrl.throttle,
+                        steering_output: ctrl.steering,
+                        faults: 0,
+                    },
+                    received_at: now,
+                }));
+
+                if now - last_log >= LOG_EVERY {
+                    last_log = now;
+                    debug!(
+                        "sim: pos ({:.1}, {:.1}) m, heading {:.0} deg, speed {:.2} m/s",
+                        model.x,
+                        model.y,
+                        model.heading.to_degrees(),
+                        model.speed
+                    );
+                }
+
+                thread::sleep(Duration::from_millis(interval_ms));
+            }
+
+            info!("Simulated car stopped");
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::*;
+
+    const DT: Duration = Duration::from_millis(10);
+
+    fn ctrl(steering: i8, throttle: i8) -> ControlMessage {
+        ControlMessage { steering, throttle }
+    }
+
+    fn run(model: &mut VehicleModel, ctrl: &ControlMessage, secs: f64) {
+        for _ in 0..(secs / DT.as_secs_f64()).round() as u32 {
+            model.step(ctrl, DT);
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn full_throttle_from_rest() {
+        let mut model = VehicleModel::default();
+        model.step(&ctrl(0, 100), DT);
+
+        assert_close(model.forward_accel, MAX_DRIVE_MS2, 1e-9);
+        assert_close(model.speed, MAX_DRIVE_MS2 * 0.01, 1e-9);
+        assert_close(model.x, model.speed * 0.01, 1e-12);
+        assert_eq!(
+            (model.y, model.heading, model.lateral_accel),
+            (0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn settles_at_top_speed() {
+        let mut model = VehicleModel::default();
+        run(&mut model, &ctrl(0, 100), 60.0);
+
+        // where drive and resistance balance: 0.06 v^2 + 0.2 v = 4
+        let top = (-ROLLING_PER_S
+            + (ROLLING_PER_S.powi(2) + 4.0 * DRAG_PER_MS * MAX_DRIVE_MS2).sqrt())
+            / (2.0 * DRAG_PER_MS);
+        assert_close(model.speed, top, 1e-3);
+        assert_close(model.forward_accel, 0.0, 1e-3);
+        assert_eq!(model.heading, 0.0);
+    }
+
+    #[test]
+    fn reverses_more_gently() {
+        let mut model = VehicleModel::default();
+        model.step(&ctrl(0, -100), DT);
+        assert_close(model.forward_accel, -MAX_REVERSE_MS2, 1e-9);
+        assert!(model.x < 0.0);
+    }
+
+    #[test]
+    fn coasts_to_a_stop_without_reversing() {
+        let mut model = VehicleModel {
+            speed: 3.0,
+            ..VehicleModel::default()
+        };
+        run(&mut model, &ctrl(0, 0), 30.0);
+        assert!((0.0..0.01).contains(&model.speed), "{}", model.speed);
+        assert!(model.x > 0.0);
+
+        // a long step would overshoot into reverse
+        let mut model = VehicleModel {
+            speed: -3.0,
+            ..VehicleModel::default()
+        };
+        model.step(&ctrl(0, 0), Duration::from_secs(10));
+        assert_eq!(model.speed, 0.0);
+    }
+
+    #[test]
+    fn yaw_rate_follows_speed_and_steering() {
+        let mut model = VehicleModel {
+            speed: 2.0,
+            ..VehicleModel::default()
+        };
+        model.step(&ctrl(100, 0), DT);
+
+        // full right lock turns clockwise
+        let yaw_rate = model.speed / WHEELBASE_M * (-MAX_STEER_RAD).tan();
+        assert!(yaw_rate < 0.0);
+        assert_close(model.heading, TAU + yaw_rate * 0.01, 1e-9);
+        assert_close(model.lateral_accel, model.speed * yaw_rate, 1e-9);
+    }
+
+    #[test]
+    fn steady_turn_traces_a_circle() {
+        let mut model = VehicleModel::default();
+        let left = ctrl(-100, 30);
+        // the turning radius of a bicycle model depends only on the steering angle
+        let radius = WHEELBASE_M / MAX_STEER_RAD.tan();
+
+        let mut turned = 0.0;
+        let mut last_heading = model.heading;
+        for _ in 0..1_000 {
+            model.step(&left, DT);
+            turned += (model.heading - last_heading).rem_euclid(TAU);
+            last_heading = model.heading;
+
+            let from_centre = model.x.hypot(model.y - radius);
+            assert_close(from_centre, radius, radius * 0.02);
+        }
+        // several laps, all of them anti-clockwise
+        assert!(turned > 2.0 * TAU, "turned {turned} rad");
+        assert_close(
+            model.lateral_accel,
+            model.speed * model.speed / radius,
+            1e-6,
+        );
+    }
+}
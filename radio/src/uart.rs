@@ -0,0 +1,346 @@
+//! Bridge between the UDP control path and the powertrain MCU.
+//!
+//! Every `ControlMessage` and failsafe transition handed to the `UartMotorSink` is
+//! framed with `telemetry::frame` and written to the serial port. Status frames
+//! coming back from the powertrain are published for the telemetry stream. To try
+//! it without a Pi, create a pseudo-terminal pair and point `uart.device` in
+//! `.f1-car/radio.toml` at one end:
+//!
+//! ```text
+//! socat -d -d pty,raw,echo=0 pty,raw,echo=0
+//! ```
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use log::{debug, error, info, trace, warn};
+use telemetry::{
+    ControlMessage, FailsafeState,
+    frame::{self, Frame, FrameDecoder, MAX_FRAME_LEN, Packet, PowertrainStatus},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{broadcast, watch},
+    time::sleep,
+};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config::UartSettings, hal::MotorSink};
+
+const REOPEN_DELAY: Duration = Duration::from_secs(2);
+/// Control packets arrive at ~50Hz, a short queue rides out a slow write without going stale.
+/// Once it is full the oldest command makes way for the newest.
+const CONTROL_QUEUE_LEN: usize = 8;
+const LAG_WARNING_EVERY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+pub struct PowertrainReport {
+    pub status: PowertrainStatus,
+    pub received_at: Instant,
+}
+
+/// The `MotorSink` end of a `UartBridge`.
+pub struct UartMotorSink {
+    control_tx: broadcast::Sender<ControlMessage>,
+    failsafe_tx: watch::Sender<FailsafeState>,
+}
+
+impl MotorSink for UartMotorSink {
+    fn control(&mut self, ctrl: &ControlMessage) -> Result<()> {
+        self.control_tx
+            .send(ctrl.clone())
+            .map(|_| ())
+            .map_err(|_| anyhow!("UART bridge stopped"))
+    }
+
+    fn failsafe(&mut self, state: FailsafeState) -> Result<()> {
+        self.failsafe_tx.send_replace(state);
+        Ok(())
+    }
+}
+
+pub struct UartBridge {
+    settings: UartSettings,
+    seq: u8,
+    status_tx: watch::Sender<Option<PowertrainReport>>,
+    control_rx: broadcast::Receiver<ControlMessage>,
+    failsafe_rx: watch::Receiver<FailsafeState>,
+    /// Control messages overwritten in the queue since the last warning about it
+    lagged: u64,
+    last_lag_warning: Option<Instant>,
+}
+
+impl UartBridge {
+    pub fn new(settings: UartSettings) -> (Self, UartMotorSink) {
+        let (status_tx, _) = watch::channel(None);
+        let (control_tx, control_rx) = broadcast::channel(CONTROL_QUEUE_LEN);
+        let (failsafe_tx, failsafe_rx) = watch::channel(FailsafeState::Disarmed);
+
+        let bridge = Self {
+            settings,
+            seq: 0,
+            status_tx,
+            control_rx,
+            failsafe_rx,
+            lagged: 0,
+            last_lag_warning: None,
+        };
+        let sink = UartMotorSink {
+            control_tx,
+            failsafe_tx,
+        };
+        (bridge, sink)
+    }
+
+    pub fn subscribe_status(&self) -> watch::Receiver<Option<PowertrainReport>> {
+        self.status_tx.subscribe()
+    }
+
+    pub async fn run(mut self, cancel_token: CancellationToken) {
+        info!(
+            "Starting UART bridge on {} @ {} baud",
+            self.settings.device, self.settings.baud_rate
+        );
+
+        while !cancel_token.is_cancelled() {
+            let mut port = match self.open() {
+                Ok(port) => port,
+                Err(e) => {
+                    error!("UART link error: {e:#}");
+                    tokio::select! {
+                        _ = cancel_token.cancelled() => break,
+                        _ = sleep(REOPEN_DELAY) => continue,
+                    }
+                }
+            };
+
+            info!("UART link open: {}", self.settings.device);
+
+            // Drop anything queued while the link was down, stale commands are worse than none
+            self.control_rx = self.control_rx.resubscribe();
+            let mut next = Some(Packet::Failsafe(*self.failsafe_rx.borrow_and_update()));
+            let mut decoder = FrameDecoder::new();
+            let mut rx_buf = [0u8; 64];
+
+            loop {
+                if let Some(packet) = next.take()
+                    && let Err(e) = self.write_packet(&mut port, packet).await
+                {
+                    error!("UART link error: {e:#}");
+                    break;
+                }
+
+                // shutting down and failsafe changes go out ahead of any queued control
+                next = tokio::select! {
+                    biased;
+                    _ = cancel_token.cancelled() => {
+                        let disarm = Packet::Failsafe(FailsafeState::Disarmed);
+                        let _ = self.write_packet(&mut port, disarm).await;
+                        break;
+                    }
+                    changed = self.failsafe_rx.changed() => match changed {
+                        Ok(()) => Some(Packet::Failsafe(*self.failsafe_rx.borrow_and_update())),
+                        Err(_) => {
+                            debug!("Failsafe channel closed, stopping UART bridge");
+                            return;
+                        }
+                    },
+                    msg = self.control_rx.recv() => match msg {
+                        Ok(ctrl) => Some(Packet::Control(ctrl)),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            self.on_lagged(skipped);
+                            None
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            debug!("Control channel closed, stopping UART bridge");
+                            return;
+                        }
+                    },
+                    read = port.read(&mut rx_buf) => match read {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            for &byte in &rx_buf[..n] {
+                                self.on_byte(&mut decoder, byte);
+                            }
+                            None
+                        }
+                        Err(e) => {
+                            error!("UART link error: Failed to read frame: {e}");
+                            break;
+                        }
+                    },
+                };
+            }
+
+            self.status_tx.send_replace(None);
+
+            if !cancel_token.is_cancelled() {
+                warn!(
+                    "UART link to {} lost, reopening in {}s",
+                    self.settings.device,
+                    REOPEN_DELAY.as_secs()
+                );
+                sleep(REOPEN_DELAY).await;
+            }
+        }
+
+        info!("UART bridge stopped");
+    }
+
+    fn open(&self) -> Result<SerialStream> {
+        tokio_serial::new(&self.settings.device, self.settings.baud_rate)
+            .open_native_async()
+            .with_context(|| format!("Failed to open serial port {}", self.settings.device))
+    }
+
+    /// Warns about commands lost to slow writes, at most every `LAG_WARNING_EVERY`.
+    fn on_lagged(&mut self, skipped: u64) {
+        self.lagged += skipped;
+        if self
+            .last_lag_warning
+            .is_some_and(|at| at.elapsed() < LAG_WARNING_EVERY)
+        {
+            return;
+        }
+
+        warn!(
+            "UART link lagged, dropped {} stale control messages",
+            self.lagged
+        );
+        self.lagged = 0;
+        self.last_lag_warning = Some(Instant::now());
+    }
+
+    fn on_byte(&self, decoder: &mut FrameDecoder, byte: u8) {
+        match decoder.push(byte) {
+            Some(Ok(Frame {
+                packet: Packet::Status(status),
+                ..
+            })) => {
+                trace!("UART rx {status:?}");
+                self.status_tx.send_replace(Some(PowertrainReport {
+                    status,
+                    received_at: Instant::now(),
+                }));
+            }
+            Some(Ok(frame)) => warn!("Unexpected frame from powertrain: {frame:?}"),
+            Some(Err(e)) => debug!("Dropped corrupt frame from powertrain: {e}"),
+            None => {}
+        }
+    }
+
+    async fn write_packet(&mut self, port: &mut SerialStream, packet: Packet) -> Result<()> {
+        let frame = Frame {
+            seq: self.seq,
+            packet,
+        };
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let len =
+            frame::encode(&frame, &mut buf).map_err(|e| anyhow!("Failed to encode frame: {e}"))?;
+        trace!("UART tx {:02x?}", &buf[..len]);
+
+        port.write_all(&buf[..len])
+            .await
+            .context("Failed to write frame")?;
+        port.flush().await.context("Failed to flush serial port")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::timeout;
+    use tokio_serial::SerialPort;
+
+    use super::*;
+
+    /// Reads from the powertrain end of the pty until a whole frame decodes.
+    async fn read_frame(port: &mut SerialStream, decoder: &mut FrameDecoder) -> Frame {
+        let mut buf = [0u8; 1];
+        loop {
+            port.read_exact(&mut buf).await.unwrap();
+            if let Some(frame) = decoder.push(buf[0]) {
+                return frame.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn full_queue_keeps_the_newest_commands() {
+        let (mut bridge, mut sink) = UartBridge::new(UartSettings::default());
+        let sent = CONTROL_QUEUE_LEN as i8 + 2;
+        for throttle in 0..sent {
+            sink.control(&ControlMessage {
+                steering: 0,
+                throttle,
+            })
+            .unwrap();
+        }
+
+        assert!(matches!(
+            bridge.control_rx.recv().await,
+            Err(broadcast::error::RecvError::Lagged(2))
+        ));
+        for throttle in 2..sent {
+            assert_eq!(bridge.control_rx.recv().await.unwrap().throttle, throttle);
+        }
+
+        drop(bridge);
+        let neutral = ControlMessage {
+            steering: 0,
+            throttle: 0,
+        };
+        assert!(sink.control(&neutral).is_err());
+    }
+
+    #[tokio::test]
+    async fn frames_commands_over_a_pty() {
+        let (mut powertrain, car_end) = SerialStream::pair().unwrap();
+        let (bridge, mut sink) = UartBridge::new(UartSettings {
+            enabled: true,
+            device: car_end.name().unwrap(),
+            baud_rate: 115_200,
+        });
+        let cancel_token = CancellationToken::new();
+        let task = tokio::spawn(bridge.run(cancel_token.clone()));
+        let mut decoder = FrameDecoder::new();
+
+        let frames = async {
+            // the failsafe state goes down first so the MCU starts from it
+            let first = read_frame(&mut powertrain, &mut decoder).await;
+            assert_eq!(first.packet, Packet::Failsafe(FailsafeState::Disarmed));
+
+            sink.failsafe(FailsafeState::Armed).unwrap();
+            let armed = read_frame(&mut powertrain, &mut decoder).await;
+            assert_eq!(armed.packet, Packet::Failsafe(FailsafeState::Armed));
+            assert_eq!(armed.seq, first.seq.wrapping_add(1));
+
+            let ctrl = ControlMessage {
+                steering: -40,
+                throttle: 75,
+            };
+            sink.control(&ctrl).unwrap();
+            let control = read_frame(&mut powertrain, &mut decoder).await;
+            assert_eq!(control.packet, Packet::Control(ctrl));
+            assert_eq!(control.seq, armed.seq.wrapping_add(1));
+        };
+        timeout(Duration::from_secs(5), frames)
+            .await
+            .expect("no frames on the pty");
+
+        // shutting down disarms the powertrain
+        cancel_token.cancel();
+        let last = timeout(
+            Duration::from_secs(5),
+            read_frame(&mut powertrain, &mut decoder),
+        )
+        .await
+        .expect("no disarm on shutdown");
+        assert_eq!(last.packet, Packet::Failsafe(FailsafeState::Disarmed));
+        task.await.unwrap();
+    }
+}
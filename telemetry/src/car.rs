@@ -0,0 +1,125 @@
+use std::time::SystemTime;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "specta")]
+use specta::Type;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub struct CarConfiguration {
+    pub number: u8,          // Car number
+    pub driver_name: String, // Driver's name
+    pub team_name: String,   // Team name
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub camera: CameraSettings, // Onboard camera
+}
+
+impl Default for CarConfiguration {
+    fn default() -> Self {
+        CarConfiguration {
+            number: 0,
+            driver_name: "Unknown Driver".into(),
+            team_name: "Unknown Team".into(),
+            camera: CameraSettings::default(),
+        }
+    }
+}
+
+/// Onboard camera capture settings. The radio clamps them to what the camera supports and
+/// reports back the settings it actually runs with.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub struct CameraSettings {
+    pub width: u16,
+    pub height: u16,
+    pub fps: u8,
+    /// JPEG quality, 1-100
+    pub quality: u8,
+    /// Degrees, 0 or 180
+    pub rotation: u16,
+    pub hflip: bool,
+    pub vflip: bool,
+    pub exposure: ExposureMode,
+    pub codec: VideoCodec,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        // the camera is mounted upside down
+        CameraSettings {
+            width: 1280,
+            height: 720,
+            fps: 30,
+            quality: 93,
+            rotation: 0,
+            hflip: true,
+            vflip: true,
+            exposure: ExposureMode::Normal,
+            codec: VideoCodec::Mjpeg,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub enum ExposureMode {
+    #[default]
+    Normal,
+    /// Shorter exposures to cut motion blur at speed
+    Sport,
+    Short,
+    Long,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub enum VideoCodec {
+    /// JPEG frames, for the MJPEG stream, snapshots and recordings
+    #[default]
+    Mjpeg,
+    /// Hardware H.264 at a fraction of the bandwidth, over the WebSocket video only
+    H264,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub struct F1Car {
+    pub id: String,
+    pub number: u32,
+    pub driver: String,
+    pub team: String,
+    pub ip: String,
+    pub port: u16,
+    pub version: String,
+    pub connection_status: ConnectionStatus,
+    pub last_seen: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub enum CarStatus {
+    Online,
+    Offline,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub enum ConnectionStatus {
+    #[default]
+    Disconnected,
+    Connecting,
+    Connected,
+    Failed(String),
+}
@@ -0,0 +1,487 @@
+//! Binary framing for the Pi <-> STM32 UART link.
+//!
+//! A frame on the wire is `COBS([type][seq][payload..][crc16 LE]) 0x00`. The
+//! CRC is CRC-16/CCITT-FALSE over type, seq and payload. Everything here works
+//! on caller-provided buffers so the firmware can use it without an allocator.
+
+use core::fmt;
+
+use crate::{ControlMessage, FailsafeState};
+
+/// Largest unencoded frame: type + seq + payload + crc.
+pub const MAX_RAW_LEN: usize = 2 + MAX_PAYLOAD_LEN + 2;
+/// Largest frame on the wire, including COBS overhead and the delimiter.
+pub const MAX_FRAME_LEN: usize = MAX_RAW_LEN + MAX_RAW_LEN / 254 + 2;
+pub const FRAME_DELIMITER: u8 = 0x00;
+
+const MAX_PAYLOAD_LEN: usize = 8;
+
+const TYPE_CONTROL: u8 = 0x01;
+const TYPE_FAILSAFE: u8 = 0x02;
+const TYPE_STATUS: u8 = 0x81;
+
+/// Powertrain state reported back up to the Pi.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PowertrainStatus {
+    /// 0 on boards without a battery sense divider
+    pub battery_mv: u16,
+    pub motor_output: i8,    // -100 to 100, what the driver is actually applying
+    pub steering_output: i8, // -100 to 100
+    pub faults: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// Pi -> MCU
+    Control(ControlMessage),
+    /// Pi -> MCU
+    Failsafe(FailsafeState),
+    /// MCU -> Pi
+    Status(PowertrainStatus),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub seq: u8,
+    pub packet: Packet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    BufferTooSmall,
+    Cobs,
+    Crc { expected: u16, actual: u16 },
+    UnknownType(u8),
+    Length { msg_type: u8, len: usize },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::BufferTooSmall => write!(f, "buffer too small"),
+            FrameError::Cobs => write!(f, "invalid COBS encoding"),
+            FrameError::Crc { expected, actual } => {
+                write!(
+                    f,
+                    "CRC mismatch: expected {expected:#06x}, got {actual:#06x}"
+                )
+            }
+            FrameError::UnknownType(t) => write!(f, "unknown message type {t:#04x}"),
+            FrameError::Length { msg_type, len } => {
+                write!(
+                    f,
+                    "bad payload length {len} for message type {msg_type:#04x}"
+                )
+            }
+        }
+    }
+}
+
+impl Packet {
+    fn msg_type(&self) -> u8 {
+        match self {
+            Packet::Control(_) => TYPE_CONTROL,
+            Packet::Failsafe(_) => TYPE_FAILSAFE,
+            Packet::Status(_) => TYPE_STATUS,
+        }
+    }
+
+    fn write_payload(&self, out: &mut [u8]) -> usize {
+        match self {
+            Packet::Control(ctrl) => {
+                out[0] = ctrl.steering as u8;
+                out[1] = ctrl.throttle as u8;
+                2
+            }
+            Packet::Failsafe(state) => {
+                out[0] = match state {
+                    FailsafeState::Armed => 0,
+                    FailsafeState::Coast => 1,
+                    FailsafeState::Brake => 2,
+                    FailsafeState::Disarmed => 3,
+                };
+                1
+            }
+            Packet::Status(status) => {
+                out[..2].copy_from_slice(&status.battery_mv.to_le_bytes());
+                out[2] = status.motor_output as u8;
+                out[3] = status.steering_output as u8;
+                out[4] = status.faults;
+                5
+            }
+        }
+    }
+
+    fn read_payload(msg_type: u8, payload: &[u8]) -> Result<Self, FrameError> {
+        let length_error = FrameError::Length {
+            msg_type,
+            len: payload.len(),
+        };
+
+        match msg_type {
+            TYPE_CONTROL => match payload {
+                [steering, throttle] => Ok(Packet::Control(ControlMessage {
+                    steering: *steering as i8,
+                    throttle: *throttle as i8,
+                })),
+                _ => Err(length_error),
+            },
+            TYPE_FAILSAFE => match payload {
+                [0] => Ok(Packet::Failsafe(FailsafeState::Armed)),
+                [1] => Ok(Packet::Failsafe(FailsafeState::Coast)),
+                [2] => Ok(Packet::Failsafe(FailsafeState::Brake)),
+                // unknown levels are treated as the safest state
+                [_] => Ok(Packet::Failsafe(FailsafeState::Disarmed)),
+                _ => Err(length_error),
+            },
+            TYPE_STATUS => match payload {
+                [b0, b1, motor, steering, faults] => Ok(Packet::Status(PowertrainStatus {
+                    battery_mv: u16::from_le_bytes([*b0, *b1]),
+                    motor_output: *motor as i8,
+                    steering_output: *steering as i8,
+                    faults: *faults,
+                })),
+                _ => Err(length_error),
+            },
+            other => Err(FrameError::UnknownType(other)),
+        }
+    }
+}
+
+/// Encodes `frame` into `out`, including the trailing delimiter. Returns the number of bytes
+/// written.
+pub fn encode(frame: &Frame, out: &mut [u8]) -> Result<usize, FrameError> {
+    let mut raw = [0u8; MAX_RAW_LEN];
+    raw[0] = frame.packet.msg_type();
+    raw[1] = frame.seq;
+    let payload_len = frame.packet.write_payload(&mut raw[2..2 + MAX_PAYLOAD_LEN]);
+
+    let body_len = 2 + payload_len;
+    let crc = crc16(&raw[..body_len]);
+    raw[body_len..body_len + 2].copy_from_slice(&crc.to_le_bytes());
+
+    let written = cobs_encode(&raw[..body_len + 2], out)?;
+    if written >= out.len() {
+        return Err(FrameError::BufferTooSmall);
+    }
+    out[written] = FRAME_DELIMITER;
+
+    Ok(written + 1)
+}
+
+/// Decodes a single COBS-encoded frame, without its delimiter.
+pub fn decode(encoded: &[u8]) -> Result<Frame, FrameError> {
+    let mut raw = [0u8; MAX_RAW_LEN];
+    let len = cobs_decode(encoded, &mut raw)?;
+    if len < 4 {
+        return Err(FrameError::Cobs);
+    }
+
+    let (body, crc_bytes) = raw[..len].split_at(len - 2);
+    let expected = u16::from_le_bytes([crc_bytes[0], crc_bytes[1]]);
+    let actual = crc16(body);
+    if expected != actual {
+        return Err(FrameError::Crc { expected, actual });
+    }
+
+    Ok(Frame {
+        seq: body[1],
+        packet: Packet::read_payload(body[0], &body[2..])?,
+    })
+}
+
+/// Accumulates bytes from a stream and yields frames as their delimiters arrive.
+pub struct FrameDecoder {
+    buf: [u8; MAX_FRAME_LEN],
+    len: usize,
+    overflowed: bool,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Feeds one byte. Returns `Some` once a delimiter completes a frame; empty frames (back to
+    /// back delimiters) are skipped.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame, FrameError>> {
+        if byte == FRAME_DELIMITER {
+            let len = core::mem::replace(&mut self.len, 0);
+            if core::mem::replace(&mut self.overflowed, false) {
+                return Some(Err(FrameError::BufferTooSmall));
+            }
+            if len == 0 {
+                return None;
+            }
+            return Some(decode(&self.buf[..len]));
+        }
+
+        if self.len < self.buf.len() {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        } else {
+            self.overflowed = true;
+        }
+
+        None
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn cobs_encode(input: &[u8], out: &mut [u8]) -> Result<usize, FrameError> {
+    let mut code_idx = 0;
+    let mut write_idx = 1;
+    let mut code = 1u8;
+
+    for &byte in input {
+        if write_idx >= out.len() {
+            return Err(FrameError::BufferTooSmall);
+        }
+
+        if byte == 0 {
+            out[code_idx] = code;
+            code_idx = write_idx;
+            write_idx += 1;
+            code = 1;
+            continue;
+        }
+
+        out[write_idx] = byte;
+        write_idx += 1;
+        code += 1;
+
+        if code == 0xFF {
+            if write_idx >= out.len() {
+                return Err(FrameError::BufferTooSmall);
+            }
+            out[code_idx] = code;
+            code_idx = write_idx;
+            write_idx += 1;
+            code = 1;
+        }
+    }
+
+    if code_idx >= out.len() {
+        return Err(FrameError::BufferTooSmall);
+    }
+    out[code_idx] = code;
+
+    Ok(write_idx)
+}
+
+fn cobs_decode(input: &[u8], out: &mut [u8]) -> Result<usize, FrameError> {
+    let mut read_idx = 0;
+    let mut write_idx = 0;
+
+    while read_idx < input.len() {
+        let code = input[read_idx];
+        if code == 0 {
+            return Err(FrameError::Cobs);
+        }
+        read_idx += 1;
+
+        let run = code as usize - 1;
+        if read_idx + run > input.len() {
+            return Err(FrameError::Cobs);
+        }
+        if write_idx + run > out.len() {
+            return Err(FrameError::BufferTooSmall);
+        }
+
+        for &byte in &input[read_idx..read_idx + run] {
+            if byte == 0 {
+                return Err(FrameError::Cobs);
+            }
+            out[write_idx] = byte;
+            write_idx += 1;
+        }
+        read_idx += run;
+
+        if code != 0xFF && read_idx < input.len() {
+            if write_idx >= out.len() {
+                return Err(FrameError::BufferTooSmall);
+            }
+            out[write_idx] = 0;
+            write_idx += 1;
+        }
+    }
+
+    Ok(write_idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_vec(frame: &Frame) -> ([u8; MAX_FRAME_LEN], usize) {
+        let mut out = [0u8; MAX_FRAME_LEN];
+        let len = encode(frame, &mut out).unwrap();
+        (out, len)
+    }
+
+    fn control(seq: u8, steering: i8, throttle: i8) -> Frame {
+        Frame {
+            seq,
+            packet: Packet::Control(ControlMessage { steering, throttle }),
+        }
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn control_round_trip() {
+        for (steering, throttle) in [(0, 0), (-100, 100), (100, -100), (i8::MIN, i8::MAX)] {
+            let frame = control(7, steering, throttle);
+            let (out, len) = encode_vec(&frame);
+
+            assert_eq!(out[len - 1], FRAME_DELIMITER);
+            assert!(!out[..len - 1].contains(&FRAME_DELIMITER));
+            assert_eq!(decode(&out[..len - 1]), Ok(frame));
+        }
+    }
+
+    #[test]
+    fn status_round_trip() {
+        let frame = Frame {
+            seq: 255,
+            packet: Packet::Status(PowertrainStatus {
+                battery_mv: 7400,
+                motor_output: -42,
+                steering_output: 0,
+                faults: 0b1010_0000,
+            }),
+        };
+        let (out, len) = encode_vec(&frame);
+
+        assert_eq!(decode(&out[..len - 1]), Ok(frame));
+    }
+
+    #[test]
+    fn failsafe_round_trip() {
+        for state in [
+            FailsafeState::Armed,
+            FailsafeState::Coast,
+            FailsafeState::Brake,
+            FailsafeState::Disarmed,
+        ] {
+            let frame = Frame {
+                seq: 1,
+                packet: Packet::Failsafe(state),
+            };
+            let (out, len) = encode_vec(&frame);
+
+            assert_eq!(decode(&out[..len - 1]), Ok(frame));
+        }
+    }
+
+    #[test]
+    fn stream_decoder_handles_back_to_back_frames() {
+        let frames = [control(0, 0, 0), control(1, 10, -10), control(2, -1, 1)];
+        let mut decoder = FrameDecoder::new();
+        let mut decoded = 0;
+
+        // leading delimiter mimics joining a stream mid-frame
+        assert!(decoder.push(FRAME_DELIMITER).is_none());
+
+        for frame in &frames {
+            let (out, len) = encode_vec(frame);
+            for &byte in &out[..len] {
+                if let Some(result) = decoder.push(byte) {
+                    assert_eq!(result.as_ref(), Ok(&frames[decoded]));
+                    decoded += 1;
+                }
+            }
+        }
+
+        assert_eq!(decoded, frames.len());
+    }
+
+    #[test]
+    fn single_bit_flips_are_rejected() {
+        let frame = control(42, 55, -73);
+        let (out, len) = encode_vec(&frame);
+
+        for i in 0..len - 1 {
+            for bit in 0..8 {
+                let mut corrupted = out;
+                corrupted[i] ^= 1 << bit;
+
+                let body = &corrupted[..len - 1];
+                if body.contains(&FRAME_DELIMITER) {
+                    // the stream decoder would split this into two frames, neither valid
+                    continue;
+                }
+                assert!(decode(body).is_err(), "flip at byte {i} bit {bit} accepted");
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_and_garbage_frames_are_rejected() {
+        let (out, len) = encode_vec(&control(3, 1, 2));
+
+        for cut in 0..len - 1 {
+            assert!(decode(&out[..cut]).is_err());
+        }
+        assert_eq!(decode(&[0x02, 0x01]), Err(FrameError::Cobs));
+        assert!(decode(&[0xFF; 40]).is_err());
+    }
+
+    #[test]
+    fn unknown_type_is_reported() {
+        let raw = [0x7Eu8, 0x00];
+        let crc = crc16(&raw).to_le_bytes();
+        let mut encoded = [0u8; 8];
+        let len = cobs_encode(&[raw[0], raw[1], crc[0], crc[1]], &mut encoded).unwrap();
+
+        assert_eq!(decode(&encoded[..len]), Err(FrameError::UnknownType(0x7E)));
+    }
+
+    #[test]
+    fn decoder_recovers_after_overflow() {
+        let mut decoder = FrameDecoder::new();
+        for _ in 0..MAX_FRAME_LEN + 10 {
+            assert!(decoder.push(0x11).is_none());
+        }
+        assert_eq!(
+            decoder.push(FRAME_DELIMITER),
+            Some(Err(FrameError::BufferTooSmall))
+        );
+
+        let frame = control(9, -5, 5);
+        let (out, len) = encode_vec(&frame);
+        let mut result = None;
+        for &byte in &out[..len] {
+            result = decoder.push(byte).or(result);
+        }
+        assert_eq!(result, Some(Ok(frame)));
+    }
+}
@@ -1,58 +1,46 @@
-use std::time::SystemTime;
+//! Types and protocols shared by the cockpit, the radio and the powertrain firmware.
+//!
+//! The crate is `no_std` without the `std` feature so the firmware can use the
+//! control types and the UART framing. `serde` and `specta` derives are opt-in.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "specta")]
 use specta::Type;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg(feature = "std")]
+mod car;
+pub mod frame;
+mod stream;
+
+#[cfg(feature = "std")]
+pub use car::{
+    CameraSettings, CarConfiguration, CarStatus, ConnectionStatus, ExposureMode, F1Car, VideoCodec,
+};
+pub use stream::{CarTelemetry, LinkQuality, SeqSample, Vector3, VideoProfile, VideoQuality};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ControlMessage {
     pub steering: i8, // -100 to 100 (left to right)
     pub throttle: i8, // -100 to 100 (reverse to forward)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct CarConfiguration {
-    pub number: u8,          // Car number
-    pub driver_name: String, // Driver's name
-    pub team_name: String,   // Team name
-}
-
-impl Default for CarConfiguration {
-    fn default() -> Self {
-        CarConfiguration {
-            number: 0,
-            driver_name: "Unknown Driver".into(),
-            team_name: "Unknown Team".into(),
-        }
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Type)]
-#[serde(rename_all = "camelCase")]
-pub struct F1Car {
-    pub id: String,
-    pub number: u32,
-    pub driver: String,
-    pub team: String,
-    pub ip: String,
-    pub port: u16,
-    pub version: String,
-    pub connection_status: ConnectionStatus,
-    pub last_seen: Option<SystemTime>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Type)]
-pub enum CarStatus {
-    Online,
-    Offline,
+/// Car-side failsafe, escalated by the radio as control packets go missing.
+///
+/// Variants are ordered by severity. `Disarmed` is the power-on state and is
+/// only left once the driver sends a neutral throttle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub enum FailsafeState {
+    Armed,
+    Coast,
+    Brake,
+    #[default]
+    Disarmed,
 }
 
 pub const SERVICE_TYPE: &str = "_f1-car._udp.local.";
-
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Type, Default)]
-pub enum ConnectionStatus {
-    #[default]
-    Disconnected,
-    Connecting,
-    Connected,
-    Failed(String),
-}
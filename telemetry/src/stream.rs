@@ -0,0 +1,129 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "specta")]
+use specta::Type;
+
+use crate::FailsafeState;
+
+/// Periodic car state pushed from the radio to the controlling client.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub struct CarTelemetry {
+    /// Unix time on the car when the snapshot was taken
+    pub timestamp_ms: u64,
+    /// m/s^2 in the car frame, x pointing forward
+    pub acceleration: Vector3,
+    /// m/s, integrated from forward acceleration so it drifts back to zero at rest
+    pub speed_estimate: f32,
+    pub link: LinkQuality,
+    /// `None` until the powertrain has reported in, or if it can't sense the battery
+    pub battery_mv: Option<u16>,
+    pub failsafe: FailsafeState,
+    /// `None` while the camera is off or adaptive quality is disabled
+    pub video: Option<VideoQuality>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub struct Vector3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub struct LinkQuality {
+    /// Time since the driver's last accepted control packet
+    pub control_age_ms: Option<u32>,
+    /// Control packets accepted per second over the last telemetry period
+    pub control_rate_hz: f32,
+    /// Stale or duplicate joystick packets discarded since the driver connected
+    pub control_dropped: u32,
+    /// Fraction of the driver's joystick packets that never arrived over the last telemetry
+    /// period, from how far the sequence advanced against how many packets came in
+    pub control_loss: f32,
+    /// Whether status frames are arriving from the powertrain
+    pub uart_connected: bool,
+}
+
+/// How far the driver's joystick packets had got at some point: the highest sequence number
+/// and a running count of the packets that arrived. The radio and the cockpit both work out
+/// control loss from two of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeqSample {
+    pub last_seq: u32,
+    pub received: u32,
+}
+
+impl SeqSample {
+    /// Fraction of the packets sent since `earlier` that never arrived, from how far the
+    /// sequence advanced against how many packets came in.
+    pub fn loss_since(&self, earlier: &SeqSample) -> f32 {
+        let sent = self.last_seq.wrapping_sub(earlier.last_seq);
+        let received = self.received.wrapping_sub(earlier.received);
+        // seq going backwards means the joystick restarted, nothing to compare
+        if sent == 0 || sent >= u32::MAX / 2 {
+            return 0.0;
+        }
+        1.0 - received.min(sent) as f32 / sent as f32
+    }
+}
+
+/// Caps the radio puts on the camera, a step of its quality ladder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub struct VideoProfile {
+    pub width: u16,
+    pub height: u16,
+    pub fps: u8,
+    /// JPEG quality, 1-100
+    pub quality: u8,
+}
+
+/// The video quality the radio settled on for the current link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "specta", derive(Type))]
+pub struct VideoQuality {
+    /// Step of the ladder, 0 being the configured camera settings
+    pub level: u8,
+    /// What the camera runs at
+    pub profile: VideoProfile,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(last_seq: u32, received: u32) -> SeqSample {
+        SeqSample { last_seq, received }
+    }
+
+    #[test]
+    fn loss_is_the_share_of_seqs_that_never_arrived() {
+        assert_eq!(sample(110, 60).loss_since(&sample(10, 10)), 0.5);
+        assert_eq!(sample(110, 110).loss_since(&sample(10, 10)), 0.0);
+        // more packets than the seq advanced by, as duplicates would give, clamps loss at zero
+        assert_eq!(sample(20, 40).loss_since(&sample(10, 10)), 0.0);
+    }
+
+    #[test]
+    fn loss_survives_wrapping() {
+        let earlier = sample(u32::MAX - 4, u32::MAX - 1);
+        assert_eq!(sample(5, 8).loss_since(&earlier), 0.0);
+        assert_eq!(sample(5, 3).loss_since(&earlier), 0.5);
+    }
+
+    #[test]
+    fn no_loss_without_progress_or_after_a_restart() {
+        assert_eq!(sample(10, 10).loss_since(&sample(10, 10)), 0.0);
+        assert_eq!(sample(3, 20).loss_since(&sample(500, 15)), 0.0);
+    }
+}